@@ -0,0 +1,62 @@
+//! Compares evaluating a large balanced expression on one thread and on
+//! rayon's global pool, where independent subtrees run concurrently.
+//!
+//! Run with `cargo bench --features rayon`.
+
+use std::time::{Duration, Instant};
+use vector_expr::{BindingId, Expression, RealExpression, Registers};
+
+const REGISTER_LENGTH: usize = 4096;
+const ITERATIONS: usize = 200;
+
+fn binding_map(var_name: &str) -> BindingId {
+    match var_name {
+        "x" => 0,
+        "y" => 1,
+        _ => unreachable!(),
+    }
+}
+
+/// A balanced tree with `2 ^ depth` leaves.
+fn balanced(depth: u32) -> String {
+    match depth {
+        0 => "sqrt(x * y + 1)".to_owned(),
+        _ => format!("({} + {})", balanced(depth - 1), balanced(depth - 1)),
+    }
+}
+
+fn median_time(real: &RealExpression<f64>, bindings: &[&[f64]]) -> Duration {
+    let mut registers = Registers::new(REGISTER_LENGTH);
+    let mut times: Vec<Duration> = (0..ITERATIONS)
+        .map(|_| {
+            let start = Instant::now();
+            real.evaluate(bindings, &mut registers);
+            start.elapsed()
+        })
+        .collect();
+    times.sort_unstable();
+    times[ITERATIONS / 2]
+}
+
+fn main() {
+    let x: Vec<f64> = (0..REGISTER_LENGTH).map(|i| i as f64).collect();
+    let y: Vec<f64> = (0..REGISTER_LENGTH).map(|i| (i % 7) as f64).collect();
+    let bindings = [&x[..], &y[..]];
+    let single_thread = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    for depth in [4, 7, 10] {
+        let real = Expression::parse(&balanced(depth), binding_map)
+            .unwrap()
+            .unwrap_real();
+        let serial = single_thread.install(|| median_time(&real, &bindings));
+        let parallel = median_time(&real, &bindings);
+        println!(
+            "depth {depth:>2}: 1 thread {serial:>10.2?}, {} threads {parallel:>10.2?}, speedup {:.2}",
+            rayon::current_num_threads(),
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+}
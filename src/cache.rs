@@ -0,0 +1,162 @@
+use crate::{BindingId, Expression, FloatExt, ParseError};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Memoizes [`Expression::parse`] for inputs that are parsed repeatedly.
+///
+/// Entries are keyed by the input and a caller-chosen version of the binding
+/// map, which must change whenever the binding map would map a name
+/// differently. At most `capacity` expressions are kept, evicting the least
+/// recently used. Parse errors are not cached.
+///
+/// The cache can be shared between threads. Parsing happens outside the lock,
+/// so threads that miss on the same input concurrently may each parse it.
+pub struct ExpressionCache<Real> {
+    capacity: usize,
+    state: Mutex<CacheState<Real>>,
+}
+
+struct CacheState<Real> {
+    entries: HashMap<(String, u64), CacheEntry<Real>>,
+    /// Incremented on every lookup, to order entries by their last use.
+    clock: u64,
+    hits: usize,
+    misses: usize,
+}
+
+struct CacheEntry<Real> {
+    expression: Arc<Expression<Real>>,
+    last_used: u64,
+}
+
+impl<Real: FloatExt> ExpressionCache<Real> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// Like [`Expression::parse`], but returns the cached expression if
+    /// `input` was already parsed with the same `binding_map_version`.
+    pub fn parse(
+        &self,
+        input: &str,
+        binding_map_version: u64,
+        binding_map: impl Fn(&str) -> BindingId,
+    ) -> Result<Arc<Expression<Real>>, ParseError> {
+        let key = (input.to_owned(), binding_map_version);
+        {
+            let mut state = self.lock();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&key) {
+                entry.last_used = clock;
+                let expression = entry.expression.clone();
+                state.hits += 1;
+                return Ok(expression);
+            }
+            state.misses += 1;
+        }
+
+        let expression = Arc::new(Expression::parse(input, binding_map)?);
+        if self.capacity == 0 {
+            return Ok(expression);
+        }
+        let mut state = self.lock();
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            let least_recent = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                state.entries.remove(&least_recent);
+            }
+        }
+        let last_used = state.clock;
+        state.entries.insert(
+            key,
+            CacheEntry {
+                expression: expression.clone(),
+                last_used,
+            },
+        );
+        Ok(expression)
+    }
+
+    /// The number of cached expressions.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of calls to [`Self::parse`] that found a cached expression.
+    pub fn hits(&self) -> usize {
+        self.lock().hits
+    }
+
+    /// The number of calls to [`Self::parse`] that had to parse.
+    pub fn misses(&self) -> usize {
+        self.lock().misses
+    }
+
+    /// Removes every cached expression.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState<Real>> {
+        // The state is consistent between statements, so a panic while it was
+        // locked cannot have corrupted it.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn binding_map(var_name: &str) -> BindingId {
+        match var_name {
+            "x" => 0,
+            "y" => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn second_parse_is_a_hit() {
+        let cache = ExpressionCache::<f64>::new(2);
+        let first = cache.parse("x + y", 0, binding_map).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (0, 1));
+        let second = cache.parse("x + y", 0, binding_map).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert!(std::sync::Arc::ptr_eq(&first, &second));
+
+        // Another binding map version is parsed again.
+        let swapped = cache
+            .parse("x + y", 1, |name| 1 - binding_map(name))
+            .unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+        assert_ne!(swapped, first);
+
+        // "x + y" with version 0 was used least recently.
+        cache.parse("x * y", 0, binding_map).unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.parse("x + y", 1, binding_map).unwrap();
+        cache.parse("x + y", 0, binding_map).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 4));
+
+        assert!(cache.parse("x +", 0, binding_map).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{BoolExpression, RealExpression};
+use std::fmt;
+
+/// Formats an expression like its derived [`Debug`](fmt::Debug)
+/// implementation, but replaces nodes nested deeper than `max_depth` with
+/// `...`.
+///
+/// Formatting recurses once per level it prints, so the recursion depth is
+/// bounded by `max_depth`, not by the depth of the expression. To format
+/// expressions too deep for the stack, e.g. untrusted ones, choose a small
+/// `max_depth`: a large one like `usize::MAX` recurses through the whole tree.
+#[derive(Clone, Copy)]
+pub struct DebugTruncated<'a, E> {
+    expr: &'a E,
+    max_depth: usize,
+}
+
+impl<Real> RealExpression<Real> {
+    /// See [`DebugTruncated`].
+    pub fn debug_truncated(&self, max_depth: usize) -> DebugTruncated<'_, Self> {
+        DebugTruncated {
+            expr: self,
+            max_depth,
+        }
+    }
+}
+
+impl<Real> BoolExpression<Real> {
+    /// See [`DebugTruncated`].
+    pub fn debug_truncated(&self, max_depth: usize) -> DebugTruncated<'_, Self> {
+        DebugTruncated {
+            expr: self,
+            max_depth,
+        }
+    }
+}
+
+impl<'a, E> DebugTruncated<'a, E> {
+    fn child<C>(&self, expr: &'a C) -> DebugTruncated<'a, C> {
+        DebugTruncated {
+            expr,
+            max_depth: self.max_depth - 1,
+        }
+    }
+}
+
+impl<Real: fmt::Debug> fmt::Debug for DebugTruncated<'_, RealExpression<Real>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.max_depth == 0 {
+            return f.write_str("...");
+        }
+        let name = self.expr.node_name();
+        match self.expr {
+            RealExpression::Add(lhs, rhs)
+            | RealExpression::Div(lhs, rhs)
+            | RealExpression::Mul(lhs, rhs)
+            | RealExpression::Pow(lhs, rhs)
+            | RealExpression::Sub(lhs, rhs) => f
+                .debug_tuple(name)
+                .field(&self.child(&**lhs))
+                .field(&self.child(&**rhs))
+                .finish(),
+            RealExpression::Neg(only) => f.debug_tuple(name).field(&self.child(&**only)).finish(),
+            RealExpression::Func1(func, only) => f
+                .debug_tuple(name)
+                .field(func)
+                .field(&self.child(&**only))
+                .finish(),
+            RealExpression::PowI(only, n) => f
+                .debug_tuple(name)
+                .field(&self.child(&**only))
+                .field(n)
+                .finish(),
+            RealExpression::Func2(func, lhs, rhs) => f
+                .debug_tuple(name)
+                .field(func)
+                .field(&self.child(&**lhs))
+                .field(&self.child(&**rhs))
+                .finish(),
+            RealExpression::Case(arms, otherwise) => {
+                let mut tuple = f.debug_tuple(name);
+                for (condition, value) in arms {
+                    tuple.field(&(self.child(condition), self.child(value)));
+                }
+                tuple.field(&self.child(&**otherwise)).finish()
+            }
+            RealExpression::Literal(value) => f.debug_tuple(name).field(value).finish(),
+            RealExpression::IntLiteral(value) => f.debug_tuple(name).field(value).finish(),
+            RealExpression::Binding(binding) => f.debug_tuple(name).field(binding).finish(),
+        }
+    }
+}
+
+impl<Real: fmt::Debug> fmt::Debug for DebugTruncated<'_, BoolExpression<Real>> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.max_depth == 0 {
+            return f.write_str("...");
+        }
+        let name = self.expr.node_name();
+        match self.expr {
+            BoolExpression::And(lhs, rhs)
+            | BoolExpression::Or(lhs, rhs)
+            | BoolExpression::Xor(lhs, rhs)
+            | BoolExpression::Implies(lhs, rhs) => f
+                .debug_tuple(name)
+                .field(&self.child(&**lhs))
+                .field(&self.child(&**rhs))
+                .finish(),
+            BoolExpression::Not(only) => f.debug_tuple(name).field(&self.child(&**only)).finish(),
+            BoolExpression::Equal(lhs, rhs)
+            | BoolExpression::Greater(lhs, rhs)
+            | BoolExpression::GreaterEqual(lhs, rhs)
+            | BoolExpression::Less(lhs, rhs)
+            | BoolExpression::LessEqual(lhs, rhs)
+            | BoolExpression::NotEqual(lhs, rhs) => f
+                .debug_tuple(name)
+                .field(&self.child(&**lhs))
+                .field(&self.child(&**rhs))
+                .finish(),
+            BoolExpression::Between(value, lo, hi) => f
+                .debug_tuple(name)
+                .field(&self.child(&**value))
+                .field(&self.child(&**lo))
+                .field(&self.child(&**hi))
+                .finish(),
+            BoolExpression::StrEqual(lhs, rhs) | BoolExpression::StrNotEqual(lhs, rhs) => {
+                f.debug_tuple(name).field(lhs).field(rhs).finish()
+            }
+            BoolExpression::CoercedEqual(lhs, rhs) | BoolExpression::CoercedNotEqual(lhs, rhs) => f
+                .debug_tuple(name)
+                .field(lhs)
+                .field(&self.child(&**rhs))
+                .finish(),
+            BoolExpression::Literal(value) => f.debug_tuple(name).field(value).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn debug_truncated_matches_derived_debug() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("x > -sqrt(x) + 1 && !(x < 2)", binding_map).unwrap();
+        let bool = parsed.unwrap_bool();
+        assert_eq!(
+            format!("{:?}", bool.debug_truncated(usize::MAX)),
+            format!("{bool:?}")
+        );
+        assert_eq!(
+            format!("{:?}", bool.debug_truncated(2)),
+            "And(Greater(..., ...), Not(...))"
+        );
+    }
+
+    #[test]
+    fn debug_truncated_deep_expression() {
+        let mut deep = RealExpression::Binding(0);
+        for i in 0..100_000 {
+            deep = RealExpression::Add(Box::new(deep), Box::new(RealExpression::Literal(i as f64)));
+        }
+        assert_eq!(
+            format!("{:?}", deep.debug_truncated(3)),
+            "Add(Add(Add(..., ...), Literal(99998.0)), Literal(99999.0))"
+        );
+        // Dropping is recursive too, so take the chain apart one node at a
+        // time.
+        while let RealExpression::Add(lhs, _) = deep {
+            deep = *lhs;
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{BoolExpression, Expression, RealExpression, StringExpression, TupleExpression};
+use std::fmt;
+
+// Writes expressions in the syntax accepted by `Expression::parse`, with as
+// few parentheses as the grammar allows. Each node has a precedence, and is
+// parenthesized where its parent requires a higher one. A leading `-`, `!` or
+// `when` extends as far to the right as possible, so those nodes have the
+// lowest precedence and are parenthesized as any operand.
+
+/// The precedence of nodes that never need parentheses, e.g. calls.
+const ATOM: u8 = 7;
+
+impl<Real: fmt::Debug> fmt::Display for Expression<Real> {
+    /// Writes the expression in the syntax accepted by [`Expression::parse`].
+    ///
+    /// A binding is written as `_` followed by its ID, e.g. `_0`. Literals
+    /// keep whether they were written as integers, so a parsed `2` is written
+    /// as `2` and a parsed `2.0` as `2.0`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Boolean(b) => b.fmt(f),
+            Self::Real(r) => r.fmt(f),
+            Self::String(s) => s.fmt(f),
+            Self::Tuple(t) => t.fmt(f),
+        }
+    }
+}
+
+impl<Real: fmt::Debug> fmt::Display for TupleExpression<Real> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(")?;
+        for (i, element) in self.elements.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write_real(f, element, 0)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl<Real: fmt::Debug> fmt::Display for RealExpression<Real> {
+    /// See [`Expression`'s implementation](Expression#impl-Display-for-Expression<Real>).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_real(f, self, 0)
+    }
+}
+
+impl<Real: fmt::Debug> fmt::Display for BoolExpression<Real> {
+    /// See [`Expression`'s implementation](Expression#impl-Display-for-Expression<Real>).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_bool(f, self, 0)
+    }
+}
+
+impl fmt::Display for StringExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Literal(value) if !value.contains(['"', '\\']) => write!(f, "\"{value}\""),
+            // Delimit with more `#` than follow any quote in the value.
+            Self::Literal(value) => {
+                let hashes = (1..)
+                    .map(|n| "#".repeat(n))
+                    .find(|hashes| !value.contains(&format!("\"{hashes}")))
+                    .unwrap_or_default();
+                write!(f, "r{hashes}\"{value}\"{hashes}")
+            }
+            Self::Binding(binding) => write!(f, "_{binding}"),
+        }
+    }
+}
+
+fn real_precedence<Real: fmt::Debug>(expr: &RealExpression<Real>) -> u8 {
+    match expr {
+        RealExpression::Add(..) | RealExpression::Sub(..) => 4,
+        RealExpression::Mul(..) | RealExpression::Div(..) => 5,
+        RealExpression::Pow(..) => 6,
+        RealExpression::Neg(_) | RealExpression::Case(..) => 0,
+        RealExpression::IntLiteral(value) if *value < 0 => 0,
+        RealExpression::Literal(value) if format!("{value:?}").starts_with('-') => 0,
+        _ => ATOM,
+    }
+}
+
+fn write_real<Real: fmt::Debug>(
+    f: &mut fmt::Formatter<'_>,
+    expr: &RealExpression<Real>,
+    min_precedence: u8,
+) -> fmt::Result {
+    let precedence = real_precedence(expr);
+    if precedence < min_precedence {
+        f.write_str("(")?;
+        write_real(f, expr, 0)?;
+        return f.write_str(")");
+    }
+    // `^` associates to the right and the other operators to the left.
+    let mut binary = |op, lhs, rhs, right_associative| {
+        let (lhs_min, rhs_min) = match right_associative {
+            true => (precedence + 1, precedence),
+            false => (precedence, precedence + 1),
+        };
+        write_real(f, lhs, lhs_min)?;
+        write!(f, " {op} ")?;
+        write_real(f, rhs, rhs_min)
+    };
+    match expr {
+        RealExpression::Add(lhs, rhs) => binary("+", lhs, rhs, false),
+        RealExpression::Sub(lhs, rhs) => binary("-", lhs, rhs, false),
+        RealExpression::Mul(lhs, rhs) => binary("*", lhs, rhs, false),
+        RealExpression::Div(lhs, rhs) => binary("/", lhs, rhs, false),
+        RealExpression::Pow(lhs, rhs) => binary("^", lhs, rhs, true),
+        RealExpression::Neg(only) => {
+            f.write_str("-")?;
+            write_real(f, only, ATOM)
+        }
+        RealExpression::Func1(func, only) => {
+            write!(f, "{}(", func.name())?;
+            write_real(f, only, 0)?;
+            f.write_str(")")
+        }
+        RealExpression::PowI(only, n) => {
+            f.write_str("powi(")?;
+            write_real(f, only, 0)?;
+            write!(f, ", {n})")
+        }
+        RealExpression::Func2(func, lhs, rhs) => {
+            write!(f, "{}(", func.name())?;
+            write_real(f, lhs, 0)?;
+            f.write_str(", ")?;
+            write_real(f, rhs, 0)?;
+            f.write_str(")")
+        }
+        RealExpression::Case(arms, otherwise) => {
+            for (condition, value) in arms {
+                f.write_str("when ")?;
+                write_bool(f, condition, 0)?;
+                f.write_str(" then ")?;
+                write_real(f, value, 0)?;
+                f.write_str(" ")?;
+            }
+            f.write_str("else ")?;
+            write_real(f, otherwise, 0)
+        }
+        RealExpression::Literal(value) => write!(f, "{value:?}"),
+        RealExpression::IntLiteral(value) => write!(f, "{value}"),
+        RealExpression::Binding(binding) => write!(f, "_{binding}"),
+    }
+}
+
+fn bool_precedence<Real>(expr: &BoolExpression<Real>) -> u8 {
+    match expr {
+        BoolExpression::Implies(..) => 1,
+        BoolExpression::And(..) | BoolExpression::Or(..) | BoolExpression::Xor(..) => 2,
+        BoolExpression::Not(_) => 0,
+        BoolExpression::Between(..) | BoolExpression::Literal(_) => ATOM,
+        _ => 3,
+    }
+}
+
+fn write_bool<Real: fmt::Debug>(
+    f: &mut fmt::Formatter<'_>,
+    expr: &BoolExpression<Real>,
+    min_precedence: u8,
+) -> fmt::Result {
+    let precedence = bool_precedence(expr);
+    if precedence < min_precedence {
+        f.write_str("(")?;
+        write_bool(f, expr, 0)?;
+        return f.write_str(")");
+    }
+    // `->` associates to the right and the other operators to the left.
+    let mut logic = |op, lhs, rhs, right_associative| {
+        let (lhs_min, rhs_min) = match right_associative {
+            true => (precedence + 1, precedence),
+            false => (precedence, precedence + 1),
+        };
+        write_bool(f, lhs, lhs_min)?;
+        write!(f, " {op} ")?;
+        write_bool(f, rhs, rhs_min)
+    };
+    match expr {
+        BoolExpression::And(lhs, rhs) => logic("&&", lhs, rhs, false),
+        BoolExpression::Or(lhs, rhs) => logic("||", lhs, rhs, false),
+        BoolExpression::Xor(lhs, rhs) => logic("^^", lhs, rhs, false),
+        BoolExpression::Implies(lhs, rhs) => logic("->", lhs, rhs, true),
+        BoolExpression::Not(only) => {
+            f.write_str("!")?;
+            write_bool(f, only, ATOM)
+        }
+        BoolExpression::Equal(lhs, rhs) => write_comparison(f, "==", lhs, rhs),
+        BoolExpression::Greater(lhs, rhs) => write_comparison(f, ">", lhs, rhs),
+        BoolExpression::GreaterEqual(lhs, rhs) => write_comparison(f, ">=", lhs, rhs),
+        BoolExpression::Less(lhs, rhs) => write_comparison(f, "<", lhs, rhs),
+        BoolExpression::LessEqual(lhs, rhs) => write_comparison(f, "<=", lhs, rhs),
+        BoolExpression::NotEqual(lhs, rhs) => write_comparison(f, "!=", lhs, rhs),
+        BoolExpression::Between(value, lo, hi) => {
+            f.write_str("between(")?;
+            write_real(f, value, 0)?;
+            f.write_str(", ")?;
+            write_real(f, lo, 0)?;
+            f.write_str(", ")?;
+            write_real(f, hi, 0)?;
+            f.write_str(")")
+        }
+        BoolExpression::StrEqual(lhs, rhs) => write!(f, "{lhs} == {rhs}"),
+        BoolExpression::StrNotEqual(lhs, rhs) => write!(f, "{lhs} != {rhs}"),
+        BoolExpression::CoercedEqual(lhs, rhs) => {
+            write!(f, "{lhs} == ")?;
+            write_real(f, rhs, 1)
+        }
+        BoolExpression::CoercedNotEqual(lhs, rhs) => {
+            write!(f, "{lhs} != ")?;
+            write_real(f, rhs, 1)
+        }
+        BoolExpression::Literal(value) => write!(f, "{value}"),
+    }
+}
+
+/// Comparisons do not chain, and their real operands bind tighter.
+fn write_comparison<Real: fmt::Debug>(
+    f: &mut fmt::Formatter<'_>,
+    op: &str,
+    lhs: &RealExpression<Real>,
+    rhs: &RealExpression<Real>,
+) -> fmt::Result {
+    write_real(f, lhs, 1)?;
+    write!(f, " {op} ")?;
+    write_real(f, rhs, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn binding_map(var_name: &str) -> BindingId {
+        var_name[1..].parse().unwrap()
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let display = |input| {
+            Expression::<f64>::parse(input, binding_map)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(display("2"), "2");
+        assert_eq!(display("2.0"), "2.0");
+        assert_eq!(display("_0^2"), "_0 ^ 2");
+
+        for input in [
+            "_0 + 2 * _1",
+            "(_0 + 2) * _1",
+            "_0 - (_1 - 2)",
+            "_0 - _1 - 2",
+            "_0 ^ _1 ^ 2",
+            "(_0 ^ _1) ^ 2",
+            "(-_0) * 2.5",
+            "-(_0 * 2.5)",
+            "max(_0, sqrt(_1)) / powi(_0, 3)",
+            "(when _0 < 1 then -1 else _1) + 1",
+            "(_0, _1 * 2)",
+            "(!(_0 < 1)) && between(_0, 0, _1) -> _1 > 0 -> _0 != 2",
+            "(_0 < 1 -> _1 > 0) || _0 == 2",
+        ] {
+            assert_eq!(display(input), input);
+        }
+
+        let parsed = Expression::<f64>::parse_typed("_0 == r#\"say \"hi\"\"#", binding_map, |_| {
+            ValueType::String
+        })
+        .unwrap();
+        assert_eq!(parsed.to_string(), "_0 == r#\"say \"hi\"\"#");
+    }
+}
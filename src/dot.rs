@@ -0,0 +1,191 @@
+use crate::{BindingId, BoolExpression, RealExpression, StringExpression};
+use std::fmt::{Display, Write};
+
+impl<Real: Display> RealExpression<Real> {
+    /// Renders the syntax tree in Graphviz DOT format, with one node per
+    /// syntax tree node. Bindings are labeled with `names`.
+    pub fn to_dot(&self, names: impl Fn(BindingId) -> String) -> String {
+        DotWriter::write(|w| w.real(self, &names))
+    }
+}
+
+impl<Real: Display> BoolExpression<Real> {
+    /// Renders the syntax tree in Graphviz DOT format, with one node per
+    /// syntax tree node. Real and string bindings are labeled with `names`.
+    pub fn to_dot(&self, names: impl Fn(BindingId) -> String) -> String {
+        DotWriter::write(|w| w.bool(self, &names))
+    }
+}
+
+impl StringExpression {
+    /// Renders the syntax tree in Graphviz DOT format.
+    pub fn to_dot(&self, names: impl Fn(BindingId) -> String) -> String {
+        DotWriter::write(|w| w.string(self, &names))
+    }
+}
+
+struct DotWriter {
+    out: String,
+    num_nodes: usize,
+}
+
+impl DotWriter {
+    fn write(root: impl FnOnce(&mut Self) -> usize) -> String {
+        let mut writer = Self {
+            out: "digraph {\n".into(),
+            num_nodes: 0,
+        };
+        root(&mut writer);
+        writer.out.push_str("}\n");
+        writer.out
+    }
+
+    /// Writes a node and the edges to its `children`, returning its ID.
+    fn node(&mut self, label: &str, children: &[usize]) -> usize {
+        let id = self.num_nodes;
+        self.num_nodes += 1;
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(self.out, "    n{id} [label=\"{label}\"];").unwrap();
+        for child in children {
+            writeln!(self.out, "    n{id} -> n{child};").unwrap();
+        }
+        id
+    }
+
+    fn real<Real: Display>(
+        &mut self,
+        expr: &RealExpression<Real>,
+        names: &impl Fn(BindingId) -> String,
+    ) -> usize {
+        match expr {
+            RealExpression::Add(lhs, rhs)
+            | RealExpression::Div(lhs, rhs)
+            | RealExpression::Mul(lhs, rhs)
+            | RealExpression::Pow(lhs, rhs)
+            | RealExpression::Sub(lhs, rhs) => {
+                let children = [self.real(lhs, names), self.real(rhs, names)];
+                self.node(expr.node_name(), &children)
+            }
+            RealExpression::Neg(only) => {
+                let child = self.real(only, names);
+                self.node(expr.node_name(), &[child])
+            }
+            RealExpression::Func1(func, only) => {
+                let child = self.real(only, names);
+                self.node(func.name(), &[child])
+            }
+            RealExpression::PowI(only, n) => {
+                let child = self.real(only, names);
+                self.node(&format!("powi {n}"), &[child])
+            }
+            RealExpression::Func2(func, lhs, rhs) => {
+                let children = [self.real(lhs, names), self.real(rhs, names)];
+                self.node(func.name(), &children)
+            }
+            RealExpression::Case(arms, otherwise) => {
+                let mut children = Vec::with_capacity(2 * arms.len() + 1);
+                for (condition, value) in arms {
+                    children.push(self.bool(condition, names));
+                    children.push(self.real(value, names));
+                }
+                children.push(self.real(otherwise, names));
+                self.node(expr.node_name(), &children)
+            }
+            RealExpression::Literal(value) => self.node(&value.to_string(), &[]),
+            RealExpression::IntLiteral(value) => self.node(&value.to_string(), &[]),
+            RealExpression::Binding(binding) => self.node(&names(*binding), &[]),
+        }
+    }
+
+    fn bool<Real: Display>(
+        &mut self,
+        expr: &BoolExpression<Real>,
+        names: &impl Fn(BindingId) -> String,
+    ) -> usize {
+        match expr {
+            BoolExpression::And(lhs, rhs)
+            | BoolExpression::Or(lhs, rhs)
+            | BoolExpression::Xor(lhs, rhs)
+            | BoolExpression::Implies(lhs, rhs) => {
+                let children = [self.bool(lhs, names), self.bool(rhs, names)];
+                self.node(expr.node_name(), &children)
+            }
+            BoolExpression::Not(only) => {
+                let child = self.bool(only, names);
+                self.node(expr.node_name(), &[child])
+            }
+            BoolExpression::Equal(lhs, rhs)
+            | BoolExpression::Greater(lhs, rhs)
+            | BoolExpression::GreaterEqual(lhs, rhs)
+            | BoolExpression::Less(lhs, rhs)
+            | BoolExpression::LessEqual(lhs, rhs)
+            | BoolExpression::NotEqual(lhs, rhs) => {
+                let children = [self.real(lhs, names), self.real(rhs, names)];
+                self.node(expr.node_name(), &children)
+            }
+            BoolExpression::Between(value, lo, hi) => {
+                let children = [
+                    self.real(value, names),
+                    self.real(lo, names),
+                    self.real(hi, names),
+                ];
+                self.node(expr.node_name(), &children)
+            }
+            BoolExpression::StrEqual(lhs, rhs) | BoolExpression::StrNotEqual(lhs, rhs) => {
+                let children = [self.string(lhs, names), self.string(rhs, names)];
+                self.node(expr.node_name(), &children)
+            }
+            BoolExpression::CoercedEqual(lhs, rhs) | BoolExpression::CoercedNotEqual(lhs, rhs) => {
+                let children = [self.string(lhs, names), self.real(rhs, names)];
+                self.node(expr.node_name(), &children)
+            }
+            BoolExpression::Literal(value) => self.node(&value.to_string(), &[]),
+        }
+    }
+
+    fn string(&mut self, expr: &StringExpression, names: &impl Fn(BindingId) -> String) -> usize {
+        match expr {
+            StringExpression::Literal(value) => self.node(&format!("\"{value}\""), &[]),
+            StringExpression::Binding(binding) => self.node(&names(*binding), &[]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn binding_map(var_name: &str) -> BindingId {
+        match var_name {
+            "x" => 0,
+            "y" => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn names(binding: BindingId) -> String {
+        ["x", "y"][binding].into()
+    }
+
+    #[test]
+    fn real_expression_to_dot() {
+        let parsed = Expression::<f64>::parse("x * (y + 2.5)", binding_map).unwrap();
+        let dot = parsed.unwrap_real().to_dot(names);
+        assert!(dot.starts_with("digraph {\n"), "{dot}");
+        assert_eq!(dot.matches("[label=").count(), 5, "{dot}");
+        assert_eq!(dot.matches(" -> ").count(), 4, "{dot}");
+        assert!(dot.contains("n0 [label=\"x\"];"), "{dot}");
+        assert!(dot.contains("n2 [label=\"2.5\"];"), "{dot}");
+        assert!(dot.contains("n3 [label=\"Add\"];"), "{dot}");
+        assert!(dot.contains("n4 -> n3;"), "{dot}");
+    }
+
+    #[test]
+    fn bool_expression_to_dot() {
+        let parsed = Expression::<f64>::parse("x == \"a b\" || y > 1", binding_map).unwrap();
+        let dot = parsed.unwrap_bool().to_dot(names);
+        assert_eq!(dot.matches("[label=").count(), 7, "{dot}");
+        assert!(dot.contains("n1 [label=\"\\\"a b\\\"\"];"), "{dot}");
+        assert!(dot.contains("n6 [label=\"Or\"];"), "{dot}");
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{BindingId, ValueType};
+use std::fmt;
+
+/// An error detected while evaluating an expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    /// A node produced a non-finite value at element `index` while
+    /// [`NonFinitePolicy::Report`](crate::NonFinitePolicy::Report) was set.
+    NonFinite { index: usize },
+    /// A power produced a non-finite value from finite operands at element
+    /// `index` while
+    /// [`Registers::set_check_power_overflow`](crate::Registers::set_check_power_overflow)
+    /// was enabled.
+    Overflow { index: usize },
+    /// The expression reads a binding beyond the end of the provided
+    /// bindings.
+    MissingBinding(BindingId),
+    /// A binding's length differs from the register length, or from the
+    /// length of the first binding where the register length is inferred.
+    BindingLengthMismatch {
+        binding: BindingId,
+        len: usize,
+        expected: usize,
+    },
+    /// The function with this name is not supported by the real type (see
+    /// [`ExprScalar::func1`](crate::ExprScalar::func1)).
+    Unsupported(&'static str),
+    /// The string literal could not be resolved to a
+    /// [`StringId`](crate::StringId).
+    UnknownStringLiteral(String),
+    /// Evaluation needs `needed` bytes of registers at once, more than the
+    /// `budget` of [`Registers::with_budget`](crate::Registers::with_budget).
+    MemoryBudgetExceeded { needed: usize, budget: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonFinite { index } => write!(f, "non-finite value at element {index}"),
+            Self::Overflow { index } => write!(f, "power overflowed at element {index}"),
+            Self::MissingBinding(binding) => write!(f, "binding {binding} was not provided"),
+            Self::BindingLengthMismatch {
+                binding,
+                len,
+                expected,
+            } => write!(f, "binding {binding} has length {len}, expected {expected}"),
+            Self::Unsupported(name) => write!(f, "`{name}` is not supported by the scalar type"),
+            Self::UnknownStringLiteral(literal) => write!(f, "unknown string literal {literal:?}"),
+            Self::MemoryBudgetExceeded { needed, budget } => write!(
+                f,
+                "evaluation needs {needed} bytes of registers, exceeding the budget of {budget}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// An error detected by [`Expression::validate`](crate::Expression::validate).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The schema does not declare the binding.
+    UnknownBinding(BindingId),
+    /// The expression reads the binding as `expected`, but the schema
+    /// declares it as `found`.
+    BindingType {
+        binding: BindingId,
+        expected: ValueType,
+        found: ValueType,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownBinding(binding) => write!(f, "binding {binding} is not in the schema"),
+            Self::BindingType {
+                binding,
+                expected,
+                found,
+            } => write!(
+                f,
+                "binding {binding} is used as a {expected} but is a {found}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
@@ -1,5 +1,14 @@
-use crate::{BoolExpression, FloatExt, RealExpression, StringExpression};
+use crate::{
+    BindingId, BoolExpression, CaseArm, ChildIndex, CustomFunction, EvalError, ExprScalar,
+    FloatExt, Func1, Func2, FunctionTable, Profile, RealExpression, StringExpression, Trace,
+    TraceStep, TupleExpression, TRACE_VALUES,
+};
 use bitvec::vec::BitVec;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 
 #[cfg(feature = "rayon")]
 use rayon::{
@@ -7,65 +16,351 @@ use rayon::{
         IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
         ParallelExtend, ParallelIterator,
     },
-    slice::ParallelSlice,
+    slice::{ParallelSlice, ParallelSliceMut},
 };
 
+/// Number of rows evaluated between checks for cancellation by
+/// [`RealExpression::evaluate_cancellable`].
+const CANCELLATION_CHUNK_LEN: usize = 1 << 16;
+
 /// To speed up string comparisons, we use string interning.
 pub type StringId = u32;
 
-impl<Real: FloatExt> BoolExpression<Real> {
+impl<Real: ExprScalar> BoolExpression<Real> {
     /// Calculates the `bool`-valued results of the expression component-wise.
     pub fn evaluate<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
         &self,
         real_bindings: &[R],
         string_bindings: &[S],
-        mut get_string_literal_id: impl FnMut(&str) -> StringId,
+        get_string_literal_id: impl FnMut(&str) -> StringId,
         registers: &mut Registers<Real>,
     ) -> BitVec {
         validate_bindings(real_bindings, registers.register_length);
         validate_bindings(string_bindings, registers.register_length);
-        self.evaluate_recursive(
+        unbudgeted(self.evaluate_recursive(
             real_bindings,
             string_bindings,
-            &mut get_string_literal_id,
+            &mut StringLookup {
+                get_literal_id: get_string_literal_id,
+                parse: None,
+            },
             registers,
-        )
+        ))
     }
 
-    fn evaluate_recursive<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+    /// Like [`Self::evaluate`], but `get_string_literal_id` may return
+    /// `None` for a literal it does not know, which fails with
+    /// [`EvalError::UnknownStringLiteral`] instead of panicking.
+    ///
+    /// Every literal is looked up once before evaluation starts. Also fails if
+    /// the expression reads a real or string binding that was not provided, if
+    /// a binding's length differs from the register length, or if evaluation
+    /// exceeds the budget of [`Registers::with_budget`].
+    pub fn try_evaluate<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
         &self,
         real_bindings: &[R],
         string_bindings: &[S],
-        get_string_literal_id: &mut impl FnMut(&str) -> StringId,
+        mut get_string_literal_id: impl FnMut(&str) -> Option<StringId>,
+        registers: &mut Registers<Real>,
+    ) -> Result<BitVec, EvalError> {
+        check_binding_lengths(real_bindings, registers.register_length)?;
+        check_binding_lengths(string_bindings, registers.register_length)?;
+        let mut real_ids = Vec::new();
+        self.collect_real_binding_ids(&mut real_ids);
+        let mut string_ids = Vec::new();
+        self.collect_string_binding_ids(&mut string_ids);
+        for (ids, num_bindings) in [
+            (real_ids, real_bindings.len()),
+            (string_ids, string_bindings.len()),
+        ] {
+            if let Some(binding) = ids.into_iter().max() {
+                if binding >= num_bindings {
+                    return Err(EvalError::MissingBinding(binding));
+                }
+            }
+        }
+        let literal_ids = self
+            .string_literals()
+            .into_iter()
+            .map(|literal| match get_string_literal_id(literal) {
+                Some(id) => Ok((literal, id)),
+                None => Err(EvalError::UnknownStringLiteral(literal.to_owned())),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        if let Some(name) = self.unsupported_function() {
+            return Err(EvalError::Unsupported(name));
+        }
+        registers.start_budget();
+        let output = self.evaluate_recursive(
+            real_bindings,
+            string_bindings,
+            &mut StringLookup {
+                get_literal_id: |literal: &str| literal_ids[literal],
+                parse: None,
+            },
+            registers,
+        );
+        registers.end_budget();
+        output
+    }
+
+    /// Like [`Self::evaluate`], but also evaluates comparisons between
+    /// strings and reals (see
+    /// [`Parser::with_string_coercion`](crate::Parser::with_string_coercion)).
+    ///
+    /// `get_string_value` looks up the value of an interned string, which is
+    /// parsed as a real for each row.
+    pub fn evaluate_coercing<'s, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        get_string_literal_id: impl FnMut(&str) -> StringId,
+        get_string_value: impl Fn(StringId) -> &'s str,
         registers: &mut Registers<Real>,
     ) -> BitVec {
+        validate_bindings(real_bindings, registers.register_length);
+        validate_bindings(string_bindings, registers.register_length);
+        let parse_string = |id| get_string_value(id).trim().parse().ok();
+        unbudgeted(self.evaluate_recursive(
+            real_bindings,
+            string_bindings,
+            &mut StringLookup {
+                get_literal_id: get_string_literal_id,
+                parse: Some(&parse_string),
+            },
+            registers,
+        ))
+    }
+
+    /// Like [`Self::evaluate`], but produces `1` where the expression is
+    /// true and `0` where it is false.
+    pub fn evaluate_real<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        get_string_literal_id: impl FnMut(&str) -> StringId,
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        let mask = self.evaluate(
+            real_bindings,
+            string_bindings,
+            get_string_literal_id,
+            registers,
+        );
+        let mut output = unbudgeted(registers.allocate_real());
+
+        #[cfg(feature = "rayon")]
+        {
+            output.resize(registers.register_length, Real::from_i32(0));
+            let bits_per_block = usize::BITS as usize;
+            output
+                .par_chunks_mut(bits_per_block)
+                .zip(mask.as_raw_slice().par_iter())
+                .for_each(|(out_chunk, block)| {
+                    for (i, out) in out_chunk.iter_mut().enumerate() {
+                        if (block >> i) & 1 == 1 {
+                            *out = Real::from_i32(1);
+                        }
+                    }
+                });
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            output.extend(mask.iter().map(|bit| Real::from_i32(i32::from(*bit))));
+        }
+
+        registers.recycle_bool(mask);
+        output
+    }
+
+    /// Like [`Self::evaluate`], but run-length encodes the output as
+    /// `(value, run_length)` pairs, which is much smaller than a [`BitVec`]
+    /// when the results are sparse or clustered.
+    ///
+    /// The runs are in order and adjacent runs have different values.
+    pub fn evaluate_rle<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        get_string_literal_id: impl FnMut(&str) -> StringId,
+        registers: &mut Registers<Real>,
+    ) -> Vec<(bool, usize)> {
+        let mask = self.evaluate(
+            real_bindings,
+            string_bindings,
+            get_string_literal_id,
+            registers,
+        );
+        let mut runs = Vec::new();
+        let mut rest = mask.as_bitslice();
+        while let Some(&value) = rest.first().as_deref() {
+            // Searches a word at a time for the end of the run.
+            let end = if value {
+                rest.first_zero()
+            } else {
+                rest.first_one()
+            };
+            let run_length = end.unwrap_or(rest.len());
+            runs.push((value, run_length));
+            rest = &rest[run_length..];
+        }
+        registers.recycle_bool(mask);
+        runs
+    }
+
+    /// Like [`Self::evaluate`], but returns the ascending indices of the rows
+    /// where the expression is true, e.g. to gather the selected rows.
+    pub fn evaluate_indices<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        get_string_literal_id: impl FnMut(&str) -> StringId,
+        registers: &mut Registers<Real>,
+    ) -> Vec<usize> {
+        let mask = self.evaluate(
+            real_bindings,
+            string_bindings,
+            get_string_literal_id,
+            registers,
+        );
+
+        #[cfg(feature = "rayon")]
+        let indices = {
+            // Each block yields its indices in order, and collecting keeps
+            // the blocks in order.
+            let bits_per_block = usize::BITS as usize;
+            let len = mask.len();
+            mask.as_raw_slice()
+                .par_iter()
+                .enumerate()
+                .flat_map_iter(|(block_index, &block)| {
+                    let start = block_index * bits_per_block;
+                    (0..bits_per_block)
+                        .filter(move |i| (block >> i) & 1 == 1)
+                        .map(move |i| start + i)
+                })
+                // Bits past the end of the last block are unspecified.
+                .filter(|&i| i < len)
+                .collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let indices = mask.iter_ones().collect();
+
+        registers.recycle_bool(mask);
+        indices
+    }
+
+    /// Like [`Self::evaluate`], but writes the mask into `out` as one byte per
+    /// row, which is 1 where the expression is true and 0 elsewhere, e.g. to
+    /// pass it over FFI.
+    ///
+    /// # Panics
+    ///
+    /// If `out.len()` differs from the register length.
+    pub fn evaluate_bytes_into<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        get_string_literal_id: impl FnMut(&str) -> StringId,
+        registers: &mut Registers<Real>,
+        out: &mut [u8],
+    ) {
+        assert_eq!(out.len(), registers.register_length);
+        let mask = self.evaluate(
+            real_bindings,
+            string_bindings,
+            get_string_literal_id,
+            registers,
+        );
+
+        #[cfg(feature = "rayon")]
+        {
+            // Each chunk of `out` is expanded from one block of the mask, and
+            // the bits past the end of the last block are never read.
+            let bits_per_block = usize::BITS as usize;
+            out.par_chunks_mut(bits_per_block)
+                .zip(mask.as_raw_slice().par_iter())
+                .for_each(|(bytes, &block)| {
+                    for (i, byte) in bytes.iter_mut().enumerate() {
+                        *byte = ((block >> i) & 1) as u8;
+                    }
+                });
+        }
+        #[cfg(not(feature = "rayon"))]
+        for (byte, bit) in out.iter_mut().zip(mask.iter().by_vals()) {
+            *byte = u8::from(bit);
+        }
+
+        registers.recycle_bool(mask);
+    }
+
+    /// Like [`RealExpression::max_register_pressure`], but there is no real
+    /// output register.
+    pub(crate) fn max_real_register_pressure(&self) -> usize {
+        // The pressure of evaluating real operands in order, where each
+        // operand that is not a binding holds a register until the end.
+        let operands = |operands: &[&RealExpression<Real>]| {
+            let mut held = 0;
+            let mut peak = 0;
+            for operand in operands {
+                if !matches!(operand, RealExpression::Binding(_)) {
+                    peak = peak.max(held + operand.max_register_pressure());
+                    held += 1;
+                }
+            }
+            peak
+        };
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => lhs
+                .max_real_register_pressure()
+                .max(rhs.max_real_register_pressure()),
+            Self::Not(only) => only.max_real_register_pressure(),
+            Self::Equal(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::NotEqual(lhs, rhs) => operands(&[lhs, rhs]),
+            Self::Between(value, lo, hi) => operands(&[value, lo, hi]),
+            Self::CoercedEqual(_, rhs) | Self::CoercedNotEqual(_, rhs) => operands(&[rhs]),
+            Self::StrEqual(..) | Self::StrNotEqual(..) | Self::Literal(_) => 0,
+        }
+    }
+
+    fn evaluate_recursive<R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+        &self,
+        real_bindings: &[R],
+        string_bindings: &[S],
+        strings: &mut StringLookup<impl FnMut(&str) -> StringId, Real>,
+        registers: &mut Registers<Real>,
+    ) -> Result<BitVec, EvalError> {
+        registers.record_node(self.node_name());
         let reg_len = registers.register_length;
         match self {
             Self::And(lhs, rhs) => evaluate_binary_logic(
-                |lhs, rhs, out| {
+                |lhs, rhs| {
                     #[cfg(feature = "rayon")]
                     {
-                        out.resize(reg_len, Default::default());
-                        lhs.as_raw_slice()
-                            .par_iter()
+                        lhs.as_raw_mut_slice()
+                            .par_iter_mut()
                             .zip(rhs.as_raw_slice().par_iter())
-                            .zip(out.as_raw_mut_slice().par_iter_mut())
-                            .for_each(|((lhs, rhs), out)| {
-                                *out = lhs & rhs;
+                            .for_each(|(lhs, rhs)| {
+                                *lhs &= rhs;
                             })
                     }
                     #[cfg(not(feature = "rayon"))]
                     {
-                        out.resize(reg_len, true);
-                        *out &= lhs;
-                        *out &= rhs;
+                        *lhs &= rhs;
                     }
                 },
                 lhs.as_ref(),
                 rhs.as_ref(),
                 real_bindings,
                 string_bindings,
-                get_string_literal_id,
+                strings,
                 registers,
             ),
             Self::Equal(lhs, rhs) => evaluate_real_comparison(
@@ -103,6 +398,18 @@ impl<Real: FloatExt> BoolExpression<Real> {
                 real_bindings,
                 registers,
             ),
+            Self::Between(value, lo, hi) => evaluate_between(
+                value.as_ref(),
+                lo.as_ref(),
+                hi.as_ref(),
+                real_bindings,
+                registers,
+            ),
+            Self::Literal(value) => {
+                let mut output = registers.allocate_bool()?;
+                output.resize(reg_len, *value);
+                Ok(output)
+            }
             Self::Not(only) => evaluate_unary_logic(
                 |only| {
                     #[cfg(feature = "rayon")]
@@ -119,7 +426,7 @@ impl<Real: FloatExt> BoolExpression<Real> {
                 only.as_ref(),
                 real_bindings,
                 string_bindings,
-                get_string_literal_id,
+                strings,
                 registers,
             ),
             Self::NotEqual(lhs, rhs) => evaluate_real_comparison(
@@ -130,30 +437,73 @@ impl<Real: FloatExt> BoolExpression<Real> {
                 registers,
             ),
             Self::Or(lhs, rhs) => evaluate_binary_logic(
-                |lhs, rhs, out| {
+                |lhs, rhs| {
+                    #[cfg(feature = "rayon")]
+                    {
+                        lhs.as_raw_mut_slice()
+                            .par_iter_mut()
+                            .zip(rhs.as_raw_slice().par_iter())
+                            .for_each(|(lhs, rhs)| {
+                                *lhs |= rhs;
+                            })
+                    }
+                    #[cfg(not(feature = "rayon"))]
+                    {
+                        *lhs |= rhs;
+                    }
+                },
+                lhs.as_ref(),
+                rhs.as_ref(),
+                real_bindings,
+                string_bindings,
+                strings,
+                registers,
+            ),
+            Self::Xor(lhs, rhs) => evaluate_binary_logic(
+                |lhs, rhs| {
                     #[cfg(feature = "rayon")]
                     {
-                        out.resize(reg_len, Default::default());
-                        lhs.as_raw_slice()
-                            .par_iter()
+                        lhs.as_raw_mut_slice()
+                            .par_iter_mut()
                             .zip(rhs.as_raw_slice().par_iter())
-                            .zip(out.as_raw_mut_slice().par_iter_mut())
-                            .for_each(|((lhs, rhs), out)| {
-                                *out = lhs | rhs;
+                            .for_each(|(lhs, rhs)| {
+                                *lhs ^= rhs;
                             })
                     }
                     #[cfg(not(feature = "rayon"))]
                     {
-                        out.resize(reg_len, false);
-                        *out |= lhs;
-                        *out |= rhs;
+                        *lhs ^= rhs;
                     }
                 },
                 lhs.as_ref(),
                 rhs.as_ref(),
                 real_bindings,
                 string_bindings,
-                get_string_literal_id,
+                strings,
+                registers,
+            ),
+            Self::Implies(lhs, rhs) => evaluate_binary_logic(
+                |lhs, rhs| {
+                    #[cfg(feature = "rayon")]
+                    {
+                        lhs.as_raw_mut_slice()
+                            .par_iter_mut()
+                            .zip(rhs.as_raw_slice().par_iter())
+                            .for_each(|(lhs, rhs)| {
+                                *lhs = !*lhs | rhs;
+                            })
+                    }
+                    #[cfg(not(feature = "rayon"))]
+                    {
+                        *lhs = !std::mem::take(lhs);
+                        *lhs |= rhs;
+                    }
+                },
+                lhs.as_ref(),
+                rhs.as_ref(),
+                real_bindings,
+                string_bindings,
+                strings,
                 registers,
             ),
             Self::StrEqual(lhs, rhs) => evaluate_string_comparison(
@@ -161,7 +511,7 @@ impl<Real: FloatExt> BoolExpression<Real> {
                 lhs,
                 rhs,
                 string_bindings,
-                get_string_literal_id,
+                &mut strings.get_literal_id,
                 registers,
             ),
             Self::StrNotEqual(lhs, rhs) => evaluate_string_comparison(
@@ -169,14 +519,32 @@ impl<Real: FloatExt> BoolExpression<Real> {
                 lhs,
                 rhs,
                 string_bindings,
-                get_string_literal_id,
+                &mut strings.get_literal_id,
+                registers,
+            ),
+            Self::CoercedEqual(lhs, rhs) => evaluate_coerced_comparison(
+                |lhs, rhs| lhs == Some(rhs),
+                lhs,
+                rhs.as_ref(),
+                real_bindings,
+                string_bindings,
+                strings.parse,
+                registers,
+            ),
+            Self::CoercedNotEqual(lhs, rhs) => evaluate_coerced_comparison(
+                |lhs, rhs| lhs != Some(rhs),
+                lhs,
+                rhs.as_ref(),
+                real_bindings,
+                string_bindings,
+                strings.parse,
                 registers,
             ),
         }
     }
 }
 
-impl<Real: FloatExt> RealExpression<Real> {
+impl<Real: ExprScalar> RealExpression<Real> {
     pub fn evaluate_without_vars(&self, registers: &mut Registers<Real>) -> Vec<Real> {
         self.evaluate::<[_; 0]>(&[], registers)
     }
@@ -188,27 +556,532 @@ impl<Real: FloatExt> RealExpression<Real> {
         registers: &mut Registers<Real>,
     ) -> Vec<Real> {
         validate_bindings(bindings, registers.register_length);
-        self.evaluate_recursive(bindings, registers)
+        unbudgeted(self.evaluate_recursive(bindings, registers))
     }
 
-    fn evaluate_recursive<R: AsRef<[Real]>>(
+    /// Like [`Self::evaluate`], but runs every parallel kernel on `pool`
+    /// instead of the global rayon pool.
+    #[cfg(feature = "rayon")]
+    pub fn evaluate_in_pool<R: AsRef<[Real]> + Sync>(
         &self,
+        pool: &rayon::ThreadPool,
         bindings: &[R],
         registers: &mut Registers<Real>,
     ) -> Vec<Real> {
+        // Parallel iterators started within `install` run on the installed
+        // pool, including those nested inside recursive evaluation.
+        pool.install(|| self.evaluate(bindings, registers))
+    }
+
+    /// Like [`Self::try_evaluate`], but infers the register length from the
+    /// first binding and evaluates with temporary [`Registers`].
+    ///
+    /// Without any bindings, the output has a single element.
+    pub fn evaluate_auto<R: AsRef<[Real]>>(&self, bindings: &[R]) -> Result<Vec<Real>, EvalError> {
+        let expected = bindings.first().map_or(1, |b| b.as_ref().len());
+        self.try_evaluate(bindings, &mut Registers::new(expected))
+    }
+
+    /// Like [`Self::evaluate`], but fails if the expression reads a binding
+    /// that was not provided, if a binding's length differs from the register
+    /// length, if any node produced a non-finite value while
+    /// [`NonFinitePolicy::Report`] is set on `registers`, if a power
+    /// overflowed while [`Registers::set_check_power_overflow`] is enabled, or
+    /// if evaluation exceeds the budget of [`Registers::with_budget`].
+    ///
+    /// # Panics
+    ///
+    /// If the expression calls a custom function that is not in the function
+    /// table of `registers` (see [`Registers::set_functions`]).
+    pub fn try_evaluate<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Result<Vec<Real>, EvalError> {
+        check_binding_lengths(bindings, registers.register_length)?;
+        if let Some(&binding) = self.binding_ids().last() {
+            if binding >= bindings.len() {
+                return Err(EvalError::MissingBinding(binding));
+            }
+        }
+        if let Some(name) = self.unsupported_function() {
+            return Err(EvalError::Unsupported(name));
+        }
+        registers.first_non_finite = None;
+        registers.first_overflow = None;
+        registers.start_budget();
+        let output = self.evaluate_recursive(bindings, registers);
+        registers.end_budget();
+        let output = output?;
+        let error = match (
+            registers.first_non_finite.take(),
+            registers.first_overflow.take(),
+        ) {
+            (Some(index), _) => EvalError::NonFinite { index },
+            (None, Some(index)) => EvalError::Overflow { index },
+            (None, None) => return Ok(output),
+        };
+        registers.recycle_real(output);
+        Err(error)
+    }
+
+    /// Evaluates only the subexpression at `path` (see
+    /// [`Self::node_at_path`]), e.g. to inspect intermediate values while
+    /// debugging.
+    ///
+    /// # Panics
+    ///
+    /// If there is no subexpression at `path`.
+    pub fn evaluate_node_path<R: AsRef<[Real]>>(
+        &self,
+        path: &[ChildIndex],
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        match self.node_at_path(path) {
+            Some(node) => node.evaluate(bindings, registers),
+            None => panic!("No subexpression at path {path:?}"),
+        }
+    }
+
+    /// Like [`Self::evaluate`], but gives up and returns `None` once `cancel`
+    /// is set.
+    ///
+    /// The rows are evaluated in chunks, and `cancel` is checked before each
+    /// chunk, so cancellation takes effect within one chunk's evaluation.
+    pub fn evaluate_cancellable<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        cancel: &AtomicBool,
+        registers: &mut Registers<Real>,
+    ) -> Option<Vec<Real>> {
+        validate_bindings(bindings, registers.register_length);
+        let register_length = registers.register_length;
+        if register_length <= CANCELLATION_CHUNK_LEN {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            return Some(unbudgeted(self.evaluate_recursive(bindings, registers)));
+        }
+
+        // The chunks are evaluated with their own registers, so that neither
+        // these nor the caller's registers are resized for each chunk.
+        let mut scratch = registers.child(CANCELLATION_CHUNK_LEN);
+        let mut chunk: Vec<&[Real]> = Vec::with_capacity(bindings.len());
+        let mut output = unbudgeted(registers.allocate_real());
+        let mut cancelled = false;
+        for start in (0..register_length).step_by(CANCELLATION_CHUNK_LEN) {
+            if cancel.load(Ordering::Relaxed) {
+                cancelled = true;
+                break;
+            }
+            let end = (start + CANCELLATION_CHUNK_LEN).min(register_length);
+            chunk.clear();
+            chunk.extend(bindings.iter().map(|b| &b.as_ref()[start..end]));
+            scratch.set_register_length(end - start);
+            let values = unbudgeted(self.evaluate_recursive(&chunk, &mut scratch));
+            if registers.first_non_finite.is_none() {
+                registers.first_non_finite = scratch.first_non_finite.map(|i| start + i);
+            }
+            if registers.first_overflow.is_none() {
+                registers.first_overflow = scratch.first_overflow.map(|i| start + i);
+            }
+            output.extend_from_slice(&values);
+            scratch.recycle_real(values);
+        }
+        // Drops the chunk-sized registers, which the caller cannot use.
+        scratch.set_register_length(register_length);
+        registers.join(scratch);
+        if cancelled {
+            registers.recycle_real(output);
+            return None;
+        }
+        Some(output)
+    }
+
+    /// Like [`Self::evaluate`], but borrows the binding instead of copying it
+    /// if the whole expression is a single binding.
+    pub fn evaluate_borrowed<'a, R: AsRef<[Real]>>(
+        &self,
+        bindings: &'a [R],
+        registers: &mut Registers<Real>,
+    ) -> Cow<'a, [Real]> {
+        if let Self::Binding(binding) = self {
+            validate_bindings(bindings, registers.register_length);
+            return Cow::Borrowed(binding_values(bindings, *binding));
+        }
+        Cow::Owned(self.evaluate(bindings, registers))
+    }
+
+    /// Like [`Self::evaluate`], but returns the output as an `Arc` that can
+    /// be shared cheaply.
+    ///
+    /// An `Arc` stores its reference counts alongside the values, so it can
+    /// never reuse a register. The output is always copied into a new
+    /// allocation outside the pool, and its register is recycled.
+    pub fn evaluate_arc<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Arc<[Real]> {
+        let output = self.evaluate(bindings, registers);
+        let shared = Arc::from(output.as_slice());
+        registers.recycle_real(output);
+        shared
+    }
+
+    /// Like [`Self::evaluate`], but takes ownership of the bindings so their
+    /// buffers can be donated to `registers`.
+    ///
+    /// If the expression is a bare binding, that binding is returned without
+    /// copying. Every other binding is recycled into `registers` once
+    /// evaluation finishes, so subsequent evaluations can reuse the buffers
+    /// instead of allocating.
+    pub fn evaluate_owned(
+        &self,
+        mut bindings: Vec<Vec<Real>>,
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        validate_bindings(&bindings, registers.register_length);
+        let output = if let Self::Binding(binding) = self {
+            std::mem::take(&mut bindings[*binding])
+        } else {
+            unbudgeted(self.evaluate_recursive(&bindings, registers))
+        };
+        for binding in bindings {
+            if binding.capacity() > 0 {
+                registers.donate_real(binding);
+            }
+        }
+        output
+    }
+
+    /// Like [`Self::evaluate`], but reads the bindings from a row-major
+    /// `data` buffer with `num_vars` values per row, i.e. element `j` of
+    /// binding `i` is `data[j * num_vars + i]`.
+    ///
+    /// Each referenced binding is gathered into a register of `registers`.
+    ///
+    /// # Panics
+    ///
+    /// If `data` does not have `num_vars` values for each of the register
+    /// length rows, or if a binding is not less than `num_vars`.
+    pub fn evaluate_interleaved(
+        &self,
+        data: &[Real],
+        num_vars: usize,
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        assert_eq!(data.len(), num_vars * registers.register_length);
+        self.evaluate_materialized(
+            |binding, register| {
+                assert!(binding < num_vars, "Missing binding {binding}");
+                register.extend(data.iter().skip(binding).step_by(num_vars))
+            },
+            registers,
+        )
+    }
+
+    /// Like [`Self::evaluate`], but reads each binding from an iterator, so
+    /// lazily generated columns need not be collected first.
+    ///
+    /// Each referenced binding is buffered into a register of `registers`,
+    /// taking the first register length values of its iterator. Bindings that
+    /// are not referenced are never advanced.
+    ///
+    /// # Panics
+    ///
+    /// If a referenced binding is missing or yields fewer values than the
+    /// register length.
+    pub fn evaluate_iter<I: Iterator<Item = Real>>(
+        &self,
+        mut bindings: Vec<I>,
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        let register_length = registers.register_length;
+        self.evaluate_materialized(
+            |binding, register| {
+                let values = bindings
+                    .get_mut(binding)
+                    .unwrap_or_else(|| panic!("Missing binding {binding}"));
+                register.extend(values.take(register_length))
+            },
+            registers,
+        )
+    }
+
+    /// Like [`Self::evaluate`], but each binding is paired with a default
+    /// value for the rows past its end, so bindings may be shorter than the
+    /// register length.
+    ///
+    /// Each referenced binding is padded into a register of `registers`.
+    /// Elements past the register length are ignored.
+    ///
+    /// # Panics
+    ///
+    /// If a referenced binding is missing.
+    pub fn evaluate_with_defaults<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[(R, Real)],
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        let register_length = registers.register_length;
+        self.evaluate_materialized(
+            |binding, register| {
+                let (values, default) = bindings
+                    .get(binding)
+                    .unwrap_or_else(|| panic!("Missing binding {binding}"));
+                let values = values.as_ref();
+                register.extend_from_slice(&values[..values.len().min(register_length)]);
+                register.resize(register_length, *default);
+            },
+            registers,
+        )
+    }
+
+    /// Like [`Self::evaluate`], but only at the rows of `bindings` given by
+    /// `indices`, e.g. those selected by an earlier filter.
+    ///
+    /// Each referenced binding is gathered into a register of `registers`,
+    /// so the register length must be `indices.len()`, while the bindings
+    /// may be any length.
+    ///
+    /// # Panics
+    ///
+    /// If the register length is not `indices.len()`, or if a referenced
+    /// binding is missing or shorter than an index.
+    pub fn evaluate_gather<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        indices: &[usize],
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        assert_eq!(indices.len(), registers.register_length);
+        self.evaluate_materialized(
+            |binding, register| {
+                let values = bindings
+                    .get(binding)
+                    .unwrap_or_else(|| panic!("Missing binding {binding}"))
+                    .as_ref();
+                register.extend(indices.iter().map(|&i| values[i]))
+            },
+            registers,
+        )
+    }
+
+    /// Evaluates `self` at every point of the grid `xs` × `ys`, without the
+    /// caller materializing the cross product.
+    ///
+    /// The output is row-major: `output[i * ys.len() + j]` is the value at
+    /// `(xs[i], ys[j])`.
+    ///
+    /// # Panics
+    ///
+    /// If the register length is not `xs.len() * ys.len()`, or if `self`
+    /// references a binding other than `x_binding` and `y_binding`.
+    pub fn evaluate_grid(
+        &self,
+        x_binding: BindingId,
+        xs: &[Real],
+        y_binding: BindingId,
+        ys: &[Real],
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        assert_eq!(xs.len() * ys.len(), registers.register_length);
+        self.evaluate_materialized(
+            |binding, register| {
+                if binding == x_binding {
+                    register.extend(xs.iter().flat_map(|&x| std::iter::repeat(x).take(ys.len())))
+                } else if binding == y_binding {
+                    register.extend(xs.iter().flat_map(|_| ys.iter().copied()))
+                } else {
+                    panic!("Missing binding {binding}")
+                }
+            },
+            registers,
+        )
+    }
+
+    /// Evaluates with bindings that are first written into registers by
+    /// `materialize`.
+    ///
+    /// Only bindings referenced by `self` are materialized, and their
+    /// registers are recycled afterwards.
+    pub(crate) fn evaluate_materialized(
+        &self,
+        mut materialize: impl FnMut(BindingId, &mut Vec<Real>),
+        registers: &mut Registers<Real>,
+    ) -> Vec<Real> {
+        let mut bindings: Vec<Vec<Real>> = Vec::new();
+        for binding in self.binding_ids() {
+            if bindings.len() <= binding {
+                bindings.resize_with(binding + 1, Vec::new);
+            }
+            let mut register = unbudgeted(registers.allocate_real());
+            materialize(binding, &mut register);
+            assert_eq!(register.len(), registers.register_length);
+            bindings[binding] = register;
+        }
+
+        let output = unbudgeted(self.evaluate_recursive(&bindings, registers));
+
+        for register in bindings {
+            if register.capacity() > 0 {
+                registers.recycle_real(register);
+            }
+        }
+        output
+    }
+
+    /// Like [`Self::evaluate`], but also folds every element of the output
+    /// into an accumulator as the root node's kernel writes it, rather than
+    /// in a second pass over the output.
+    ///
+    /// A [`Case`](Self::Case) root, a power root while
+    /// [`Registers::set_check_power_overflow`] is enabled and
+    /// [`NonFinitePolicy::Saturate`] still fold the finished output.
+    pub(crate) fn evaluate_folding<R: AsRef<[Real]>, A: Copy + Send + Sync>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+        fold: &Fold<A, Real>,
+    ) -> (Vec<Real>, A) {
+        validate_bindings(bindings, registers.register_length);
+        let fused = registers.non_finite_policy != NonFinitePolicy::Saturate;
+        let powers_fused = fused && !registers.check_power_overflow;
+        let (mut output, folded) = unbudgeted(match self {
+            Self::Add(lhs, rhs) if fused => {
+                fold_binary_real_op(|lhs, rhs| lhs + rhs, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Div(lhs, rhs) if fused => {
+                fold_binary_real_op(|lhs, rhs| lhs / rhs, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Mul(lhs, rhs) if fused => {
+                fold_binary_real_op(|lhs, rhs| lhs * rhs, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Sub(lhs, rhs) if fused => {
+                fold_binary_real_op(|lhs, rhs| lhs - rhs, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Pow(lhs, rhs) if powers_fused => {
+                fold_binary_real_op(Real::pow, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Func2(func, lhs, rhs) if fused => {
+                let op = registers.func2(*func);
+                fold_binary_real_op(op, lhs, rhs, bindings, registers, fold)
+            }
+            Self::Neg(only) if fused => {
+                fold_unary_real_op(|only| -only, only, bindings, registers, fold)
+            }
+            Self::Func1(func, only) if fused => {
+                let op = registers.func1(*func);
+                fold_unary_real_op(op, only, bindings, registers, fold)
+            }
+            Self::PowI(only, n) if powers_fused => {
+                fold_unary_real_op(|only| only.pow_int(*n), only, bindings, registers, fold)
+            }
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) if fused => {
+                fold_unary_real_op(|only| only, self, bindings, registers, fold)
+            }
+            _ => {
+                let output = unbudgeted(self.evaluate_recursive(bindings, registers));
+                let folded = output
+                    .iter()
+                    .fold(fold.identity, |acc, &v| (fold.push)(acc, v));
+                return (output, folded);
+            }
+        });
+        registers.record_node(self.node_name());
+        registers.check_non_finite(&mut output);
+        registers.record_values(self.node_name(), &output);
+        (output, folded)
+    }
+
+    /// The peak number of real registers that are live at once while
+    /// evaluating `self`, including the output register.
+    ///
+    /// This is the number of registers that [`Self::evaluate`] allocates when
+    /// given [`Registers`] without any free registers, so preallocating this
+    /// many avoids allocation entirely.
+    pub fn max_register_pressure(&self) -> usize {
         match self {
+            Self::Add(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Pow(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Func2(_, lhs, rhs) => {
+                #[cfg(feature = "rayon")]
+                if has_at_least_nodes(lhs, MIN_PARALLEL_SUBTREE_NODES)
+                    && has_at_least_nodes(rhs, MIN_PARALLEL_SUBTREE_NODES)
+                {
+                    // Both subtrees are evaluated concurrently.
+                    return (lhs.max_register_pressure() + rhs.max_register_pressure()).max(3);
+                }
+                // Mirrors the operand handling of `evaluate_binary_real_op`,
+                // which broadcasts a literal operand.
+                if literal_scalar(rhs).is_some() {
+                    return lhs.unary_op_register_pressure();
+                }
+                if literal_scalar(lhs).is_some() {
+                    return rhs.unary_op_register_pressure();
+                }
+                let (lhs_peak, lhs_held) = match lhs.as_ref() {
+                    Self::Binding(_) => (0, 0),
+                    lhs => (lhs.max_register_pressure(), 1),
+                };
+                let (rhs_peak, rhs_held) = match rhs.as_ref() {
+                    Self::Binding(_) => (0, 0),
+                    rhs => (lhs_held + rhs.max_register_pressure(), 1),
+                };
+                lhs_peak.max(rhs_peak).max(lhs_held + rhs_held + 1)
+            }
+            Self::Neg(only) | Self::Func1(_, only) | Self::PowI(only, _) => {
+                only.unary_op_register_pressure()
+            }
+            // Mirrors `evaluate_case`, which holds the output while
+            // evaluating each arm.
+            Self::Case(arms, otherwise) => arms
+                .iter()
+                .map(|(condition, value)| {
+                    let value_peak = match value {
+                        Self::Binding(_) => 0,
+                        value => value.max_register_pressure(),
+                    };
+                    1 + condition.max_real_register_pressure().max(value_peak)
+                })
+                .fold(otherwise.max_register_pressure(), usize::max),
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => 1,
+        }
+    }
+
+    /// The register pressure of `evaluate_unary_real_op` with `self` as the
+    /// operand.
+    fn unary_op_register_pressure(&self) -> usize {
+        match self {
+            Self::Binding(_) => 1,
+            only => only.max_register_pressure().max(2),
+        }
+    }
+
+    fn evaluate_recursive<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Result<Vec<Real>, EvalError> {
+        registers.record_node(self.node_name());
+        let mut output = match self {
             Self::Add(lhs, rhs) => evaluate_binary_real_op(
                 |lhs, rhs| lhs + rhs,
                 lhs.as_ref(),
                 rhs.as_ref(),
                 bindings,
                 registers,
-            ),
+            )?,
             // This branch should only be taken if the entire expression is
             // literally the identity map from one of the bindings.
             Self::Binding(binding) => {
-                let mut output = registers.allocate_real();
-                output.extend_from_slice(bindings[*binding].as_ref());
+                let mut output = registers.allocate_real()?;
+                output.extend_from_slice(binding_values(bindings, *binding));
                 output
             }
             Self::Div(lhs, rhs) => evaluate_binary_real_op(
@@ -217,10 +1090,10 @@ impl<Real: FloatExt> RealExpression<Real> {
                 rhs.as_ref(),
                 bindings,
                 registers,
-            ),
-            Self::Literal(value) => {
-                let mut output = registers.allocate_real();
-                output.extend(std::iter::repeat(*value).take(registers.register_length));
+            )?,
+            Self::Literal(_) | Self::IntLiteral(_) => {
+                let mut output = registers.allocate_real()?;
+                output.resize(registers.register_length, literal_scalar(self).unwrap());
                 output
             }
             Self::Mul(lhs, rhs) => evaluate_binary_real_op(
@@ -229,105 +1102,488 @@ impl<Real: FloatExt> RealExpression<Real> {
                 rhs.as_ref(),
                 bindings,
                 registers,
-            ),
+            )?,
             Self::Neg(only) => {
-                evaluate_unary_real_op(|only| -only, only.as_ref(), bindings, registers)
+                evaluate_unary_real_op(|only| -only, only.as_ref(), bindings, registers)?
+            }
+            Self::Func1(func, only) => {
+                let op = registers.func1(*func);
+                evaluate_unary_real_op(op, only.as_ref(), bindings, registers)?
+            }
+            Self::PowI(only, n) if registers.check_power_overflow => {
+                evaluate_checked_power(|x, _| x.pow_int(*n), only, None, bindings, registers)?
+            }
+            Self::PowI(only, n) => {
+                evaluate_unary_real_op(|only| only.pow_int(*n), only.as_ref(), bindings, registers)?
+            }
+            Self::Func2(func, lhs, rhs) => evaluate_binary_real_op(
+                registers.func2(*func),
+                lhs.as_ref(),
+                rhs.as_ref(),
+                bindings,
+                registers,
+            )?,
+            Self::Pow(lhs, rhs) if registers.check_power_overflow => {
+                evaluate_checked_power(Real::pow, lhs, Some(rhs), bindings, registers)?
             }
             Self::Pow(lhs, rhs) => evaluate_binary_real_op(
-                |lhs, rhs| lhs.powf(rhs),
+                |lhs, rhs| lhs.pow(rhs),
                 lhs.as_ref(),
                 rhs.as_ref(),
                 bindings,
                 registers,
-            ),
+            )?,
             Self::Sub(lhs, rhs) => evaluate_binary_real_op(
                 |lhs, rhs| lhs - rhs,
                 lhs.as_ref(),
                 rhs.as_ref(),
                 bindings,
                 registers,
-            ),
+            )?,
+            Self::Case(arms, otherwise) => evaluate_case(arms, otherwise, bindings, registers)?,
+        };
+        registers.check_non_finite(&mut output);
+        registers.record_values(self.node_name(), &output);
+        Ok(output)
+    }
+}
+
+impl<Real: FloatExt> RealExpression<Real> {
+    /// Like [`Self::evaluate`], but accumulates in a different real type, so
+    /// e.g. `f32` bindings can be evaluated with `f64` precision.
+    ///
+    /// This is not free: the expression is copied with its literals converted
+    /// to `Out`, and each referenced binding is converted up front into a
+    /// full register of `registers`, which is then evaluated like any other
+    /// binding.
+    ///
+    /// # Panics
+    ///
+    /// If a literal or binding element is not representable as `Out`.
+    pub fn evaluate_as<Out: FloatExt, R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Out>,
+    ) -> Vec<Out> {
+        validate_bindings(bindings, registers.register_length);
+        let convert = |value: &Real| -> Out {
+            num_traits::cast(*value).expect("Value not representable in output type")
+        };
+        self.map_literals(&convert).evaluate_materialized(
+            |binding, register| {
+                register.extend(binding_values(bindings, binding).iter().map(convert))
+            },
+            registers,
+        )
+    }
+}
+
+impl<Real: ExprScalar> TupleExpression<Real> {
+    /// Calculates the real-valued results of every element component-wise,
+    /// sharing `registers` between elements.
+    pub fn evaluate<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Vec<Vec<Real>> {
+        validate_bindings(bindings, registers.register_length);
+        self.elements
+            .iter()
+            .map(|element| unbudgeted(element.evaluate_recursive(bindings, registers)))
+            .collect()
+    }
+}
+
+/// Evaluates each of `exprs` and writes the results into the column-major
+/// matrix `out`.
+///
+/// `out` has `num_rows` rows and one column per expression. Column-major means
+/// the output of `exprs[j]` occupies the contiguous region
+/// `out[j * num_rows..(j + 1) * num_rows]`, so element `i` of expression `j`
+/// lands at `out[j * num_rows + i]`.
+///
+/// # Panics
+///
+/// If `num_rows` differs from the register length or `out.len()` is not
+/// exactly `exprs.len() * num_rows`.
+pub fn evaluate_batch_into<Real: ExprScalar, R: AsRef<[Real]>>(
+    exprs: &[RealExpression<Real>],
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+    out: &mut [Real],
+    num_rows: usize,
+) {
+    assert_eq!(num_rows, registers.register_length);
+    assert_eq!(out.len(), exprs.len() * num_rows);
+    validate_bindings(bindings, registers.register_length);
+    if num_rows == 0 {
+        return;
+    }
+    for (expr, column) in exprs.iter().zip(out.chunks_exact_mut(num_rows)) {
+        let output = unbudgeted(expr.evaluate_recursive(bindings, registers));
+        column.copy_from_slice(&output);
+        registers.recycle_real(output);
+    }
+}
+
+/// Evaluates each of `exprs` concurrently on the rayon pool, returning one
+/// output per expression.
+///
+/// Every rayon task owns its own [`Registers`], sized like
+/// [`RealExpression::evaluate_auto`] from the first binding, which are reused
+/// for the expressions that task evaluates.
+///
+/// # Panics
+///
+/// If the bindings have different lengths.
+#[cfg(feature = "rayon")]
+pub fn evaluate_many_parallel<Real: ExprScalar, R: AsRef<[Real]> + Sync>(
+    exprs: &[RealExpression<Real>],
+    bindings: &[R],
+) -> Vec<Vec<Real>> {
+    let register_length = bindings.first().map_or(1, |b| b.as_ref().len());
+    validate_bindings(bindings, register_length);
+    exprs
+        .par_iter()
+        .map_init(
+            || Registers::new(register_length),
+            |registers, expr| unbudgeted(expr.evaluate_recursive(bindings, registers)),
+        )
+        .collect()
+}
+
+/// Reads the values of `binding`, panicking with a clear message if fewer
+/// bindings were provided.
+fn binding_values<T, B: AsRef<[T]>>(bindings: &[B], binding: BindingId) -> &[T] {
+    match bindings.get(binding) {
+        Some(values) => values.as_ref(),
+        None => panic!(
+            "Missing binding {binding}: only {} bindings were provided",
+            bindings.len()
+        ),
+    }
+}
+
+fn check_binding_lengths<T, B: AsRef<[T]>>(
+    bindings: &[B],
+    expected: usize,
+) -> Result<(), EvalError> {
+    for (binding, values) in bindings.iter().enumerate() {
+        let len = values.as_ref().len();
+        if len != expected {
+            return Err(EvalError::BindingLengthMismatch {
+                binding,
+                len,
+                expected,
+            });
         }
     }
+    Ok(())
 }
 
-fn validate_bindings<T, B: AsRef<[T]>>(input_bindings: &[B], expected_length: usize) {
+pub(crate) fn validate_bindings<T, B: AsRef<[T]>>(input_bindings: &[B], expected_length: usize) {
     for b in input_bindings.iter() {
         assert_eq!(b.as_ref().len(), expected_length);
     }
 }
 
-fn evaluate_binary_real_op<Real: FloatExt, R: AsRef<[Real]>>(
+/// Unwraps the result of an evaluation outside [`Registers::start_budget`],
+/// which cannot fail since it only fails by exceeding the budget.
+fn unbudgeted<T>(result: Result<T, EvalError>) -> T {
+    result.unwrap_or_else(|error| unreachable!("Unbudgeted evaluation failed: {error}"))
+}
+
+fn evaluate_binary_real_op<Real: ExprScalar, R: AsRef<[Real]>>(
     op: fn(Real, Real) -> Real,
     lhs: &RealExpression<Real>,
     rhs: &RealExpression<Real>,
     bindings: &[R],
     registers: &mut Registers<Real>,
-) -> Vec<Real> {
+) -> Result<Vec<Real>, EvalError> {
+    #[cfg(feature = "rayon")]
+    if let Some([lhs_reg, rhs_reg]) = join_subtrees(lhs, rhs, bindings, registers)? {
+        let mut output = registers.allocate_real()?;
+        output.par_extend(
+            lhs_reg
+                .par_iter()
+                .zip(rhs_reg.par_iter())
+                .map(|(lhs, rhs)| op(*lhs, *rhs)),
+        );
+        registers.recycle_real(lhs_reg);
+        registers.recycle_real(rhs_reg);
+        return Ok(output);
+    }
+
+    // A literal operand is broadcast as a scalar instead of being
+    // materialized into a full register.
+    if let Some(rhs_value) = literal_scalar(rhs) {
+        return evaluate_unary_real_op(|lhs| op(lhs, rhs_value), lhs, bindings, registers);
+    }
+    if let Some(lhs_value) = literal_scalar(lhs) {
+        return evaluate_unary_real_op(|rhs| op(lhs_value, rhs), rhs, bindings, registers);
+    }
+
     // Before doing recursive evaluation, we check first if we already have
     // input values in our bindings. This avoids unnecessary copies.
     let mut lhs_reg = None;
     let lhs_values = if let RealExpression::Binding(binding) = lhs {
-        bindings[*binding].as_ref()
+        binding_values(bindings, *binding)
     } else {
-        lhs_reg = Some(lhs.evaluate_recursive(bindings, registers));
+        lhs_reg = Some(lhs.evaluate_recursive(bindings, registers)?);
         lhs_reg.as_ref().unwrap()
     };
+
     let mut rhs_reg = None;
     let rhs_values = if let RealExpression::Binding(binding) = rhs {
-        bindings[*binding].as_ref()
+        binding_values(bindings, *binding)
     } else {
-        rhs_reg = Some(rhs.evaluate_recursive(bindings, registers));
+        rhs_reg = Some(rhs.evaluate_recursive(bindings, registers)?);
         rhs_reg.as_ref().unwrap()
     };
     // Allocate this output register as lazily as possible.
-    let mut output = registers.allocate_real();
+    let mut output = registers.allocate_real()?;
+
+    #[cfg(feature = "rayon")]
+    {
+        output.par_extend(
+            lhs_values
+                .par_iter()
+                .zip(rhs_values.par_iter())
+                .map(|(lhs, rhs)| op(*lhs, *rhs)),
+        );
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        output.extend(
+            lhs_values
+                .iter()
+                .zip(rhs_values.iter())
+                .map(|(lhs, rhs)| op(*lhs, *rhs)),
+        );
+    }
+
+    if let Some(r) = lhs_reg {
+        registers.recycle_real(r);
+    }
+    if let Some(r) = rhs_reg {
+        registers.recycle_real(r);
+    }
+    Ok(output)
+}
+
+/// How [`RealExpression::evaluate_folding`] accumulates the output.
+pub(crate) struct Fold<A, Real> {
+    pub(crate) identity: A,
+    pub(crate) push: fn(A, Real) -> A,
+    /// Combines the accumulators of parts of the output, in order.
+    pub(crate) merge: fn(A, A) -> A,
+}
+
+/// An operand of a fused kernel.
+enum Operand<'a, Real> {
+    Values(&'a [Real]),
+    Scalar(Real),
+    Register(Vec<Real>),
+}
+
+impl<'a, Real: ExprScalar> Operand<'a, Real> {
+    /// Reads a binding or literal in place, and only evaluates other
+    /// expressions into a register.
+    fn evaluate<R: AsRef<[Real]>>(
+        expr: &RealExpression<Real>,
+        bindings: &'a [R],
+        registers: &mut Registers<Real>,
+    ) -> Result<Self, EvalError> {
+        Ok(if let RealExpression::Binding(binding) = expr {
+            Self::Values(binding_values(bindings, *binding))
+        } else if let Some(value) = literal_scalar(expr) {
+            Self::Scalar(value)
+        } else {
+            Self::Register(expr.evaluate_recursive(bindings, registers)?)
+        })
+    }
+
+    fn get(&self, index: usize) -> Real {
+        match self {
+            Self::Values(values) => values[index],
+            Self::Scalar(value) => *value,
+            Self::Register(register) => register[index],
+        }
+    }
+
+    fn recycle(self, registers: &mut Registers<Real>) {
+        if let Self::Register(register) = self {
+            registers.recycle_real(register);
+        }
+    }
+}
+
+fn fold_binary_real_op<Real: ExprScalar, R: AsRef<[Real]>, A: Copy + Send + Sync>(
+    op: impl Fn(Real, Real) -> Real + Sync,
+    lhs: &RealExpression<Real>,
+    rhs: &RealExpression<Real>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+    fold: &Fold<A, Real>,
+) -> Result<(Vec<Real>, A), EvalError> {
+    let lhs = Operand::evaluate(lhs, bindings, registers)?;
+    let rhs = Operand::evaluate(rhs, bindings, registers)?;
+    let mut output = registers.allocate_real()?;
+    let folded = fill_folding(
+        &mut output,
+        registers.register_length,
+        |index| op(lhs.get(index), rhs.get(index)),
+        fold,
+    );
+    lhs.recycle(registers);
+    rhs.recycle(registers);
+    Ok((output, folded))
+}
+
+fn fold_unary_real_op<Real: ExprScalar, R: AsRef<[Real]>, A: Copy + Send + Sync>(
+    op: impl Fn(Real) -> Real + Sync,
+    only: &RealExpression<Real>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+    fold: &Fold<A, Real>,
+) -> Result<(Vec<Real>, A), EvalError> {
+    let only = Operand::evaluate(only, bindings, registers)?;
+    let mut output = registers.allocate_real()?;
+    let folded = fill_folding(
+        &mut output,
+        registers.register_length,
+        |index| op(only.get(index)),
+        fold,
+    );
+    only.recycle(registers);
+    Ok((output, folded))
+}
 
+/// Fills `output` with `value(index)` for each index, folding each element as
+/// it is written.
+fn fill_folding<Real: ExprScalar, A: Copy + Send + Sync>(
+    output: &mut Vec<Real>,
+    len: usize,
+    value: impl Fn(usize) -> Real + Sync,
+    fold: &Fold<A, Real>,
+) -> A {
     #[cfg(feature = "rayon")]
     {
-        output.par_extend(
-            lhs_values
-                .par_iter()
-                .zip(rhs_values.par_iter())
-                .map(|(lhs, rhs)| op(*lhs, *rhs)),
-        );
+        output.resize(len, Real::from_i32(0));
+        output
+            .par_iter_mut()
+            .enumerate()
+            .fold(
+                || fold.identity,
+                |acc, (index, out)| {
+                    *out = value(index);
+                    (fold.push)(acc, *out)
+                },
+            )
+            .reduce(|| fold.identity, fold.merge)
     }
     #[cfg(not(feature = "rayon"))]
     {
-        output.extend(
-            lhs_values
-                .iter()
-                .zip(rhs_values.iter())
-                .map(|(lhs, rhs)| op(*lhs, *rhs)),
-        );
+        let _ = fold.merge;
+        let mut acc = fold.identity;
+        output.extend((0..len).map(|index| {
+            let value = value(index);
+            acc = (fold.push)(acc, value);
+            value
+        }));
+        acc
     }
+}
 
-    if let Some(r) = lhs_reg {
-        registers.recycle_real(r);
+#[cfg(feature = "rayon")]
+fn split_half<T>(pool: &mut Vec<T>) -> Vec<T> {
+    pool.split_off(pool.len() / 2)
+}
+
+/// Subtrees with fewer nodes are evaluated serially, since a rayon task
+/// costs more than it saves on them.
+#[cfg(feature = "rayon")]
+const MIN_PARALLEL_SUBTREE_NODES: usize = 16;
+
+/// Evaluates `lhs` and `rhs` concurrently if both are large enough.
+///
+/// `rhs` is evaluated with forked registers, which are joined back into
+/// `registers` afterwards.
+#[cfg(feature = "rayon")]
+fn join_subtrees<Real: ExprScalar, R: AsRef<[Real]>>(
+    lhs: &RealExpression<Real>,
+    rhs: &RealExpression<Real>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+) -> Result<Option<[Vec<Real>; 2]>, EvalError> {
+    if !has_at_least_nodes(lhs, MIN_PARALLEL_SUBTREE_NODES)
+        || !has_at_least_nodes(rhs, MIN_PARALLEL_SUBTREE_NODES)
+    {
+        return Ok(None);
     }
-    if let Some(r) = rhs_reg {
-        registers.recycle_real(r);
+    if let Some(profile) = &mut registers.profile {
+        profile.concurrent_subtrees += 1;
     }
-    output
+    // `R` is not necessarily `Sync`, but the slices it refers to are.
+    let bindings: Vec<&[Real]> = bindings.iter().map(AsRef::as_ref).collect();
+    let mut rhs_registers = registers.fork();
+    let (lhs_reg, rhs_reg) = rayon::join(
+        || lhs.evaluate_recursive(&bindings, registers),
+        || rhs.evaluate_recursive(&bindings, &mut rhs_registers),
+    );
+    registers.join(rhs_registers);
+    Ok(Some([lhs_reg?, rhs_reg?]))
+}
+
+/// Counts at most `min` nodes of `expr`, so this is cheap to check at every
+/// node of a large tree.
+#[cfg(feature = "rayon")]
+fn has_at_least_nodes<Real>(expr: &RealExpression<Real>, min: usize) -> bool {
+    fn count_up_to<Real>(expr: &RealExpression<Real>, limit: usize) -> usize {
+        let children: [Option<&RealExpression<Real>>; 2] = match expr {
+            RealExpression::Add(lhs, rhs)
+            | RealExpression::Div(lhs, rhs)
+            | RealExpression::Mul(lhs, rhs)
+            | RealExpression::Pow(lhs, rhs)
+            | RealExpression::Sub(lhs, rhs)
+            | RealExpression::Func2(_, lhs, rhs) => [Some(lhs), Some(rhs)],
+            RealExpression::Neg(only)
+            | RealExpression::Func1(_, only)
+            | RealExpression::PowI(only, _) => [Some(only), None],
+            RealExpression::Case(..) => return expr.node_count().min(limit),
+            RealExpression::Literal(_)
+            | RealExpression::IntLiteral(_)
+            | RealExpression::Binding(_) => [None, None],
+        };
+        let mut count = 1;
+        for child in children.into_iter().flatten() {
+            if count >= limit {
+                break;
+            }
+            count += count_up_to(child, limit - count);
+        }
+        count
+    }
+    count_up_to(expr, min) >= min
 }
 
-fn evaluate_unary_real_op<Real: FloatExt, R: AsRef<[Real]>>(
-    op: fn(Real) -> Real,
+fn evaluate_unary_real_op<Real: ExprScalar, R: AsRef<[Real]>>(
+    op: impl Fn(Real) -> Real + Sync,
     only: &RealExpression<Real>,
     bindings: &[R],
     registers: &mut Registers<Real>,
-) -> Vec<Real> {
+) -> Result<Vec<Real>, EvalError> {
     // Before doing recursive evaluation, we check first if we already have
     // input values in our bindings. This avoids unnecessary copies.
     let mut only_reg = None;
     let only_values = if let RealExpression::Binding(binding) = only {
-        bindings[*binding].as_ref()
+        binding_values(bindings, *binding)
     } else {
-        only_reg = Some(only.evaluate_recursive(bindings, registers));
+        only_reg = Some(only.evaluate_recursive(bindings, registers)?);
         only_reg.as_ref().unwrap()
     };
     // Allocate this output register as lazily as possible.
-    let mut output = registers.allocate_real();
+    let mut output = registers.allocate_real()?;
 
     #[cfg(feature = "rayon")]
     {
@@ -341,34 +1597,149 @@ fn evaluate_unary_real_op<Real: FloatExt, R: AsRef<[Real]>>(
     if let Some(r) = only_reg {
         registers.recycle_real(r);
     }
-    output
+    Ok(output)
+}
+
+/// Evaluates `op(base, exponent)`, where `exponent` is zero if absent, and
+/// records the first element that overflowed.
+fn evaluate_checked_power<Real: ExprScalar, R: AsRef<[Real]>>(
+    op: impl Fn(Real, Real) -> Real + Sync,
+    base: &RealExpression<Real>,
+    exponent: Option<&RealExpression<Real>>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+) -> Result<Vec<Real>, EvalError> {
+    let base_values = base.evaluate_recursive(bindings, registers)?;
+    let exponent_values = match exponent {
+        Some(exponent) => Some(exponent.evaluate_recursive(bindings, registers)?),
+        None => None,
+    };
+    let exponent_at = |i: usize| exponent_values.as_ref().map_or(Real::from_i32(0), |e| e[i]);
+    let mut output = registers.allocate_real()?;
+    let is_finite = registers.float_checks().is_finite;
+    let overflowed = |(i, (&base, &output)): (usize, (&Real, &Real))| {
+        !is_finite(output) && is_finite(base) && is_finite(exponent_at(i))
+    };
+
+    #[cfg(feature = "rayon")]
+    let overflow = {
+        output.par_extend(
+            base_values
+                .par_iter()
+                .enumerate()
+                .map(|(i, &base)| op(base, exponent_at(i))),
+        );
+        base_values
+            .par_iter()
+            .zip(output.par_iter())
+            .enumerate()
+            .position_first(overflowed)
+    };
+    #[cfg(not(feature = "rayon"))]
+    let overflow = {
+        output.extend(
+            base_values
+                .iter()
+                .enumerate()
+                .map(|(i, &base)| op(base, exponent_at(i))),
+        );
+        base_values
+            .iter()
+            .zip(output.iter())
+            .enumerate()
+            .position(overflowed)
+    };
+    if registers.first_overflow.is_none() {
+        registers.first_overflow = overflow;
+    }
+
+    registers.recycle_real(base_values);
+    if let Some(exponent_values) = exponent_values {
+        registers.recycle_real(exponent_values);
+    }
+    Ok(output)
+}
+
+fn evaluate_case<Real: ExprScalar, R: AsRef<[Real]>>(
+    arms: &[CaseArm<Real>],
+    otherwise: &RealExpression<Real>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+) -> Result<Vec<Real>, EvalError> {
+    let no_strings: &[&[StringId]] = &[];
+    let mut strings = StringLookup {
+        get_literal_id: |_: &str| -> StringId { unreachable!("Case conditions compare strings") },
+        parse: None,
+    };
+
+    // Arms are applied from last to first, so each lane ends up with the
+    // value of the first arm that matches it.
+    let mut output = otherwise.evaluate_recursive(bindings, registers)?;
+    for (condition, value) in arms.iter().rev() {
+        let mask = condition.evaluate_recursive(bindings, no_strings, &mut strings, registers)?;
+        let mut value_reg = None;
+        let values = if let RealExpression::Binding(binding) = value {
+            binding_values(bindings, *binding)
+        } else {
+            value_reg = Some(value.evaluate_recursive(bindings, registers)?);
+            value_reg.as_ref().unwrap()
+        };
+
+        #[cfg(feature = "rayon")]
+        {
+            let bits_per_block = usize::BITS as usize;
+            output
+                .par_chunks_mut(bits_per_block)
+                .zip(values.par_chunks(bits_per_block))
+                .zip(mask.as_raw_slice().par_iter())
+                .for_each(|((out_chunk, value_chunk), block)| {
+                    for (i, (out, value)) in out_chunk.iter_mut().zip(value_chunk).enumerate() {
+                        if (block >> i) & 1 == 1 {
+                            *out = *value;
+                        }
+                    }
+                });
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            for i in mask.iter_ones() {
+                output[i] = values[i];
+            }
+        }
+
+        registers.recycle_bool(mask);
+        if let Some(r) = value_reg {
+            registers.recycle_real(r);
+        }
+    }
+    Ok(output)
 }
 
-fn evaluate_real_comparison<Real: FloatExt, R: AsRef<[Real]>>(
+fn evaluate_real_comparison<Real: ExprScalar, R: AsRef<[Real]>>(
     op: fn(Real, Real) -> bool,
     lhs: &RealExpression<Real>,
     rhs: &RealExpression<Real>,
     bindings: &[R],
     registers: &mut Registers<Real>,
-) -> BitVec {
+) -> Result<BitVec, EvalError> {
     // Before doing recursive evaluation, we check first if we already have
     // input values in our bindings. This avoids unnecessary copies.
     let mut lhs_reg = None;
     let lhs_values = if let RealExpression::Binding(binding) = lhs {
-        bindings[*binding].as_ref()
+        binding_values(bindings, *binding)
     } else {
-        lhs_reg = Some(lhs.evaluate_recursive(bindings, registers));
+        lhs_reg = Some(lhs.evaluate_recursive(bindings, registers)?);
         lhs_reg.as_ref().unwrap()
     };
     let mut rhs_reg = None;
     let rhs_values = if let RealExpression::Binding(binding) = rhs {
-        bindings[*binding].as_ref()
+        binding_values(bindings, *binding)
     } else {
-        rhs_reg = Some(rhs.evaluate_recursive(bindings, registers));
+        rhs_reg = Some(rhs.evaluate_recursive(bindings, registers)?);
         rhs_reg.as_ref().unwrap()
     };
     // Allocate this output register as lazily as possible.
-    let mut output = registers.allocate_bool();
+    let mut output = registers.allocate_bool()?;
 
     #[cfg(feature = "rayon")]
     {
@@ -391,7 +1762,76 @@ fn evaluate_real_comparison<Real: FloatExt, R: AsRef<[Real]>>(
     if let Some(r) = rhs_reg {
         registers.recycle_real(r);
     }
-    output
+    Ok(output)
+}
+
+fn evaluate_between<Real: ExprScalar, R: AsRef<[Real]>>(
+    value: &RealExpression<Real>,
+    lo: &RealExpression<Real>,
+    hi: &RealExpression<Real>,
+    bindings: &[R],
+    registers: &mut Registers<Real>,
+) -> Result<BitVec, EvalError> {
+    // Each operand, including the value, is read or evaluated exactly once.
+    let mut value_reg = None;
+    let value_values = if let RealExpression::Binding(binding) = value {
+        binding_values(bindings, *binding)
+    } else {
+        value_reg = Some(value.evaluate_recursive(bindings, registers)?);
+        value_reg.as_ref().unwrap()
+    };
+    let mut lo_reg = None;
+    let lo_values = if let RealExpression::Binding(binding) = lo {
+        binding_values(bindings, *binding)
+    } else {
+        lo_reg = Some(lo.evaluate_recursive(bindings, registers)?);
+        lo_reg.as_ref().unwrap()
+    };
+    let mut hi_reg = None;
+    let hi_values = if let RealExpression::Binding(binding) = hi {
+        binding_values(bindings, *binding)
+    } else {
+        hi_reg = Some(hi.evaluate_recursive(bindings, registers)?);
+        hi_reg.as_ref().unwrap()
+    };
+    // Allocate this output register as lazily as possible.
+    let mut output = registers.allocate_bool()?;
+
+    #[cfg(feature = "rayon")]
+    {
+        output.resize(registers.register_length, Default::default());
+        parallel_comparison(
+            |lo, value| lo <= value,
+            lo_values,
+            value_values,
+            &mut output,
+        );
+        let mut upper = registers.allocate_bool()?;
+        upper.resize(registers.register_length, Default::default());
+        parallel_comparison(|value, hi| value <= hi, value_values, hi_values, &mut upper);
+        output
+            .as_raw_mut_slice()
+            .par_iter_mut()
+            .zip(upper.as_raw_slice().par_iter())
+            .for_each(|(out, upper)| {
+                *out &= upper;
+            });
+        registers.recycle_bool(upper);
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        output.extend(
+            value_values
+                .iter()
+                .zip(lo_values.iter().zip(hi_values.iter()))
+                .map(|(value, (lo, hi))| lo <= value && value <= hi),
+        );
+    }
+
+    for r in [value_reg, lo_reg, hi_reg].into_iter().flatten() {
+        registers.recycle_real(r);
+    }
+    Ok(output)
 }
 
 fn evaluate_string_comparison<Real, S: AsRef<[StringId]>>(
@@ -401,31 +1841,31 @@ fn evaluate_string_comparison<Real, S: AsRef<[StringId]>>(
     bindings: &[S],
     mut get_string_literal_id: impl FnMut(&str) -> StringId,
     registers: &mut Registers<Real>,
-) -> BitVec {
+) -> Result<BitVec, EvalError> {
     let mut lhs_reg = None;
     let lhs_values = match lhs {
-        StringExpression::Binding(binding) => bindings[*binding].as_ref(),
+        StringExpression::Binding(binding) => binding_values(bindings, *binding),
         StringExpression::Literal(literal_value) => {
-            let mut reg = registers.allocate_string();
+            let mut reg = registers.allocate_string()?;
             let literal_id = get_string_literal_id(literal_value);
-            reg.extend(std::iter::repeat(literal_id).take(registers.register_length));
+            reg.resize(registers.register_length, literal_id);
             lhs_reg = Some(reg);
             lhs_reg.as_ref().unwrap()
         }
     };
     let mut rhs_reg = None;
     let rhs_values = match rhs {
-        StringExpression::Binding(binding) => bindings[*binding].as_ref(),
+        StringExpression::Binding(binding) => binding_values(bindings, *binding),
         StringExpression::Literal(literal_value) => {
-            let mut reg = registers.allocate_string();
+            let mut reg = registers.allocate_string()?;
             let literal_id = get_string_literal_id(literal_value);
-            reg.extend(std::iter::repeat(literal_id).take(registers.register_length));
+            reg.resize(registers.register_length, literal_id);
             rhs_reg = Some(reg);
             rhs_reg.as_ref().unwrap()
         }
     };
     // Allocate this output register as lazily as possible.
-    let mut output = registers.allocate_bool();
+    let mut output = registers.allocate_bool()?;
 
     #[cfg(feature = "rayon")]
     {
@@ -448,7 +1888,47 @@ fn evaluate_string_comparison<Real, S: AsRef<[StringId]>>(
     if let Some(r) = rhs_reg {
         registers.recycle_string(r);
     }
-    output
+    Ok(output)
+}
+
+fn evaluate_coerced_comparison<Real: ExprScalar, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+    op: fn(Option<Real>, Real) -> bool,
+    lhs: &StringExpression,
+    rhs: &RealExpression<Real>,
+    real_bindings: &[R],
+    string_bindings: &[S],
+    parse_string: Option<&dyn Fn(StringId) -> Option<Real>>,
+    registers: &mut Registers<Real>,
+) -> Result<BitVec, EvalError> {
+    let parse_string =
+        parse_string.expect("Comparing strings to reals requires `evaluate_coercing`");
+    let mut rhs_reg = None;
+    let rhs_values = if let RealExpression::Binding(binding) = rhs {
+        binding_values(real_bindings, *binding)
+    } else {
+        rhs_reg = Some(rhs.evaluate_recursive(real_bindings, registers)?);
+        rhs_reg.as_ref().unwrap()
+    };
+    // Allocate this output register as lazily as possible.
+    let mut output = registers.allocate_bool()?;
+
+    match lhs {
+        StringExpression::Binding(binding) => output.extend(
+            binding_values(string_bindings, *binding)
+                .iter()
+                .zip(rhs_values)
+                .map(|(lhs, rhs)| op(parse_string(*lhs), *rhs)),
+        ),
+        StringExpression::Literal(literal_value) => {
+            let lhs = literal_value.trim().parse().ok();
+            output.extend(rhs_values.iter().map(|rhs| op(lhs, *rhs)));
+        }
+    }
+
+    if let Some(r) = rhs_reg {
+        registers.recycle_real(r);
+    }
+    Ok(output)
 }
 
 #[cfg(feature = "rayon")]
@@ -484,56 +1964,65 @@ fn parallel_comparison<T: Copy + Send + Sync>(
         });
 }
 
-fn evaluate_binary_logic<Real: FloatExt, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
-    op: impl Fn(&BitVec, &BitVec, &mut BitVec),
+fn evaluate_binary_logic<Real: ExprScalar, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+    op: impl Fn(&mut BitVec, &BitVec),
     lhs: &BoolExpression<Real>,
     rhs: &BoolExpression<Real>,
     real_bindings: &[R],
     string_bindings: &[S],
-    get_string_literal_id: &mut impl FnMut(&str) -> StringId,
+    strings: &mut StringLookup<impl FnMut(&str) -> StringId, Real>,
     registers: &mut Registers<Real>,
-) -> BitVec {
-    let lhs_values = lhs.evaluate_recursive(
-        real_bindings,
-        string_bindings,
-        get_string_literal_id,
-        registers,
-    );
-    let rhs_values = rhs.evaluate_recursive(
-        real_bindings,
-        string_bindings,
-        get_string_literal_id,
-        registers,
-    );
-
-    // Allocate this output register as lazily as possible.
-    let mut output = registers.allocate_bool();
+) -> Result<BitVec, EvalError> {
+    let mut lhs_values =
+        lhs.evaluate_recursive(real_bindings, string_bindings, strings, registers)?;
+    let rhs_values = rhs.evaluate_recursive(real_bindings, string_bindings, strings, registers)?;
 
-    op(&lhs_values, &rhs_values, &mut output);
+    // Accumulate into the left operand's register rather than allocating an
+    // output register.
+    op(&mut lhs_values, &rhs_values);
 
-    registers.recycle_bool(lhs_values);
     registers.recycle_bool(rhs_values);
-    output
+    Ok(lhs_values)
 }
 
-fn evaluate_unary_logic<Real: FloatExt, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
+fn evaluate_unary_logic<Real: ExprScalar, R: AsRef<[Real]>, S: AsRef<[StringId]>>(
     op: fn(&mut BitVec),
     only: &BoolExpression<Real>,
     real_bindings: &[R],
     string_bindings: &[S],
-    get_string_literal_id: &mut impl FnMut(&str) -> StringId,
+    strings: &mut StringLookup<impl FnMut(&str) -> StringId, Real>,
     registers: &mut Registers<Real>,
-) -> BitVec {
-    let mut only_values = only.evaluate_recursive(
-        real_bindings,
-        string_bindings,
-        get_string_literal_id,
-        registers,
-    );
+) -> Result<BitVec, EvalError> {
+    let mut only_values =
+        only.evaluate_recursive(real_bindings, string_bindings, strings, registers)?;
 
     op(&mut only_values);
 
-    only_values
+    Ok(only_values)
+}
+
+/// Resolves strings while evaluating a [`BoolExpression`].
+struct StringLookup<'a, F, Real> {
+    get_literal_id: F,
+    /// Parses an interned string as a real, if string-to-real comparisons
+    /// can be evaluated.
+    parse: Option<&'a dyn Fn(StringId) -> Option<Real>>,
+}
+
+/// What to do when a real-valued node produces an infinite or NaN element.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Leave non-finite values as they are.
+    #[default]
+    Propagate,
+    /// Clamp infinities to `Real::max_value()` or `Real::min_value()`.
+    ///
+    /// NaN has no sign to saturate towards, so it is left unchanged.
+    Saturate,
+    /// Record the first offending element of the first offending node, to be
+    /// returned as an
+    /// [`EvalError::NonFinite`] by e.g. [`RealExpression::try_evaluate`].
+    Report,
 }
 
 /// Scratch space for calculations. Can be reused across evaluations with the
@@ -547,6 +2036,18 @@ pub struct Registers<Real> {
     bool_registers: Vec<BitVec>,
     string_registers: Vec<Vec<StringId>>,
     register_length: usize,
+    profile: Option<Profile>,
+    trace: Option<Trace<Real>>,
+    non_finite_policy: NonFinitePolicy,
+    first_non_finite: Option<usize>,
+    check_power_overflow: bool,
+    first_overflow: Option<usize>,
+    float_checks: Option<FloatChecks<Real>>,
+    budget: Option<usize>,
+    /// The bytes of registers in use by the current fallible evaluation,
+    /// shared with forked registers. Only counted if there is a budget.
+    bytes_in_use: Option<Arc<AtomicUsize>>,
+    functions: Vec<CustomFunction<Real>>,
 }
 
 impl<Real> Registers<Real> {
@@ -557,6 +2058,138 @@ impl<Real> Registers<Real> {
             bool_registers: vec![],
             string_registers: vec![],
             register_length,
+            profile: None,
+            trace: None,
+            non_finite_policy: NonFinitePolicy::default(),
+            first_non_finite: None,
+            check_power_overflow: false,
+            first_overflow: None,
+            float_checks: None,
+            budget: None,
+            bytes_in_use: None,
+            functions: vec![],
+        }
+    }
+
+    /// Like [`Self::new`], but evaluation with e.g.
+    /// [`RealExpression::try_evaluate`] fails with
+    /// [`EvalError::MemoryBudgetExceeded`] if it would use more than
+    /// `max_bytes` of registers at once.
+    ///
+    /// Every register is counted as it is taken from the pool or allocated, so
+    /// the evaluation fails instead of making the allocation that would exceed
+    /// the budget. Methods that cannot fail ignore the budget.
+    pub fn with_budget(register_length: usize, max_bytes: usize) -> Self {
+        let mut registers = Self::new(register_length);
+        registers.budget = Some(max_bytes);
+        registers
+    }
+
+    /// Like [`Self::new`], but pre-allocates `num_registers` registers of
+    /// each type, so the first evaluation does not have to allocate.
+    ///
+    /// Size `num_registers` with e.g.
+    /// [`RealExpression::max_register_pressure`]. Pre-allocated registers are
+    /// not counted by [`Self::num_allocations`].
+    pub fn with_capacity(register_length: usize, num_registers: usize) -> Self {
+        let mut registers = Self::new(register_length);
+        registers.real_registers = (0..num_registers)
+            .map(|_| Vec::with_capacity(register_length))
+            .collect();
+        registers.bool_registers = (0..num_registers)
+            .map(|_| BitVec::with_capacity(register_length))
+            .collect();
+        registers.string_registers = (0..num_registers)
+            .map(|_| Vec::with_capacity(register_length))
+            .collect();
+        registers
+    }
+
+    /// Registers without any free registers but with the same configuration
+    /// as `self`. Must be passed to [`Self::join`] afterwards.
+    fn child(&self, register_length: usize) -> Self {
+        let mut child = Self::new(register_length);
+        child.profile = self.profile.as_ref().map(|_| Profile::default());
+        child.trace = self.trace.as_ref().map(|_| Trace { steps: Vec::new() });
+        child.non_finite_policy = self.non_finite_policy;
+        child.check_power_overflow = self.check_power_overflow;
+        child.float_checks = self.float_checks;
+        child.budget = self.budget;
+        child.bytes_in_use = self.bytes_in_use.clone();
+        child.functions = self.functions.clone();
+        child
+    }
+
+    /// Like [`Self::child`], but with half of the free registers of `self`,
+    /// for evaluating a subtree concurrently.
+    #[cfg(feature = "rayon")]
+    fn fork(&mut self) -> Self {
+        let mut forked = self.child(self.register_length);
+        forked.real_registers = split_half(&mut self.real_registers);
+        forked.bool_registers = split_half(&mut self.bool_registers);
+        forked.string_registers = split_half(&mut self.string_registers);
+        forked
+    }
+
+    /// Takes over the registers and statistics of `forked`, which was created
+    /// by [`Self::child`] or [`Self::fork`].
+    fn join(&mut self, forked: Self) {
+        self.num_allocations += forked.num_allocations;
+        self.real_registers.extend(forked.real_registers);
+        self.bool_registers.extend(forked.bool_registers);
+        self.string_registers.extend(forked.string_registers);
+        if let (Some(profile), Some(forked)) = (&mut self.profile, forked.profile) {
+            profile.merge(forked);
+        }
+        if let (Some(trace), Some(forked)) = (&mut self.trace, forked.trace) {
+            trace.steps.extend(forked.steps);
+        }
+        self.first_non_finite = self.first_non_finite.or(forked.first_non_finite);
+        self.first_overflow = self.first_overflow.or(forked.first_overflow);
+    }
+
+    /// Provide the [`FunctionTable`] that custom [`Func2`] calls were parsed
+    /// with.
+    pub fn set_functions(&mut self, functions: &FunctionTable<Real>) {
+        self.functions = functions.functions().collect();
+    }
+
+    /// Start collecting a [`Profile`] of every subsequent evaluation.
+    ///
+    /// Any profile collected so far is reset.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// Stop profiling and return the profile collected since
+    /// [`Self::enable_profiling`], if it was enabled.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Start recording a [`Trace`] of every subsequent evaluation.
+    ///
+    /// Any trace recorded so far is reset.
+    pub fn enable_tracing(&mut self) {
+        self.trace = Some(Trace { steps: Vec::new() });
+    }
+
+    /// Stop tracing and return the trace recorded since
+    /// [`Self::enable_tracing`], if it was enabled.
+    pub fn take_trace(&mut self) -> Option<Trace<Real>> {
+        self.trace.take()
+    }
+
+    fn record_node(&mut self, node_name: &'static str) {
+        if let Some(profile) = &mut self.profile {
+            profile.record_node(node_name, self.register_length);
+        }
+    }
+
+    fn record_allocation(&mut self) {
+        self.num_allocations += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.allocations += 1;
         }
     }
 
@@ -577,43 +2210,308 @@ impl<Real> Registers<Real> {
             .retain(|reg| reg.capacity() >= self.register_length);
     }
 
-    fn recycle_real(&mut self, mut used: Vec<Real>) {
+    pub(crate) fn recycle_real(&mut self, mut used: Vec<Real>) {
         used.clear();
+        self.release_bytes(self.real_register_bytes());
         self.real_registers.push(used);
     }
 
-    fn recycle_bool(&mut self, mut used: BitVec) {
+    /// Adds a buffer that was not allocated by `self` to the free registers.
+    fn donate_real(&mut self, mut donated: Vec<Real>) {
+        donated.clear();
+        self.real_registers.push(donated);
+    }
+
+    pub(crate) fn recycle_bool(&mut self, mut used: BitVec) {
         used.clear();
+        self.release_bytes(self.bool_register_bytes());
         self.bool_registers.push(used);
     }
 
     fn recycle_string(&mut self, mut used: Vec<StringId>) {
         used.clear();
+        self.release_bytes(self.string_register_bytes());
         self.string_registers.push(used);
     }
 
-    fn allocate_real(&mut self) -> Vec<Real> {
-        self.real_registers.pop().unwrap_or_else(|| {
-            self.num_allocations += 1;
+    pub(crate) fn allocate_real(&mut self) -> Result<Vec<Real>, EvalError> {
+        self.use_bytes(self.real_register_bytes())?;
+        Ok(self.real_registers.pop().unwrap_or_else(|| {
+            self.record_allocation();
             Vec::with_capacity(self.register_length)
-        })
+        }))
     }
 
-    fn allocate_bool(&mut self) -> BitVec {
-        self.bool_registers.pop().unwrap_or_else(|| {
-            self.num_allocations += 1;
+    fn allocate_bool(&mut self) -> Result<BitVec, EvalError> {
+        self.use_bytes(self.bool_register_bytes())?;
+        Ok(self.bool_registers.pop().unwrap_or_else(|| {
+            self.record_allocation();
             BitVec::with_capacity(self.register_length)
-        })
+        }))
     }
 
-    fn allocate_string(&mut self) -> Vec<StringId> {
-        self.string_registers.pop().unwrap_or_else(|| {
-            self.num_allocations += 1;
+    fn allocate_string(&mut self) -> Result<Vec<StringId>, EvalError> {
+        self.use_bytes(self.string_register_bytes())?;
+        Ok(self.string_registers.pop().unwrap_or_else(|| {
+            self.record_allocation();
             Vec::with_capacity(self.register_length)
-        })
+        }))
+    }
+
+    fn real_register_bytes(&self) -> usize {
+        self.register_length * std::mem::size_of::<Real>()
+    }
+
+    fn bool_register_bytes(&self) -> usize {
+        let bits_per_block = usize::BITS as usize;
+        self.register_length.div_ceil(bits_per_block) * std::mem::size_of::<usize>()
+    }
+
+    fn string_register_bytes(&self) -> usize {
+        self.register_length * std::mem::size_of::<StringId>()
+    }
+
+    /// Counts `bytes` more of registers in use, failing without counting
+    /// them if that would exceed the budget.
+    fn use_bytes(&self, bytes: usize) -> Result<(), EvalError> {
+        let (Some(budget), Some(in_use)) = (self.budget, &self.bytes_in_use) else {
+            return Ok(());
+        };
+        in_use
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_use| {
+                (in_use + bytes <= budget).then_some(in_use + bytes)
+            })
+            .map(drop)
+            .map_err(|in_use| EvalError::MemoryBudgetExceeded {
+                needed: in_use + bytes,
+                budget,
+            })
+    }
+
+    fn release_bytes(&self, bytes: usize) {
+        if let Some(in_use) = &self.bytes_in_use {
+            let previous = in_use.fetch_sub(bytes, Ordering::Relaxed);
+            debug_assert!(
+                previous >= bytes,
+                "Released a register that was not counted"
+            );
+        }
+    }
+
+    /// Starts counting the registers in use for a fallible evaluation, until
+    /// [`Self::end_budget`].
+    fn start_budget(&mut self) {
+        self.bytes_in_use = self.budget.map(|_| Arc::default());
+    }
+
+    /// Stops counting the registers in use. Registers still held by the
+    /// evaluation, like its output, are not released.
+    fn end_budget(&mut self) {
+        self.bytes_in_use = None;
     }
 
     pub fn num_allocations(&self) -> usize {
         self.num_allocations
     }
 }
+
+/// Free registers shared between [`Registers`], which may have different real
+/// types, e.g. to serve both `f32` and `f64` evaluations from one pool.
+///
+/// Only bool and string registers are shared between real types, since they do
+/// not depend on the real type. Real registers are pooled separately for each
+/// real type and never share memory: evaluation hands out real registers as
+/// `Vec<Real>`, which must be freed with the alignment of `Real`, so a buffer
+/// allocated for `f32` cannot be handed out to an `f64` evaluation or vice
+/// versa.
+pub struct RegisterPool {
+    register_length: usize,
+    /// A `Vec<Vec<Real>>` of free registers for each real type.
+    real_registers: Vec<Box<dyn Any + Send>>,
+    bool_registers: Vec<BitVec>,
+    string_registers: Vec<Vec<StringId>>,
+}
+
+impl RegisterPool {
+    pub fn new(register_length: usize) -> Self {
+        Self {
+            register_length,
+            real_registers: vec![],
+            bool_registers: vec![],
+            string_registers: vec![],
+        }
+    }
+
+    /// Registers that start out with up to `num_registers` of the pool's free
+    /// registers of each type that `Real` can use, leaving the rest for other
+    /// [`Registers`] taken at the same time. Pass them to [`Self::recycle`]
+    /// when done.
+    ///
+    /// Size `num_registers` with e.g.
+    /// [`RealExpression::max_register_pressure`].
+    pub fn take<Real: ExprScalar + 'static>(&mut self, num_registers: usize) -> Registers<Real> {
+        let mut registers = Registers::new(self.register_length);
+        registers.real_registers = split_off_last(self.real_pool(), num_registers);
+        registers.bool_registers = split_off_last(&mut self.bool_registers, num_registers);
+        registers.string_registers = split_off_last(&mut self.string_registers, num_registers);
+        registers
+    }
+
+    /// Returns the free registers of `registers` to the pool.
+    ///
+    /// Registers too small for the pool's register length are dropped.
+    pub fn recycle<Real: ExprScalar + 'static>(&mut self, mut registers: Registers<Real>) {
+        registers.set_register_length(self.register_length);
+        self.real_pool().append(&mut registers.real_registers);
+        self.bool_registers.append(&mut registers.bool_registers);
+        self.string_registers
+            .append(&mut registers.string_registers);
+    }
+
+    fn real_pool<Real: ExprScalar + 'static>(&mut self) -> &mut Vec<Vec<Real>> {
+        let index = match self
+            .real_registers
+            .iter()
+            .position(|pool| pool.is::<Vec<Vec<Real>>>())
+        {
+            Some(index) => index,
+            None => {
+                self.real_registers.push(Box::<Vec<Vec<Real>>>::default());
+                self.real_registers.len() - 1
+            }
+        };
+        self.real_registers[index].downcast_mut().unwrap()
+    }
+}
+
+/// Removes and returns up to the last `n` elements of `pool`.
+fn split_off_last<T>(pool: &mut Vec<T>, n: usize) -> Vec<T> {
+    pool.split_off(pool.len().saturating_sub(n))
+}
+
+impl<Real: FloatExt> Registers<Real> {
+    /// Choose how non-finite results of real-valued nodes are handled.
+    ///
+    /// Every node's output is checked, so any policy other than
+    /// [`NonFinitePolicy::Propagate`] costs an extra pass over each register.
+    pub fn set_non_finite_policy(&mut self, policy: NonFinitePolicy) {
+        self.non_finite_policy = policy;
+        self.float_checks = Some(FloatChecks::new());
+    }
+
+    /// Choose whether [`Pow`](RealExpression::Pow) and
+    /// [`PowI`](RealExpression::PowI) nodes record the first element that
+    /// overflowed, i.e. is non-finite although the operands were finite, to be
+    /// returned as an [`EvalError::Overflow`] by e.g.
+    /// [`RealExpression::try_evaluate`].
+    ///
+    /// Unlike [`NonFinitePolicy::Report`], only power nodes are checked, but
+    /// their operands are always copied into registers.
+    pub fn set_check_power_overflow(&mut self, check: bool) {
+        self.check_power_overflow = check;
+        self.float_checks = Some(FloatChecks::new());
+    }
+}
+
+impl<Real: ExprScalar> Registers<Real> {
+    fn func1(&self, func: Func1) -> fn(Real) -> Real {
+        match Real::func1(func) {
+            Some(function) => function,
+            None => panic!("`{}` is not supported by the real type", func.name()),
+        }
+    }
+
+    fn func2(&self, func: Func2) -> fn(Real, Real) -> Real {
+        match (func, Real::func2(func)) {
+            (_, Some(function)) => function,
+            (Func2::Custom(id), None) => match self.functions.get(id) {
+                Some(function) => *function,
+                None => panic!("Custom function {id} is not in the registers' function table"),
+            },
+            (func, None) => panic!("`{}` is not supported by the real type", func.name()),
+        }
+    }
+
+    fn float_checks(&self) -> FloatChecks<Real> {
+        self.float_checks
+            .expect("Float checks are enabled with their configuration")
+    }
+
+    fn record_values(&mut self, node_name: &'static str, output: &[Real]) {
+        if let Some(trace) = &mut self.trace {
+            trace.steps.push(TraceStep {
+                label: node_name,
+                values: output[..output.len().min(TRACE_VALUES)].to_vec(),
+            });
+        }
+    }
+
+    fn check_non_finite(&mut self, output: &mut [Real]) {
+        match self.non_finite_policy {
+            NonFinitePolicy::Propagate => {}
+            NonFinitePolicy::Saturate => {
+                let saturate = self.float_checks().saturate;
+                #[cfg(feature = "rayon")]
+                output.par_iter_mut().for_each(|x| *x = saturate(*x));
+                #[cfg(not(feature = "rayon"))]
+                output.iter_mut().for_each(|x| *x = saturate(*x));
+            }
+            NonFinitePolicy::Report => {
+                if self.first_non_finite.is_some() {
+                    return;
+                }
+                let is_finite = self.float_checks().is_finite;
+                #[cfg(feature = "rayon")]
+                let index = output.par_iter().position_first(|x| !is_finite(*x));
+                #[cfg(not(feature = "rayon"))]
+                let index = output.iter().position(|x| !is_finite(*x));
+                self.first_non_finite = index;
+            }
+        }
+    }
+}
+
+/// The checks of [`NonFinitePolicy`] and
+/// [`Registers::set_check_power_overflow`], which need a [`FloatExt`] type, so
+/// they are set by those methods.
+struct FloatChecks<Real> {
+    is_finite: fn(Real) -> bool,
+    /// Clamps infinities to the finite value of the same sign with the
+    /// largest magnitude.
+    saturate: fn(Real) -> Real,
+}
+
+impl<Real> Clone for FloatChecks<Real> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Real> Copy for FloatChecks<Real> {}
+
+impl<Real: FloatExt> FloatChecks<Real> {
+    fn new() -> Self {
+        Self {
+            is_finite: Real::is_finite,
+            saturate: |x| {
+                if x == Real::infinity() {
+                    Real::max_value()
+                } else if x == Real::neg_infinity() {
+                    Real::min_value()
+                } else {
+                    x
+                }
+            },
+        }
+    }
+}
+
+/// The value of a literal operand, which is broadcast rather than
+/// materialized.
+fn literal_scalar<Real: ExprScalar>(expr: &RealExpression<Real>) -> Option<Real> {
+    match expr {
+        RealExpression::Literal(value) => Some(*value),
+        RealExpression::IntLiteral(value) => Some(Real::from_i32(*value)),
+        _ => None,
+    }
+}
@@ -1,17 +1,31 @@
+use crate::ValidationError;
+use num_traits::Float;
+use std::fmt;
+
 /// Top-level parseable calculation.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Expression<Real> {
     Boolean(BoolExpression<Real>),
     Real(RealExpression<Real>),
     String(StringExpression),
+    Tuple(TupleExpression<Real>),
+}
+
+/// Multiple real-valued expressions evaluated together, e.g. `(x + y, x - y)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TupleExpression<Real> {
+    pub elements: Vec<RealExpression<Real>>,
 }
 
 /// A `bool`-valued expression.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BoolExpression<Real> {
     // Binary logic.
     And(Box<BoolExpression<Real>>, Box<BoolExpression<Real>>),
     Or(Box<BoolExpression<Real>>, Box<BoolExpression<Real>>),
+    Xor(Box<BoolExpression<Real>>, Box<BoolExpression<Real>>),
+    /// `lhs -> rhs`, equivalent to `!lhs || rhs`.
+    Implies(Box<BoolExpression<Real>>, Box<BoolExpression<Real>>),
 
     // Unary logic.
     Not(Box<BoolExpression<Real>>),
@@ -23,14 +37,32 @@ pub enum BoolExpression<Real> {
     Less(Box<RealExpression<Real>>, Box<RealExpression<Real>>),
     LessEqual(Box<RealExpression<Real>>, Box<RealExpression<Real>>),
     NotEqual(Box<RealExpression<Real>>, Box<RealExpression<Real>>),
+    /// `between(x, lo, hi)`, equivalent to `lo <= x && x <= hi` except that
+    /// `x` is only evaluated once. Always false where `lo > hi`.
+    Between(
+        Box<RealExpression<Real>>,
+        Box<RealExpression<Real>>,
+        Box<RealExpression<Real>>,
+    ),
 
     // String comparisons.
     StrEqual(StringExpression, StringExpression),
     StrNotEqual(StringExpression, StringExpression),
+
+    // String-to-real comparisons (see `Parser::with_string_coercion`). A
+    // string that does not parse as a real is not equal to any real.
+    CoercedEqual(StringExpression, Box<RealExpression<Real>>),
+    CoercedNotEqual(StringExpression, Box<RealExpression<Real>>),
+
+    // Constant.
+    Literal(bool),
 }
 
+/// A `when condition then value` arm of [`RealExpression::Case`].
+pub type CaseArm<Real> = (BoolExpression<Real>, RealExpression<Real>);
+
 /// An `f64`-valued expression.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum RealExpression<Real> {
     // Binary real ops.
     Add(Box<RealExpression<Real>>, Box<RealExpression<Real>>),
@@ -41,15 +73,246 @@ pub enum RealExpression<Real> {
 
     // Unary real ops.
     Neg(Box<RealExpression<Real>>),
+    Func1(Func1, Box<RealExpression<Real>>),
+    /// Raises the operand to a constant integer power, e.g. `powi(x, 3)`.
+    PowI(Box<RealExpression<Real>>, i32),
 
-    // Constant.
+    // Binary real functions.
+    Func2(Func2, Box<RealExpression<Real>>, Box<RealExpression<Real>>),
+
+    /// `when c1 then v1 when c2 then v2 else e`, which is the value of the
+    /// first arm whose condition holds, or `e` if none do.
+    ///
+    /// Conditions cannot compare strings.
+    Case(Vec<CaseArm<Real>>, Box<RealExpression<Real>>),
+
+    // Constants.
     Literal(Real),
+    /// A literal written as an integer, e.g. `2` rather than `2.0`, that fits
+    /// in an `i32`. Evaluates like the equivalent [`Literal`](Self::Literal).
+    IntLiteral(i32),
 
     // Input variable.
     Binding(BindingId),
 }
 
+/// A built-in real function of one argument, called like `sqrt(x)` or
+/// `x.sqrt()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Func1 {
+    Abs,
+    Ceil,
+    Cos,
+    Exp,
+    /// `2^x`, which is usually faster and more accurate than `powf(2, x)`.
+    Exp2,
+    Floor,
+    Ln,
+    /// `1 / x`.
+    Recip,
+    /// `max(x, 0)`.
+    Relu,
+    Round,
+    /// The logistic function `1 / (1 + exp(-x))`.
+    Sigmoid,
+    Sin,
+    /// `ln(1 + exp(x))`, computed without overflow for large `x`.
+    Softplus,
+    Sqrt,
+    Tan,
+    Tanh,
+}
+
+impl Func1 {
+    /// Looks up a function by the name used to call it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Self::Abs,
+            "ceil" => Self::Ceil,
+            "cos" => Self::Cos,
+            "exp" => Self::Exp,
+            "exp2" => Self::Exp2,
+            "floor" => Self::Floor,
+            "ln" => Self::Ln,
+            "recip" => Self::Recip,
+            "relu" => Self::Relu,
+            "round" => Self::Round,
+            "sigmoid" => Self::Sigmoid,
+            "sin" => Self::Sin,
+            "softplus" => Self::Softplus,
+            "sqrt" => Self::Sqrt,
+            "tan" => Self::Tan,
+            "tanh" => Self::Tanh,
+            _ => return None,
+        })
+    }
+
+    /// The name used to call this function.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Abs => "abs",
+            Self::Ceil => "ceil",
+            Self::Cos => "cos",
+            Self::Exp => "exp",
+            Self::Exp2 => "exp2",
+            Self::Floor => "floor",
+            Self::Ln => "ln",
+            Self::Recip => "recip",
+            Self::Relu => "relu",
+            Self::Round => "round",
+            Self::Sigmoid => "sigmoid",
+            Self::Sin => "sin",
+            Self::Softplus => "softplus",
+            Self::Sqrt => "sqrt",
+            Self::Tan => "tan",
+            Self::Tanh => "tanh",
+        }
+    }
+
+    pub(crate) fn function<Real: Float>(self) -> fn(Real) -> Real {
+        match self {
+            Self::Abs => Real::abs,
+            Self::Ceil => Real::ceil,
+            Self::Cos => Real::cos,
+            Self::Exp => Real::exp,
+            Self::Exp2 => Real::exp2,
+            Self::Floor => Real::floor,
+            Self::Ln => Real::ln,
+            Self::Recip => Real::recip,
+            Self::Relu => |x: Real| x.max(Real::zero()),
+            Self::Round => Real::round,
+            Self::Sigmoid => sigmoid,
+            Self::Sin => Real::sin,
+            Self::Softplus => softplus,
+            Self::Sqrt => Real::sqrt,
+            Self::Tan => Real::tan,
+            Self::Tanh => Real::tanh,
+        }
+    }
+}
+
+/// Only exponentiates non-positive values, so it saturates to 0 or 1
+/// instead of producing NaN from `inf / inf`.
+fn sigmoid<Real: Float>(x: Real) -> Real {
+    if x >= Real::zero() {
+        (Real::one() + (-x).exp()).recip()
+    } else {
+        let e = x.exp();
+        e / (Real::one() + e)
+    }
+}
+
+/// Uses `ln(1 + exp(x)) = max(x, 0) + ln(1 + exp(-|x|))`, which never
+/// exponentiates a positive value.
+fn softplus<Real: Float>(x: Real) -> Real {
+    x.max(Real::zero()) + (-x.abs()).exp().ln_1p()
+}
+
+/// A built-in real function of two arguments, called like `max(x, y)` or
+/// `x.max(y)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Func2 {
+    /// The first argument, unless it is NaN, in which case the second.
+    Coalesce,
+    Max,
+    Min,
+    /// A function registered in a [`FunctionTable`], which must be given to
+    /// [`Registers::set_functions`](crate::Registers::set_functions) before
+    /// evaluation.
+    Custom(CustomFunctionId),
+}
+
+impl Func2 {
+    /// Looks up a function by the name used to call it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "coalesce" => Self::Coalesce,
+            "max" => Self::Max,
+            "min" => Self::Min,
+            _ => return None,
+        })
+    }
+
+    /// The name used to call this function, or `"custom"` for functions
+    /// named by a [`FunctionTable`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Coalesce => "coalesce",
+            Self::Max => "max",
+            Self::Min => "min",
+            Self::Custom(_) => "custom",
+        }
+    }
+
+    /// The built-in implementation, or `None` for custom functions.
+    pub(crate) fn function<Real: Float>(self) -> Option<fn(Real, Real) -> Real> {
+        Some(match self {
+            Self::Coalesce => |x, default| if x.is_nan() { default } else { x },
+            Self::Max => Real::max,
+            Self::Min => Real::min,
+            Self::Custom(_) => return None,
+        })
+    }
+}
+
+/// Index of a function in a [`FunctionTable`].
+pub type CustomFunctionId = usize;
+
+/// Implementation of a function registered in a [`FunctionTable`].
+pub type CustomFunction<Real> = fn(Real, Real) -> Real;
+
+/// User-defined real functions of two arguments, called like built-in
+/// [`Func2`]s.
+///
+/// Expressions only store the [`CustomFunctionId`] of each function, so the
+/// same table used for parsing must be used for evaluation.
 #[derive(Clone, Debug)]
+pub struct FunctionTable<Real> {
+    functions: Vec<(String, CustomFunction<Real>)>,
+}
+
+impl<Real> Default for FunctionTable<Real> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Real> FunctionTable<Real> {
+    pub fn new() -> Self {
+        Self {
+            functions: Vec::new(),
+        }
+    }
+
+    /// Makes `function` callable as `name`, replacing any function previously
+    /// registered with that name.
+    ///
+    /// Built-in functions take precedence over registered ones.
+    pub fn register(&mut self, name: &str, function: CustomFunction<Real>) -> CustomFunctionId {
+        if let Some(id) = self.find(name) {
+            self.functions[id].1 = function;
+            return id;
+        }
+        self.functions.push((name.to_owned(), function));
+        self.functions.len() - 1
+    }
+
+    /// Looks up a function by the name used to call it.
+    pub fn find(&self, name: &str) -> Option<CustomFunctionId> {
+        self.functions.iter().position(|(n, _)| n == name)
+    }
+
+    /// The name used to call function `id`.
+    pub fn name(&self, id: CustomFunctionId) -> Option<&str> {
+        self.functions.get(id).map(|(name, _)| name.as_str())
+    }
+
+    pub(crate) fn functions(&self) -> impl Iterator<Item = CustomFunction<Real>> + '_ {
+        self.functions.iter().map(|(_, function)| *function)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum StringExpression {
     Literal(String),
     Binding(BindingId),
@@ -57,3 +320,705 @@ pub enum StringExpression {
 
 /// Index into the `&[&[f64]]` bindings passed to expression evaluation.
 pub type BindingId = usize;
+
+/// Position of an operand among its parent's operands, e.g. `0` for the
+/// left-hand side of a binary operator and `1` for the right-hand side.
+pub type ChildIndex = usize;
+
+/// Constructor of a node with two boxed operands, e.g. `RealExpression::Add`.
+pub(crate) type BinaryNode<Operand, Out> = fn(Box<Operand>, Box<Operand>) -> Out;
+
+/// The type of value produced by an [`Expression`] or read from a variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Boolean,
+    Real,
+    String,
+    Tuple,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Boolean => write!(f, "boolean"),
+            Self::Real => write!(f, "real"),
+            Self::String => write!(f, "string"),
+            Self::Tuple => write!(f, "tuple"),
+        }
+    }
+}
+
+/// A node of any type in an [`Expression`], as returned by
+/// [`Expression::node_at_path`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeRef<'a, Real> {
+    Boolean(&'a BoolExpression<Real>),
+    Real(&'a RealExpression<Real>),
+    String(&'a StringExpression),
+    Tuple(&'a TupleExpression<Real>),
+}
+
+impl<'a, Real> NodeRef<'a, Real> {
+    /// The number of operands of this node (see [`Self::operand`]).
+    pub fn num_operands(self) -> usize {
+        match self {
+            Self::Boolean(b) => match b {
+                BoolExpression::Not(_) => 1,
+                BoolExpression::Between(..) => 3,
+                BoolExpression::Literal(_) => 0,
+                _ => 2,
+            },
+            Self::Real(r) => match r {
+                RealExpression::Add(..)
+                | RealExpression::Div(..)
+                | RealExpression::Mul(..)
+                | RealExpression::Pow(..)
+                | RealExpression::Sub(..)
+                | RealExpression::Func2(..) => 2,
+                RealExpression::Neg(_) | RealExpression::Func1(..) | RealExpression::PowI(..) => 1,
+                RealExpression::Case(arms, _) => 2 * arms.len() + 1,
+                RealExpression::Literal(_)
+                | RealExpression::IntLiteral(_)
+                | RealExpression::Binding(_) => 0,
+            },
+            Self::String(_) => 0,
+            Self::Tuple(t) => t.elements.len(),
+        }
+    }
+
+    /// The operand at `index`, if it is in bounds.
+    ///
+    /// Operands are in source order, except that those of a
+    /// [`RealExpression::Case`] are the value of each arm, then the fallback,
+    /// then the condition of each arm, so that real-valued operands are
+    /// numbered as in [`RealExpression::node_at_path`]. The elements of a
+    /// tuple are its operands.
+    pub fn operand(self, index: ChildIndex) -> Option<Self> {
+        let operand = match (self, index) {
+            (Self::Boolean(b), _) => match (b, index) {
+                (
+                    BoolExpression::And(lhs, rhs)
+                    | BoolExpression::Or(lhs, rhs)
+                    | BoolExpression::Xor(lhs, rhs)
+                    | BoolExpression::Implies(lhs, rhs),
+                    0 | 1,
+                ) => Self::Boolean([lhs, rhs][index]),
+                (BoolExpression::Not(only), 0) => Self::Boolean(only),
+                (
+                    BoolExpression::Equal(lhs, rhs)
+                    | BoolExpression::Greater(lhs, rhs)
+                    | BoolExpression::GreaterEqual(lhs, rhs)
+                    | BoolExpression::Less(lhs, rhs)
+                    | BoolExpression::LessEqual(lhs, rhs)
+                    | BoolExpression::NotEqual(lhs, rhs),
+                    0 | 1,
+                ) => Self::Real([lhs, rhs][index]),
+                (BoolExpression::Between(value, lo, hi), 0..=2) => {
+                    Self::Real([value, lo, hi][index])
+                }
+                (
+                    BoolExpression::StrEqual(lhs, rhs) | BoolExpression::StrNotEqual(lhs, rhs),
+                    0 | 1,
+                ) => Self::String([lhs, rhs][index]),
+                (
+                    BoolExpression::CoercedEqual(lhs, _) | BoolExpression::CoercedNotEqual(lhs, _),
+                    0,
+                ) => Self::String(lhs),
+                (
+                    BoolExpression::CoercedEqual(_, rhs) | BoolExpression::CoercedNotEqual(_, rhs),
+                    1,
+                ) => Self::Real(rhs),
+                _ => return None,
+            },
+            (Self::Real(RealExpression::Case(arms, _)), _) if index > arms.len() => {
+                Self::Boolean(&arms.get(index - arms.len() - 1)?.0)
+            }
+            (Self::Real(r), _) => Self::Real(r.node_at_path(&[index])?),
+            (Self::String(_), _) => return None,
+            (Self::Tuple(t), _) => Self::Real(t.elements.get(index)?),
+        };
+        Some(operand)
+    }
+
+    /// The node reached by descending into the operand at each index of
+    /// `path` in turn, if every index is in bounds.
+    pub fn node_at_path(self, path: &[ChildIndex]) -> Option<Self> {
+        path.iter()
+            .try_fold(self, |node, &index| node.operand(index))
+    }
+}
+
+impl<Real> Expression<Real> {
+    /// The total number of nodes in this expression's syntax tree.
+    pub fn node_count(&self) -> usize {
+        match self {
+            Self::Boolean(b) => b.node_count(),
+            Self::Real(r) => r.node_count(),
+            Self::String(_) => 1,
+            Self::Tuple(t) => 1 + t.elements.iter().map(|e| e.node_count()).sum::<usize>(),
+        }
+    }
+
+    /// The root node of this expression.
+    pub fn root(&self) -> NodeRef<'_, Real> {
+        match self {
+            Self::Boolean(b) => NodeRef::Boolean(b),
+            Self::Real(r) => NodeRef::Real(r),
+            Self::String(s) => NodeRef::String(s),
+            Self::Tuple(t) => NodeRef::Tuple(t),
+        }
+    }
+
+    /// The node reached by descending into the operand at each index of
+    /// `path` in turn (see [`NodeRef::operand`]), if every index is in bounds.
+    ///
+    /// Unlike [`RealExpression::node_at_path`], this also reaches boolean and
+    /// string nodes, e.g. the conditions of a [`RealExpression::Case`].
+    pub fn node_at_path(&self, path: &[ChildIndex]) -> Option<NodeRef<'_, Real>> {
+        self.root().node_at_path(path)
+    }
+
+    /// The type of value this expression produces.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Self::Boolean(_) => ValueType::Boolean,
+            Self::Real(_) => ValueType::Real,
+            Self::String(_) => ValueType::String,
+            Self::Tuple(_) => ValueType::Tuple,
+        }
+    }
+
+    /// Checks that every binding is declared by `schema` with the type it is
+    /// read as, and returns the type of the expression.
+    ///
+    /// The parser already ensures that every operator's operands have
+    /// compatible types, so this is enough to accept an expression before any
+    /// data is available.
+    pub fn validate(
+        &self,
+        schema: impl Fn(BindingId) -> Option<ValueType>,
+    ) -> Result<ValueType, ValidationError> {
+        let mut real_ids = Vec::new();
+        let mut string_ids = Vec::new();
+        match self {
+            Self::Boolean(b) => {
+                b.collect_real_binding_ids(&mut real_ids);
+                b.collect_string_binding_ids(&mut string_ids);
+            }
+            Self::Real(r) => r.collect_binding_ids(&mut real_ids),
+            Self::String(s) => s.collect_binding_ids(&mut string_ids),
+            Self::Tuple(t) => {
+                for element in &t.elements {
+                    element.collect_binding_ids(&mut real_ids);
+                }
+            }
+        }
+        for (ids, expected) in [(real_ids, ValueType::Real), (string_ids, ValueType::String)] {
+            for binding in ids {
+                match schema(binding) {
+                    None => return Err(ValidationError::UnknownBinding(binding)),
+                    Some(found) if found != expected => {
+                        return Err(ValidationError::BindingType {
+                            binding,
+                            expected,
+                            found,
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(self.value_type())
+    }
+
+    /// Rewrites every binding ID through `map`.
+    ///
+    /// This allows reusing a parsed expression with a different binding
+    /// layout.
+    pub fn remap_bindings(&mut self, map: impl Fn(BindingId) -> BindingId) {
+        match self {
+            Self::Boolean(b) => b.remap_bindings_with(&map),
+            Self::Real(r) => r.remap_bindings_with(&map),
+            Self::String(s) => s.remap_bindings_with(&map),
+            Self::Tuple(t) => {
+                for element in &mut t.elements {
+                    element.remap_bindings_with(&map);
+                }
+            }
+        }
+    }
+
+    /// The sorted, deduplicated values of all string literals in this
+    /// expression, e.g. for interning them before evaluation.
+    pub fn string_literals(&self) -> Vec<&str> {
+        match self {
+            Self::Boolean(b) => b.string_literals(),
+            Self::String(s) => s.string_literals(),
+            Self::Real(_) | Self::Tuple(_) => Vec::new(),
+        }
+    }
+}
+
+impl<Real> BoolExpression<Real> {
+    /// The sorted, deduplicated values of all string literals in this
+    /// expression, e.g. for interning them before evaluation.
+    pub fn string_literals(&self) -> Vec<&str> {
+        let mut literals = Vec::new();
+        self.collect_string_literals(&mut literals);
+        literals.sort_unstable();
+        literals.dedup();
+        literals
+    }
+
+    fn collect_string_literals<'a>(&'a self, literals: &mut Vec<&'a str>) {
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => {
+                lhs.collect_string_literals(literals);
+                rhs.collect_string_literals(literals);
+            }
+            Self::Not(only) => only.collect_string_literals(literals),
+            Self::StrEqual(lhs, rhs) | Self::StrNotEqual(lhs, rhs) => {
+                literals.extend(lhs.string_literals());
+                literals.extend(rhs.string_literals());
+            }
+            Self::CoercedEqual(lhs, _) | Self::CoercedNotEqual(lhs, _) => {
+                literals.extend(lhs.string_literals());
+            }
+            Self::Equal(..)
+            | Self::Greater(..)
+            | Self::GreaterEqual(..)
+            | Self::Less(..)
+            | Self::LessEqual(..)
+            | Self::NotEqual(..)
+            | Self::Between(..)
+            | Self::Literal(_) => {}
+        }
+    }
+
+    /// The total number of nodes in this expression's syntax tree.
+    pub fn node_count(&self) -> usize {
+        1 + match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => lhs.node_count() + rhs.node_count(),
+            Self::Not(only) => only.node_count(),
+            Self::Equal(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::NotEqual(lhs, rhs) => lhs.node_count() + rhs.node_count(),
+            Self::Between(value, lo, hi) => value.node_count() + lo.node_count() + hi.node_count(),
+            Self::StrEqual(..) | Self::StrNotEqual(..) => 2,
+            Self::CoercedEqual(_, rhs) | Self::CoercedNotEqual(_, rhs) => 1 + rhs.node_count(),
+            Self::Literal(_) => 0,
+        }
+    }
+
+    /// Rewrites every real and string binding ID through `map`.
+    pub fn remap_bindings(&mut self, map: impl Fn(BindingId) -> BindingId) {
+        self.remap_bindings_with(&map)
+    }
+
+    fn remap_bindings_with(&mut self, map: &impl Fn(BindingId) -> BindingId) {
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => {
+                lhs.remap_bindings_with(map);
+                rhs.remap_bindings_with(map);
+            }
+            Self::Not(only) => only.remap_bindings_with(map),
+            Self::Equal(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::NotEqual(lhs, rhs) => {
+                lhs.remap_bindings_with(map);
+                rhs.remap_bindings_with(map);
+            }
+            Self::Between(value, lo, hi) => {
+                value.remap_bindings_with(map);
+                lo.remap_bindings_with(map);
+                hi.remap_bindings_with(map);
+            }
+            Self::StrEqual(lhs, rhs) | Self::StrNotEqual(lhs, rhs) => {
+                lhs.remap_bindings_with(map);
+                rhs.remap_bindings_with(map);
+            }
+            Self::CoercedEqual(lhs, rhs) | Self::CoercedNotEqual(lhs, rhs) => {
+                lhs.remap_bindings_with(map);
+                rhs.remap_bindings_with(map);
+            }
+            Self::Literal(_) => {}
+        }
+    }
+
+    /// The name of this node's variant, e.g. `"And"`.
+    pub(crate) fn node_name(&self) -> &'static str {
+        match self {
+            Self::And(..) => "And",
+            Self::Or(..) => "Or",
+            Self::Xor(..) => "Xor",
+            Self::Implies(..) => "Implies",
+            Self::Not(..) => "Not",
+            Self::Equal(..) => "Equal",
+            Self::Greater(..) => "Greater",
+            Self::GreaterEqual(..) => "GreaterEqual",
+            Self::Less(..) => "Less",
+            Self::LessEqual(..) => "LessEqual",
+            Self::NotEqual(..) => "NotEqual",
+            Self::Between(..) => "Between",
+            Self::StrEqual(..) => "StrEqual",
+            Self::StrNotEqual(..) => "StrNotEqual",
+            Self::CoercedEqual(..) => "CoercedEqual",
+            Self::CoercedNotEqual(..) => "CoercedNotEqual",
+            Self::Literal(..) => "Literal",
+        }
+    }
+
+    /// Whether any node compares strings, so string bindings are needed to
+    /// evaluate `self`.
+    pub(crate) fn reads_strings(&self) -> bool {
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => lhs.reads_strings() || rhs.reads_strings(),
+            Self::Not(only) => only.reads_strings(),
+            Self::StrEqual(..)
+            | Self::StrNotEqual(..)
+            | Self::CoercedEqual(..)
+            | Self::CoercedNotEqual(..) => true,
+            Self::Equal(..)
+            | Self::Greater(..)
+            | Self::GreaterEqual(..)
+            | Self::Less(..)
+            | Self::LessEqual(..)
+            | Self::NotEqual(..)
+            | Self::Between(..)
+            | Self::Literal(_) => false,
+        }
+    }
+
+    /// Collects the IDs of real bindings read by this expression.
+    pub(crate) fn collect_real_binding_ids(&self, ids: &mut Vec<BindingId>) {
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => {
+                lhs.collect_real_binding_ids(ids);
+                rhs.collect_real_binding_ids(ids);
+            }
+            Self::Not(only) => only.collect_real_binding_ids(ids),
+            Self::Equal(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::NotEqual(lhs, rhs) => {
+                lhs.collect_binding_ids(ids);
+                rhs.collect_binding_ids(ids);
+            }
+            Self::Between(value, lo, hi) => {
+                value.collect_binding_ids(ids);
+                lo.collect_binding_ids(ids);
+                hi.collect_binding_ids(ids);
+            }
+            Self::CoercedEqual(_, rhs) | Self::CoercedNotEqual(_, rhs) => {
+                rhs.collect_binding_ids(ids)
+            }
+            Self::StrEqual(..) | Self::StrNotEqual(..) | Self::Literal(_) => {}
+        }
+    }
+
+    /// Collects the IDs of string bindings read by this expression.
+    pub(crate) fn collect_string_binding_ids(&self, ids: &mut Vec<BindingId>) {
+        match self {
+            Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs)
+            | Self::Xor(lhs, rhs)
+            | Self::Implies(lhs, rhs) => {
+                lhs.collect_string_binding_ids(ids);
+                rhs.collect_string_binding_ids(ids);
+            }
+            Self::Not(only) => only.collect_string_binding_ids(ids),
+            Self::StrEqual(lhs, rhs) | Self::StrNotEqual(lhs, rhs) => {
+                lhs.collect_binding_ids(ids);
+                rhs.collect_binding_ids(ids);
+            }
+            Self::CoercedEqual(lhs, _) | Self::CoercedNotEqual(lhs, _) => {
+                lhs.collect_binding_ids(ids)
+            }
+            Self::Equal(..)
+            | Self::Greater(..)
+            | Self::GreaterEqual(..)
+            | Self::Less(..)
+            | Self::LessEqual(..)
+            | Self::NotEqual(..)
+            | Self::Between(..)
+            | Self::Literal(_) => {}
+        }
+    }
+
+    /// Converts every real literal with `f`, preserving the tree structure.
+    pub(crate) fn map_literals<Out>(&self, f: &impl Fn(&Real) -> Out) -> BoolExpression<Out> {
+        let map_bool = |e: &Self| Box::new(e.map_literals(f));
+        let map_real = |e: &RealExpression<Real>| Box::new(e.map_literals(f));
+        match self {
+            Self::And(lhs, rhs) => BoolExpression::And(map_bool(lhs), map_bool(rhs)),
+            Self::Or(lhs, rhs) => BoolExpression::Or(map_bool(lhs), map_bool(rhs)),
+            Self::Xor(lhs, rhs) => BoolExpression::Xor(map_bool(lhs), map_bool(rhs)),
+            Self::Implies(lhs, rhs) => BoolExpression::Implies(map_bool(lhs), map_bool(rhs)),
+            Self::Not(only) => BoolExpression::Not(map_bool(only)),
+            Self::Equal(lhs, rhs) => BoolExpression::Equal(map_real(lhs), map_real(rhs)),
+            Self::Greater(lhs, rhs) => BoolExpression::Greater(map_real(lhs), map_real(rhs)),
+            Self::GreaterEqual(lhs, rhs) => {
+                BoolExpression::GreaterEqual(map_real(lhs), map_real(rhs))
+            }
+            Self::Less(lhs, rhs) => BoolExpression::Less(map_real(lhs), map_real(rhs)),
+            Self::LessEqual(lhs, rhs) => BoolExpression::LessEqual(map_real(lhs), map_real(rhs)),
+            Self::NotEqual(lhs, rhs) => BoolExpression::NotEqual(map_real(lhs), map_real(rhs)),
+            Self::Between(value, lo, hi) => {
+                BoolExpression::Between(map_real(value), map_real(lo), map_real(hi))
+            }
+            Self::StrEqual(lhs, rhs) => BoolExpression::StrEqual(lhs.clone(), rhs.clone()),
+            Self::StrNotEqual(lhs, rhs) => BoolExpression::StrNotEqual(lhs.clone(), rhs.clone()),
+            Self::CoercedEqual(lhs, rhs) => {
+                BoolExpression::CoercedEqual(lhs.clone(), map_real(rhs))
+            }
+            Self::CoercedNotEqual(lhs, rhs) => {
+                BoolExpression::CoercedNotEqual(lhs.clone(), map_real(rhs))
+            }
+            Self::Literal(value) => BoolExpression::Literal(*value),
+        }
+    }
+}
+
+impl<Real> RealExpression<Real> {
+    /// The total number of nodes in this expression's syntax tree.
+    pub fn node_count(&self) -> usize {
+        1 + match self {
+            Self::Add(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Pow(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Func2(_, lhs, rhs) => lhs.node_count() + rhs.node_count(),
+            Self::Neg(only) | Self::Func1(_, only) | Self::PowI(only, _) => only.node_count(),
+            Self::Case(arms, otherwise) => {
+                arms.iter()
+                    .map(|(condition, value)| condition.node_count() + value.node_count())
+                    .sum::<usize>()
+                    + otherwise.node_count()
+            }
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => 0,
+        }
+    }
+
+    /// Rewrites every binding ID through `map`.
+    pub fn remap_bindings(&mut self, map: impl Fn(BindingId) -> BindingId) {
+        self.remap_bindings_with(&map)
+    }
+
+    fn remap_bindings_with(&mut self, map: &impl Fn(BindingId) -> BindingId) {
+        match self {
+            Self::Add(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Pow(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Func2(_, lhs, rhs) => {
+                lhs.remap_bindings_with(map);
+                rhs.remap_bindings_with(map);
+            }
+            Self::Neg(only) | Self::Func1(_, only) | Self::PowI(only, _) => {
+                only.remap_bindings_with(map)
+            }
+            Self::Case(arms, otherwise) => {
+                for (condition, value) in arms {
+                    condition.remap_bindings_with(map);
+                    value.remap_bindings_with(map);
+                }
+                otherwise.remap_bindings_with(map);
+            }
+            Self::Literal(_) | Self::IntLiteral(_) => {}
+            Self::Binding(binding) => *binding = map(*binding),
+        }
+    }
+
+    /// The subexpression reached by descending into the operand at each index
+    /// of `path` in turn, if every index is in bounds.
+    ///
+    /// An empty `path` refers to `self`. The operands of a
+    /// [`Case`](Self::Case) are the value of each arm followed by the
+    /// fallback, since conditions are not real-valued (see
+    /// [`Expression::node_at_path`] to reach them).
+    pub fn node_at_path(&self, path: &[ChildIndex]) -> Option<&Self> {
+        let Some((&first, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let child = match (self, first) {
+            (
+                Self::Add(lhs, rhs)
+                | Self::Div(lhs, rhs)
+                | Self::Mul(lhs, rhs)
+                | Self::Pow(lhs, rhs)
+                | Self::Sub(lhs, rhs)
+                | Self::Func2(_, lhs, rhs),
+                0 | 1,
+            ) => [lhs, rhs][first],
+            (Self::Neg(only) | Self::Func1(_, only) | Self::PowI(only, _), 0) => only,
+            (Self::Case(arms, otherwise), _) => match arms.get(first) {
+                Some((_, value)) => value,
+                None if first == arms.len() => otherwise,
+                None => return None,
+            },
+            _ => return None,
+        };
+        child.node_at_path(rest)
+    }
+
+    /// The sorted, deduplicated IDs of all bindings read by this expression.
+    pub fn binding_ids(&self) -> Vec<BindingId> {
+        let mut ids = Vec::new();
+        self.collect_binding_ids(&mut ids);
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    fn collect_binding_ids(&self, ids: &mut Vec<BindingId>) {
+        match self {
+            Self::Add(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Pow(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Func2(_, lhs, rhs) => {
+                lhs.collect_binding_ids(ids);
+                rhs.collect_binding_ids(ids);
+            }
+            Self::Neg(only) | Self::Func1(_, only) | Self::PowI(only, _) => {
+                only.collect_binding_ids(ids)
+            }
+            Self::Case(arms, otherwise) => {
+                for (condition, value) in arms {
+                    condition.collect_real_binding_ids(ids);
+                    value.collect_binding_ids(ids);
+                }
+                otherwise.collect_binding_ids(ids);
+            }
+            Self::Literal(_) | Self::IntLiteral(_) => {}
+            Self::Binding(binding) => ids.push(*binding),
+        }
+    }
+
+    /// Converts every literal with `f`, preserving the tree structure, e.g.
+    /// to evaluate with another [`ExprScalar`](crate::ExprScalar) type.
+    pub fn map_literals<Out>(&self, f: &impl Fn(&Real) -> Out) -> RealExpression<Out> {
+        let map = |e: &Self| Box::new(e.map_literals(f));
+        match self {
+            Self::Add(lhs, rhs) => RealExpression::Add(map(lhs), map(rhs)),
+            Self::Div(lhs, rhs) => RealExpression::Div(map(lhs), map(rhs)),
+            Self::Mul(lhs, rhs) => RealExpression::Mul(map(lhs), map(rhs)),
+            Self::Pow(lhs, rhs) => RealExpression::Pow(map(lhs), map(rhs)),
+            Self::Sub(lhs, rhs) => RealExpression::Sub(map(lhs), map(rhs)),
+            Self::Neg(only) => RealExpression::Neg(map(only)),
+            Self::Func1(func, only) => RealExpression::Func1(*func, map(only)),
+            Self::PowI(only, n) => RealExpression::PowI(map(only), *n),
+            Self::Case(arms, otherwise) => RealExpression::Case(
+                arms.iter()
+                    .map(|(condition, value)| (condition.map_literals(f), value.map_literals(f)))
+                    .collect(),
+                map(otherwise),
+            ),
+            Self::Func2(func, lhs, rhs) => RealExpression::Func2(*func, map(lhs), map(rhs)),
+            Self::Literal(value) => RealExpression::Literal(f(value)),
+            Self::IntLiteral(value) => RealExpression::IntLiteral(*value),
+            Self::Binding(binding) => RealExpression::Binding(*binding),
+        }
+    }
+
+    /// The name of this node's variant, e.g. `"Add"`.
+    pub(crate) fn node_name(&self) -> &'static str {
+        match self {
+            Self::Add(..) => "Add",
+            Self::Div(..) => "Div",
+            Self::Mul(..) => "Mul",
+            Self::Pow(..) => "Pow",
+            Self::Sub(..) => "Sub",
+            Self::Neg(..) => "Neg",
+            Self::Func1(..) => "Func1",
+            Self::PowI(..) => "PowI",
+            Self::Case(..) => "Case",
+            Self::Func2(..) => "Func2",
+            Self::Literal(..) => "Literal",
+            Self::IntLiteral(..) => "IntLiteral",
+            Self::Binding(..) => "Binding",
+        }
+    }
+}
+
+impl<Real: Float> RealExpression<Real> {
+    /// The value of this node if it is a [`Literal`](Self::Literal) or an
+    /// [`IntLiteral`](Self::IntLiteral).
+    pub fn literal_value(&self) -> Option<Real> {
+        match self {
+            Self::Literal(value) => Some(*value),
+            Self::IntLiteral(value) => Real::from(*value),
+            _ => None,
+        }
+    }
+
+    /// The exponent of `x ^ n` where `n` was written as an integer, possibly
+    /// negated, or of `powi(x, n)`.
+    pub fn integer_exponent(&self) -> Option<i32> {
+        match self {
+            Self::Pow(_, exponent) => match exponent.as_ref() {
+                Self::IntLiteral(n) => Some(*n),
+                Self::Neg(only) => match only.as_ref() {
+                    Self::IntLiteral(n) => n.checked_neg(),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Self::PowI(_, n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl StringExpression {
+    /// Rewrites the binding ID (if any) through `map`.
+    pub fn remap_bindings(&mut self, map: impl Fn(BindingId) -> BindingId) {
+        self.remap_bindings_with(&map)
+    }
+
+    fn remap_bindings_with(&mut self, map: &impl Fn(BindingId) -> BindingId) {
+        if let Self::Binding(binding) = self {
+            *binding = map(*binding);
+        }
+    }
+
+    fn collect_binding_ids(&self, ids: &mut Vec<BindingId>) {
+        if let Self::Binding(binding) = self {
+            ids.push(*binding);
+        }
+    }
+
+    /// The value of this expression if it is a literal.
+    pub fn string_literals(&self) -> Vec<&str> {
+        match self {
+            Self::Literal(value) => vec![value.as_str()],
+            Self::Binding(_) => Vec::new(),
+        }
+    }
+}
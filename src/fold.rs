@@ -0,0 +1,758 @@
+use crate::expression::BinaryNode;
+use crate::{BindingId, BoolExpression, FloatExt, Func1, RealExpression, StringExpression};
+
+impl<Real: FloatExt> RealExpression<Real> {
+    /// Returns a copy of this expression where every read of `binding` is
+    /// replaced by the constant `value`.
+    ///
+    /// Follow with [`Self::fold_constants`] to specialize the expression.
+    pub fn substitute(&self, binding: BindingId, value: Real) -> Self {
+        let substitute = |e: &Self| Box::new(e.substitute(binding, value));
+        match self {
+            Self::Add(lhs, rhs) => Self::Add(substitute(lhs), substitute(rhs)),
+            Self::Div(lhs, rhs) => Self::Div(substitute(lhs), substitute(rhs)),
+            Self::Mul(lhs, rhs) => Self::Mul(substitute(lhs), substitute(rhs)),
+            Self::Pow(lhs, rhs) => Self::Pow(substitute(lhs), substitute(rhs)),
+            Self::Sub(lhs, rhs) => Self::Sub(substitute(lhs), substitute(rhs)),
+            Self::Neg(only) => Self::Neg(substitute(only)),
+            Self::Func1(func, only) => Self::Func1(*func, substitute(only)),
+            Self::PowI(only, n) => Self::PowI(substitute(only), *n),
+            Self::Func2(func, lhs, rhs) => Self::Func2(*func, substitute(lhs), substitute(rhs)),
+            Self::Case(arms, otherwise) => Self::Case(
+                arms.iter()
+                    .map(|(condition, v)| {
+                        (
+                            condition.substitute(binding, value),
+                            v.substitute(binding, value),
+                        )
+                    })
+                    .collect(),
+                substitute(otherwise),
+            ),
+            Self::Binding(b) if *b == binding => Self::Literal(value),
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => self.clone(),
+        }
+    }
+
+    /// Replaces every subexpression that only depends on literals with its
+    /// computed value.
+    ///
+    /// Arithmetic on [`IntLiteral`](Self::IntLiteral)s stays an `IntLiteral`
+    /// where the result is an integer that fits, e.g. `3 * 2 - 1` becomes `5`
+    /// but `3 / 2` becomes `1.5`.
+    pub fn fold_constants(self) -> Self {
+        match self {
+            Self::Add(lhs, rhs) => fold_binary_real_op(Self::Add, |lhs, rhs| lhs + rhs, *lhs, *rhs),
+            Self::Div(lhs, rhs) => fold_binary_real_op(Self::Div, |lhs, rhs| lhs / rhs, *lhs, *rhs),
+            Self::Mul(lhs, rhs) => fold_binary_real_op(Self::Mul, |lhs, rhs| lhs * rhs, *lhs, *rhs),
+            Self::Pow(lhs, rhs) => {
+                fold_binary_real_op(Self::Pow, |lhs, rhs| lhs.powf(rhs), *lhs, *rhs)
+            }
+            Self::Sub(lhs, rhs) => fold_binary_real_op(Self::Sub, |lhs, rhs| lhs - rhs, *lhs, *rhs),
+            Self::Neg(only) => {
+                let only = only.fold_constants();
+                match (&only, only.literal_value()) {
+                    // `-0` is not an integer literal.
+                    (Self::IntLiteral(n), _) if *n != 0 && *n != i32::MIN => Self::IntLiteral(-n),
+                    (_, Some(value)) => Self::Literal(-value),
+                    (_, None) => Self::Neg(Box::new(only)),
+                }
+            }
+            Self::Func1(func, only) => {
+                let only = only.fold_constants();
+                match only.literal_value() {
+                    Some(value) => Self::Literal(func.function()(value)),
+                    None => Self::Func1(func, Box::new(only)),
+                }
+            }
+            Self::PowI(only, n) => {
+                let only = only.fold_constants();
+                match only.literal_value() {
+                    Some(value) => Self::Literal(value.powi(n)),
+                    None => Self::PowI(Box::new(only), n),
+                }
+            }
+            Self::Func2(func, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.fold_constants(), rhs.fold_constants());
+                match (func.function(), lhs.literal_value(), rhs.literal_value()) {
+                    (Some(function), Some(lhs), Some(rhs)) => Self::Literal(function(lhs, rhs)),
+                    // Custom functions are unknown until evaluation.
+                    _ => Self::Func2(func, Box::new(lhs), Box::new(rhs)),
+                }
+            }
+            Self::Case(arms, otherwise) => {
+                let case = |arms: Vec<_>, otherwise| match arms.is_empty() {
+                    true => otherwise,
+                    false => Self::Case(arms, Box::new(otherwise)),
+                };
+                let mut folded = Vec::new();
+                for (condition, value) in arms {
+                    match condition.fold_constants() {
+                        BoolExpression::Literal(false) => {}
+                        // Later arms and the fallback are unreachable.
+                        BoolExpression::Literal(true) => {
+                            return case(folded, value.fold_constants());
+                        }
+                        condition => folded.push((condition, value.fold_constants())),
+                    }
+                }
+                case(folded, otherwise.fold_constants())
+            }
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => self,
+        }
+    }
+
+    /// Rewrites arithmetic into cheaper, equivalent forms that may round
+    /// slightly differently.
+    ///
+    /// Division by a literal `c` becomes multiplication by `1 / c` where that
+    /// is a normal float. Division by zero, or by a literal so small or large
+    /// that its reciprocal overflows or is subnormal, is left alone. `1 / e` becomes `recip(e)`,
+    /// `0 - e` becomes `-e`, which is `-0` rather than `0` where `e` is zero,
+    /// and `2 ^ e` becomes `exp2(e)`. See also [`Self::lower_integer_powers`].
+    pub fn optimize_arithmetic(self) -> Self {
+        let optimize = |e: Box<Self>| Box::new(e.optimize_arithmetic());
+        match self {
+            Self::Add(lhs, rhs) => Self::Add(optimize(lhs), optimize(rhs)),
+            Self::Div(lhs, rhs) => match (lhs.literal_value(), rhs.literal_value()) {
+                (_, Some(c)) if c.recip().is_normal() => {
+                    Self::Mul(optimize(lhs), Box::new(Self::Literal(c.recip())))
+                }
+                (Some(c), _) if c == Real::one() => Self::Func1(Func1::Recip, optimize(rhs)),
+                _ => Self::Div(optimize(lhs), optimize(rhs)),
+            },
+            Self::Mul(lhs, rhs) => Self::Mul(optimize(lhs), optimize(rhs)),
+            Self::Pow(lhs, rhs) => match (lhs.literal_value(), rhs.as_ref()) {
+                (Some(c), _) if c == Real::one() + Real::one() => {
+                    Self::Func1(Func1::Exp2, optimize(rhs))
+                }
+                _ => Self::Pow(optimize(lhs), optimize(rhs)),
+            },
+            Self::Sub(lhs, rhs) => match lhs.literal_value() {
+                Some(c) if c == Real::zero() => Self::Neg(optimize(rhs)),
+                _ => Self::Sub(optimize(lhs), optimize(rhs)),
+            },
+            Self::Neg(only) => Self::Neg(optimize(only)),
+            Self::Func1(func, only) => Self::Func1(func, optimize(only)),
+            Self::PowI(only, n) => Self::PowI(optimize(only), n),
+            Self::Func2(func, lhs, rhs) => Self::Func2(func, optimize(lhs), optimize(rhs)),
+            Self::Case(arms, otherwise) => Self::Case(
+                arms.into_iter()
+                    .map(|(condition, value)| {
+                        (condition.optimize_arithmetic(), value.optimize_arithmetic())
+                    })
+                    .collect(),
+                optimize(otherwise),
+            ),
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => self,
+        }
+    }
+
+    /// Rewrites `e ^ n` where `n` is an [`IntLiteral`](Self::IntLiteral) into
+    /// `powi(e, n)`, which multiplies repeatedly instead of calling `powf`.
+    ///
+    /// This is faster, but the error grows with `n`, so results may differ
+    /// from `powf` in the last few bits.
+    pub fn lower_integer_powers(self) -> Self {
+        let lower = |e: Box<Self>| Box::new(e.lower_integer_powers());
+        match self {
+            Self::Add(lhs, rhs) => Self::Add(lower(lhs), lower(rhs)),
+            Self::Div(lhs, rhs) => Self::Div(lower(lhs), lower(rhs)),
+            Self::Mul(lhs, rhs) => Self::Mul(lower(lhs), lower(rhs)),
+            Self::Pow(lhs, rhs) => match *rhs {
+                Self::IntLiteral(n) => Self::PowI(lower(lhs), n),
+                rhs => Self::Pow(lower(lhs), lower(Box::new(rhs))),
+            },
+            Self::Sub(lhs, rhs) => Self::Sub(lower(lhs), lower(rhs)),
+            Self::Neg(only) => Self::Neg(lower(only)),
+            Self::Func1(func, only) => Self::Func1(func, lower(only)),
+            Self::PowI(only, n) => Self::PowI(lower(only), n),
+            Self::Func2(func, lhs, rhs) => Self::Func2(func, lower(lhs), lower(rhs)),
+            Self::Case(arms, otherwise) => Self::Case(
+                arms.into_iter()
+                    .map(|(condition, value)| {
+                        (
+                            condition.lower_integer_powers(),
+                            value.lower_integer_powers(),
+                        )
+                    })
+                    .collect(),
+                lower(otherwise),
+            ),
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => self,
+        }
+    }
+}
+
+impl<Real: FloatExt> RealExpression<Real> {
+    /// Removes algebraic no-ops, which are common in machine-generated
+    /// expressions.
+    ///
+    /// `x + -0`, `x - 0`, `x * 1`, `x / 1` and `x ^ 1` become `x`, and `x ^ 0`
+    /// becomes `1`. If `fast_math`, `x * 0` and `x - x` also become `0`, which
+    /// is wrong where `x` is NaN or infinite, and `x + 0` and `x - -0` become
+    /// `x`, which turns `-0` into `+0` where `x` is `-0`.
+    pub fn simplify(self, fast_math: bool) -> Self {
+        let simplify = |e: Box<Self>| e.simplify(fast_math);
+        let (zero, one) = (Real::zero(), Real::one());
+        // `-0 + 0` is `+0`, so only the zero with the given sign is an exact
+        // identity.
+        let is_identity_zero = |c: &Self, negative: bool| {
+            c.literal_value()
+                .is_some_and(|c| c == zero && (fast_math || c.is_sign_negative() == negative))
+        };
+        match self {
+            Self::Add(lhs, rhs) => match (simplify(lhs), simplify(rhs)) {
+                (x, c) | (c, x) if is_identity_zero(&c, true) => x,
+                (lhs, rhs) => Self::Add(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Sub(lhs, rhs) => match (simplify(lhs), simplify(rhs)) {
+                (x, c) if is_identity_zero(&c, false) => x,
+                (lhs, rhs) if fast_math && lhs == rhs => Self::Literal(zero),
+                (lhs, rhs) => Self::Sub(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Mul(lhs, rhs) => match (simplify(lhs), simplify(rhs)) {
+                (x, c) | (c, x) if c.literal_value() == Some(one) => x,
+                (_, c) | (c, _) if fast_math && c.literal_value() == Some(zero) => {
+                    Self::Literal(zero)
+                }
+                (lhs, rhs) => Self::Mul(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Div(lhs, rhs) => match (simplify(lhs), simplify(rhs)) {
+                (x, c) if c.literal_value() == Some(one) => x,
+                (lhs, rhs) => Self::Div(Box::new(lhs), Box::new(rhs)),
+            },
+            // `powf(x, 0)` is 1 even for NaN `x`.
+            Self::Pow(lhs, rhs) => match (simplify(lhs), simplify(rhs)) {
+                (x, c) if c.literal_value() == Some(one) => x,
+                (_, c) if c.literal_value() == Some(zero) => Self::Literal(one),
+                (lhs, rhs) => Self::Pow(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::PowI(only, n) => match (simplify(only), n) {
+                (x, 1) => x,
+                (_, 0) => Self::Literal(one),
+                (only, n) => Self::PowI(Box::new(only), n),
+            },
+            Self::Neg(only) => Self::Neg(Box::new(simplify(only))),
+            Self::Func1(func, only) => Self::Func1(func, Box::new(simplify(only))),
+            Self::Func2(func, lhs, rhs) => {
+                Self::Func2(func, Box::new(simplify(lhs)), Box::new(simplify(rhs)))
+            }
+            Self::Case(arms, otherwise) => Self::Case(
+                arms.into_iter()
+                    .map(|(condition, value)| {
+                        (condition.simplify(fast_math), value.simplify(fast_math))
+                    })
+                    .collect(),
+                Box::new(simplify(otherwise)),
+            ),
+            Self::Literal(_) | Self::IntLiteral(_) | Self::Binding(_) => self,
+        }
+    }
+}
+
+impl<Real: FloatExt> BoolExpression<Real> {
+    /// Like [`RealExpression::substitute`].
+    pub(crate) fn substitute(&self, binding: BindingId, value: Real) -> Self {
+        let substitute_bool = |e: &Self| Box::new(e.substitute(binding, value));
+        let substitute_real = |e: &RealExpression<Real>| Box::new(e.substitute(binding, value));
+        match self {
+            Self::And(lhs, rhs) => Self::And(substitute_bool(lhs), substitute_bool(rhs)),
+            Self::Or(lhs, rhs) => Self::Or(substitute_bool(lhs), substitute_bool(rhs)),
+            Self::Xor(lhs, rhs) => Self::Xor(substitute_bool(lhs), substitute_bool(rhs)),
+            Self::Implies(lhs, rhs) => Self::Implies(substitute_bool(lhs), substitute_bool(rhs)),
+            Self::Not(only) => Self::Not(substitute_bool(only)),
+            Self::Equal(lhs, rhs) => Self::Equal(substitute_real(lhs), substitute_real(rhs)),
+            Self::Greater(lhs, rhs) => Self::Greater(substitute_real(lhs), substitute_real(rhs)),
+            Self::GreaterEqual(lhs, rhs) => {
+                Self::GreaterEqual(substitute_real(lhs), substitute_real(rhs))
+            }
+            Self::Less(lhs, rhs) => Self::Less(substitute_real(lhs), substitute_real(rhs)),
+            Self::LessEqual(lhs, rhs) => {
+                Self::LessEqual(substitute_real(lhs), substitute_real(rhs))
+            }
+            Self::NotEqual(lhs, rhs) => Self::NotEqual(substitute_real(lhs), substitute_real(rhs)),
+            Self::Between(v, lo, hi) => {
+                Self::Between(substitute_real(v), substitute_real(lo), substitute_real(hi))
+            }
+            Self::CoercedEqual(lhs, rhs) => Self::CoercedEqual(lhs.clone(), substitute_real(rhs)),
+            Self::CoercedNotEqual(lhs, rhs) => {
+                Self::CoercedNotEqual(lhs.clone(), substitute_real(rhs))
+            }
+            Self::StrEqual(..) | Self::StrNotEqual(..) | Self::Literal(_) => self.clone(),
+        }
+    }
+
+    /// Replaces every subexpression that only depends on literals with its
+    /// computed value, then simplifies logic with constant operands.
+    ///
+    /// `true && x` and `false || x` become `x`, `false && x` and `true || x`
+    /// become constants, and `!` of a constant is negated.
+    pub fn fold_constants(self) -> Self {
+        match self {
+            Self::And(lhs, rhs) => match (lhs.fold_constants(), rhs.fold_constants()) {
+                (Self::Literal(false), _) | (_, Self::Literal(false)) => Self::Literal(false),
+                (Self::Literal(true), other) | (other, Self::Literal(true)) => other,
+                (lhs, rhs) => Self::And(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Or(lhs, rhs) => match (lhs.fold_constants(), rhs.fold_constants()) {
+                (Self::Literal(true), _) | (_, Self::Literal(true)) => Self::Literal(true),
+                (Self::Literal(false), other) | (other, Self::Literal(false)) => other,
+                (lhs, rhs) => Self::Or(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Xor(lhs, rhs) => match (lhs.fold_constants(), rhs.fold_constants()) {
+                (Self::Literal(lhs), Self::Literal(rhs)) => Self::Literal(lhs ^ rhs),
+                (Self::Literal(false), other) | (other, Self::Literal(false)) => other,
+                (Self::Literal(true), other) | (other, Self::Literal(true)) => {
+                    Self::Not(Box::new(other))
+                }
+                (lhs, rhs) => Self::Xor(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Implies(lhs, rhs) => match (lhs.fold_constants(), rhs.fold_constants()) {
+                (Self::Literal(false), _) | (_, Self::Literal(true)) => Self::Literal(true),
+                (Self::Literal(true), rhs) => rhs,
+                (lhs, Self::Literal(false)) => Self::Not(Box::new(lhs)),
+                (lhs, rhs) => Self::Implies(Box::new(lhs), Box::new(rhs)),
+            },
+            Self::Not(only) => match only.fold_constants() {
+                Self::Literal(value) => Self::Literal(!value),
+                only => Self::Not(Box::new(only)),
+            },
+            Self::Equal(lhs, rhs) => {
+                fold_real_comparison(Self::Equal, |lhs, rhs| lhs == rhs, *lhs, *rhs)
+            }
+            Self::Greater(lhs, rhs) => {
+                fold_real_comparison(Self::Greater, |lhs, rhs| lhs > rhs, *lhs, *rhs)
+            }
+            Self::GreaterEqual(lhs, rhs) => {
+                fold_real_comparison(Self::GreaterEqual, |lhs, rhs| lhs >= rhs, *lhs, *rhs)
+            }
+            Self::Less(lhs, rhs) => {
+                fold_real_comparison(Self::Less, |lhs, rhs| lhs < rhs, *lhs, *rhs)
+            }
+            Self::LessEqual(lhs, rhs) => {
+                fold_real_comparison(Self::LessEqual, |lhs, rhs| lhs <= rhs, *lhs, *rhs)
+            }
+            Self::NotEqual(lhs, rhs) => {
+                fold_real_comparison(Self::NotEqual, |lhs, rhs| lhs != rhs, *lhs, *rhs)
+            }
+            Self::Between(value, lo, hi) => {
+                let (value, lo, hi) = (
+                    value.fold_constants(),
+                    lo.fold_constants(),
+                    hi.fold_constants(),
+                );
+                match (
+                    value.literal_value(),
+                    lo.literal_value(),
+                    hi.literal_value(),
+                ) {
+                    (Some(value), Some(lo), Some(hi)) => Self::Literal(lo <= value && value <= hi),
+                    _ => Self::Between(Box::new(value), Box::new(lo), Box::new(hi)),
+                }
+            }
+            Self::StrEqual(StringExpression::Literal(lhs), StringExpression::Literal(rhs)) => {
+                Self::Literal(lhs == rhs)
+            }
+            Self::StrNotEqual(StringExpression::Literal(lhs), StringExpression::Literal(rhs)) => {
+                Self::Literal(lhs != rhs)
+            }
+            Self::CoercedEqual(lhs, rhs) => Self::CoercedEqual(lhs, Box::new(rhs.fold_constants())),
+            Self::CoercedNotEqual(lhs, rhs) => {
+                Self::CoercedNotEqual(lhs, Box::new(rhs.fold_constants()))
+            }
+            Self::StrEqual(..) | Self::StrNotEqual(..) | Self::Literal(_) => self,
+        }
+    }
+
+    /// Applies [`RealExpression::optimize_arithmetic`] to every real operand.
+    pub fn optimize_arithmetic(self) -> Self {
+        self.map_real_operands(&RealExpression::optimize_arithmetic)
+    }
+
+    /// Applies [`RealExpression::lower_integer_powers`] to every real operand.
+    pub fn lower_integer_powers(self) -> Self {
+        self.map_real_operands(&RealExpression::lower_integer_powers)
+    }
+
+    /// Applies [`RealExpression::simplify`] to every real operand. The logic
+    /// itself is simplified by [`Self::fold_constants`].
+    pub fn simplify(self, fast_math: bool) -> Self {
+        self.map_real_operands(&|real| real.simplify(fast_math))
+    }
+
+    /// Rewrites every real operand with `f`, keeping the logic around them.
+    fn map_real_operands(self, f: &impl Fn(RealExpression<Real>) -> RealExpression<Real>) -> Self {
+        let map_bool = |e: Box<Self>| Box::new(e.map_real_operands(f));
+        let map_real = |e: Box<RealExpression<Real>>| Box::new(f(*e));
+        match self {
+            Self::And(lhs, rhs) => Self::And(map_bool(lhs), map_bool(rhs)),
+            Self::Or(lhs, rhs) => Self::Or(map_bool(lhs), map_bool(rhs)),
+            Self::Xor(lhs, rhs) => Self::Xor(map_bool(lhs), map_bool(rhs)),
+            Self::Implies(lhs, rhs) => Self::Implies(map_bool(lhs), map_bool(rhs)),
+            Self::Not(only) => Self::Not(map_bool(only)),
+            Self::Equal(lhs, rhs) => Self::Equal(map_real(lhs), map_real(rhs)),
+            Self::Greater(lhs, rhs) => Self::Greater(map_real(lhs), map_real(rhs)),
+            Self::GreaterEqual(lhs, rhs) => Self::GreaterEqual(map_real(lhs), map_real(rhs)),
+            Self::Less(lhs, rhs) => Self::Less(map_real(lhs), map_real(rhs)),
+            Self::LessEqual(lhs, rhs) => Self::LessEqual(map_real(lhs), map_real(rhs)),
+            Self::NotEqual(lhs, rhs) => Self::NotEqual(map_real(lhs), map_real(rhs)),
+            Self::Between(value, lo, hi) => {
+                Self::Between(map_real(value), map_real(lo), map_real(hi))
+            }
+            Self::CoercedEqual(lhs, rhs) => Self::CoercedEqual(lhs, map_real(rhs)),
+            Self::CoercedNotEqual(lhs, rhs) => Self::CoercedNotEqual(lhs, map_real(rhs)),
+            Self::StrEqual(..) | Self::StrNotEqual(..) | Self::Literal(_) => self,
+        }
+    }
+}
+
+fn fold_binary_real_op<Real: FloatExt>(
+    build: BinaryNode<RealExpression<Real>, RealExpression<Real>>,
+    op: fn(Real, Real) -> Real,
+    lhs: RealExpression<Real>,
+    rhs: RealExpression<Real>,
+) -> RealExpression<Real> {
+    let (lhs, rhs) = (lhs.fold_constants(), rhs.fold_constants());
+    let integers = matches!(
+        (&lhs, &rhs),
+        (RealExpression::IntLiteral(_), RealExpression::IntLiteral(_))
+    );
+    match (lhs.literal_value(), rhs.literal_value()) {
+        (Some(lhs), Some(rhs)) => {
+            let value = op(lhs, rhs);
+            match exact_integer(value) {
+                Some(n) if integers => RealExpression::IntLiteral(n),
+                _ => RealExpression::Literal(value),
+            }
+        }
+        _ => build(Box::new(lhs), Box::new(rhs)),
+    }
+}
+
+/// `value` as an `i32`, if that represents it exactly. `-0` is not exact.
+fn exact_integer<Real: FloatExt>(value: Real) -> Option<i32> {
+    if value.fract() != Real::zero() || (value == Real::zero() && value.is_sign_negative()) {
+        return None;
+    }
+    num_traits::cast(value)
+}
+
+fn fold_real_comparison<Real: FloatExt>(
+    build: BinaryNode<RealExpression<Real>, BoolExpression<Real>>,
+    op: fn(Real, Real) -> bool,
+    lhs: RealExpression<Real>,
+    rhs: RealExpression<Real>,
+) -> BoolExpression<Real> {
+    let (lhs, rhs) = (lhs.fold_constants(), rhs.fold_constants());
+    match (lhs.literal_value(), rhs.literal_value()) {
+        (Some(lhs), Some(rhs)) => BoolExpression::Literal(op(lhs, rhs)),
+        _ => build(Box::new(lhs), Box::new(rhs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn binding_map(var_name: &str) -> BindingId {
+        match var_name {
+            "x" => 0,
+            "y" => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn fold_real_literals() {
+        let parsed = Expression::<f64>::parse("x * (2 + 3) - -(4 ^ 0.5)", binding_map).unwrap();
+        let folded = parsed.unwrap_real().fold_constants();
+        let RealExpression::Sub(lhs, rhs) = folded else {
+            panic!("{folded:?}")
+        };
+        assert!(matches!(*lhs, RealExpression::Mul(_, _)), "{lhs:?}");
+        assert!(
+            matches!(*rhs, RealExpression::Literal(v) if v == -2.0),
+            "{rhs:?}"
+        );
+    }
+
+    #[test]
+    fn substitute_and_fold() {
+        let parsed = Expression::<f64>::parse("x * (y + 1) ^ 2", binding_map).unwrap();
+        let specialized = parsed.unwrap_real().substitute(1, 2.0).fold_constants();
+        let RealExpression::Mul(lhs, rhs) = &specialized else {
+            panic!("{specialized:?}")
+        };
+        assert!(matches!(**lhs, RealExpression::Binding(0)), "{lhs:?}");
+        assert!(
+            matches!(**rhs, RealExpression::Literal(v) if v == 9.0),
+            "{rhs:?}"
+        );
+
+        let mut registers = Registers::new(2);
+        let output = specialized.evaluate(&[[1.0, 2.0]], &mut registers);
+        assert_eq!(&output, &[9.0, 18.0]);
+    }
+
+    #[test]
+    fn optimize_division_by_literal() {
+        let parsed = Expression::<f64>::parse("(x + y) / 3 - y / 0", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let optimized = real.clone().optimize_arithmetic();
+        let RealExpression::Sub(lhs, rhs) = &optimized else {
+            panic!("{optimized:?}")
+        };
+        let RealExpression::Mul(_, reciprocal) = &**lhs else {
+            panic!("{lhs:?}")
+        };
+        assert!(
+            matches!(**reciprocal, RealExpression::Literal(v) if v == 1.0 / 3.0),
+            "{reciprocal:?}"
+        );
+        assert!(matches!(**rhs, RealExpression::Div(_, _)), "{rhs:?}");
+
+        let bindings = &[[1.0, 2.0, 3.0], [-4.0, 1.0, 4.0]];
+        let mut registers = Registers::new(3);
+        let expected = real.evaluate(bindings, &mut registers);
+        let output = optimized.evaluate(bindings, &mut registers);
+        for (o, e) in output.iter().zip(&expected) {
+            assert!((o - e).abs() <= 1e-12 * e.abs() || o == e, "{o} != {e}");
+        }
+
+        // The reciprocal of a subnormal literal overflows.
+        let parsed = Expression::<f64>::parse("x / 1e-310", binding_map).unwrap();
+        let optimized = parsed.unwrap_real().optimize_arithmetic();
+        assert!(
+            matches!(optimized, RealExpression::Div(..)),
+            "{optimized:?}"
+        );
+        assert_eq!(
+            optimized.evaluate(&[[0.0, 1e-300, -1e-300]], &mut registers),
+            [0.0, 1e-300 / 1e-310, -1e-300 / 1e-310]
+        );
+    }
+
+    #[test]
+    fn lower_power_of_two_to_exp2() {
+        let exp2 = Expression::<f64>::parse("exp2(3)", binding_map).unwrap();
+        let mut registers = Registers::new(1);
+        assert_eq!(
+            exp2.unwrap_real().evaluate_without_vars(&mut registers),
+            [8.0]
+        );
+
+        let parsed = Expression::<f64>::parse("2 ^ (x - y)", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let optimized = real.clone().optimize_arithmetic();
+        assert!(
+            matches!(optimized, RealExpression::Func1(Func1::Exp2, _)),
+            "{optimized:?}"
+        );
+
+        let bindings = &[[0.5, 2.0, -3.0], [0.0, -1.5, 7.25]];
+        let mut registers = Registers::new(3);
+        let expected = real.evaluate(bindings, &mut registers);
+        let output = optimized.evaluate(bindings, &mut registers);
+        for (o, e) in output.iter().zip(&expected) {
+            assert!((o - e).abs() <= 1e-12 * e.abs(), "{o} != {e}");
+        }
+    }
+
+    #[test]
+    fn fold_constant_guard_away() {
+        let parsed = Expression::<f64>::parse("(1 < 2) && x > 0", binding_map).unwrap();
+        let folded = parsed.unwrap_bool().fold_constants();
+        let BoolExpression::Greater(lhs, rhs) = folded else {
+            panic!("{folded:?}")
+        };
+        assert!(matches!(*lhs, RealExpression::Binding(0)), "{lhs:?}");
+        assert_eq!(*rhs, RealExpression::IntLiteral(0));
+    }
+
+    #[test]
+    fn fold_constant_logic() {
+        let parsed = Expression::<f64>::parse("(2 < 1) && x > 0", binding_map).unwrap();
+        let folded = parsed.unwrap_bool().fold_constants();
+        assert!(
+            matches!(folded, BoolExpression::Literal(false)),
+            "{folded:?}"
+        );
+
+        let parsed = Expression::<f64>::parse("x > y || !(2 < 1)", binding_map).unwrap();
+        let folded = parsed.unwrap_bool().fold_constants();
+        assert!(
+            matches!(folded, BoolExpression::Literal(true)),
+            "{folded:?}"
+        );
+
+        let mut registers = Registers::new(3);
+        let output =
+            folded.evaluate::<[_; 0], [_; 0]>(&[], &[], |_| unreachable!(), &mut registers);
+        assert_eq!([output[0], output[1], output[2]], [true, true, true]);
+    }
+
+    #[test]
+    fn lower_reciprocal_and_negation() {
+        let x = [0.5, -4.0, 3.0];
+        let recip = Expression::<f64>::parse("recip(x)", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let mut registers = Registers::new(x.len());
+        let expected = recip.evaluate(&[x], &mut registers);
+        assert_eq!(expected, [2.0, -0.25, 1.0 / 3.0]);
+
+        let parsed = Expression::<f64>::parse("1 / x", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let optimized = real.clone().optimize_arithmetic();
+        assert_eq!(optimized, recip);
+        let mut registers = Registers::new(x.len());
+        assert_eq!(real.evaluate(&[x], &mut registers), expected);
+        // The literal numerator is broadcast, so both only allocate the output.
+        assert_eq!(registers.num_allocations(), 1);
+        let mut registers = Registers::new(x.len());
+        assert_eq!(optimized.evaluate(&[x], &mut registers), expected);
+        assert_eq!(registers.num_allocations(), 1);
+
+        let parsed = Expression::<f64>::parse("0 - x", binding_map).unwrap();
+        let optimized = parsed.unwrap_real().optimize_arithmetic();
+        assert_eq!(
+            optimized,
+            RealExpression::Neg(Box::new(RealExpression::Binding(0)))
+        );
+    }
+
+    #[test]
+    fn integer_literals() {
+        let parse = |input: &str| {
+            Expression::<f64>::parse(input, binding_map)
+                .unwrap()
+                .unwrap_real()
+        };
+        let two = parse("2");
+        assert_eq!(two, RealExpression::IntLiteral(2));
+        assert_eq!(format!("{:?}", two.debug_truncated(1)), "IntLiteral(2)");
+        assert!(two.to_dot(|_| unreachable!()).contains("[label=\"2\"]"));
+        assert_eq!(parse("2.0"), RealExpression::Literal(2.0));
+        assert_eq!(parse("2e0"), RealExpression::Literal(2.0));
+
+        assert_eq!(parse("x ^ 2").integer_exponent(), Some(2));
+        assert_eq!(parse("x ^ -(3)").integer_exponent(), Some(-3));
+        assert_eq!(parse("x ^ 2.0").integer_exponent(), None);
+        assert_eq!(
+            parse("x ^ 2").lower_integer_powers(),
+            RealExpression::PowI(Box::new(RealExpression::Binding(0)), 2)
+        );
+        assert_eq!(parse("x ^ 2").optimize_arithmetic(), parse("x ^ 2"));
+        assert_eq!(parse("x ^ 2.0").lower_integer_powers(), parse("x ^ 2.0"));
+        assert!(matches!(
+            parse("2 ^ x").optimize_arithmetic(),
+            RealExpression::Func1(Func1::Exp2, _)
+        ));
+
+        let real = parse("(x + 1) * 3 / 2");
+        let x = [0.5, -4.0, 3.0];
+        let mut registers = Registers::new(x.len());
+        assert_eq!(real.evaluate(&[x], &mut registers), [2.25, -4.5, 6.0]);
+        assert_eq!(
+            real.fold_constants().evaluate(&[x], &mut registers),
+            [2.25, -4.5, 6.0]
+        );
+        assert_eq!(
+            parse("3 * 2 - 1").fold_constants(),
+            RealExpression::IntLiteral(5)
+        );
+        assert_eq!(
+            parse("-(2 ^ 3)").fold_constants(),
+            RealExpression::IntLiteral(-8)
+        );
+        assert_eq!(
+            parse("3 / 2").fold_constants(),
+            RealExpression::Literal(1.5)
+        );
+        assert_eq!(
+            parse("3.0 * 2").fold_constants(),
+            RealExpression::Literal(6.0)
+        );
+        assert_eq!(
+            parse("65536 * 65536").fold_constants(),
+            RealExpression::Literal(4294967296.0)
+        );
+        let negative_zero = parse("0 * -1").fold_constants();
+        assert!(
+            matches!(negative_zero, RealExpression::Literal(z) if z == 0.0 && z.is_sign_negative())
+        );
+    }
+
+    #[test]
+    fn simplify_identities() {
+        let simplify = |input: &str, fast_math: bool| {
+            Expression::<f64>::parse(input, binding_map)
+                .unwrap()
+                .unwrap_real()
+                .simplify(fast_math)
+        };
+        let x = RealExpression::Binding(0);
+        for input in ["x - 0", "x * 1", "1 * x", "x / 1", "x ^ 1"] {
+            assert_eq!(simplify(input, false), x, "{input}");
+        }
+        assert_eq!(simplify("(x - 0) * (1 * 1)", false), x);
+        assert_eq!(simplify("(x + y) ^ 0", false), RealExpression::Literal(1.0));
+
+        // Adding `+0` changes the sign of `-0`, so it is only removed with
+        // `fast_math`. Adding `-0` is always exact.
+        let negative_zero = RealExpression::Literal(-0.0);
+        let x_plus = |zero: RealExpression<f64>| {
+            RealExpression::Add(Box::new(x.clone()), Box::new(zero)).simplify(false)
+        };
+        assert_eq!(x_plus(negative_zero.clone()), x);
+        assert_ne!(x_plus(RealExpression::Literal(0.0)), x);
+        assert_eq!(
+            RealExpression::Sub(Box::new(x.clone()), Box::new(negative_zero)).simplify(true),
+            x
+        );
+        for input in ["x + 0", "0 + x"] {
+            assert_eq!(simplify(input, true), x, "{input}");
+            assert_ne!(simplify(input, false), x, "{input}");
+        }
+
+        for input in ["x * 0", "(x + y) - (x + y)"] {
+            assert_eq!(
+                simplify(input, true),
+                RealExpression::Literal(0.0),
+                "{input}"
+            );
+            assert_ne!(
+                simplify(input, false),
+                RealExpression::Literal(0.0),
+                "{input}"
+            );
+        }
+
+        // Real operands of conditions are rewritten too.
+        assert_eq!(
+            simplify(
+                "when !(x * 1 > 0) && between(x ^ 1, 0, y / 1) then x else y",
+                false
+            ),
+            simplify("when !(x > 0) && between(x, 0, y) then x else y", false)
+        );
+        let condition = |input: &str| {
+            Expression::<f64>::parse(input, binding_map)
+                .unwrap()
+                .unwrap_bool()
+        };
+        assert_eq!(
+            condition("x * 1 > y ^ 1").simplify(false),
+            condition("x > y")
+        );
+        assert_eq!(
+            condition("x / 2 > y ^ 2").optimize_arithmetic(),
+            condition("x * 0.5 > y ^ 2")
+        );
+        assert_eq!(
+            condition("x ^ 2 > y").lower_integer_powers(),
+            condition("powi(x, 2) > y")
+        );
+    }
+}
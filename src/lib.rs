@@ -33,9 +33,19 @@
 //! assert_eq!(&output, &[64.0, 100.0, 144.0]);
 //! ```
 
+mod cache;
+mod debug;
+mod display;
+mod dot;
+mod error;
 mod evaluate;
 mod expression;
+mod fold;
+mod one_shot;
 mod parse;
+mod profile;
+mod reduce;
+mod scalar;
 
 /// Uses the [`pest`] parsing expression grammar language.
 ///
@@ -44,22 +54,32 @@ mod parse;
 /// ```
 pub mod grammar_doc {}
 
+pub use cache::ExpressionCache;
+pub use debug::DebugTruncated;
+pub use error::*;
 pub use evaluate::*;
 pub use expression::*;
-pub use parse::ParseError;
+pub use one_shot::*;
+pub use parse::{NodeSpans, ParseError, ParseOptions, Parser};
+pub use profile::*;
+pub use reduce::*;
+pub use scalar::*;
 
 /// Pass to `Expression::parse` if the expression has no variables.
 pub fn empty_binding_map(_var_name: &str) -> BindingId {
     panic!("Empty binding map")
 }
 
-pub trait FloatExt: num_traits::Float + std::str::FromStr + Send + Sync {}
+/// A [`num_traits::Float`] real type, which supports every function and
+/// check that [`ExprScalar`] types may lack.
+pub trait FloatExt: num_traits::Float + ExprScalar {}
 impl FloatExt for f32 {}
 impl FloatExt for f64 {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::borrow::Cow;
 
     #[test]
     fn real_expression() {
@@ -84,6 +104,26 @@ mod tests {
         assert_eq!(registers.num_allocations(), 3);
     }
 
+    #[test]
+    fn unary_plus_and_minus() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("+x + -y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let x = [1.0, 2.0, 3.0];
+        let y = [4.0, 6.0, 8.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+        let output = real.evaluate(bindings, &mut registers);
+        assert_eq!(&output, &[-3.0, -4.0, -5.0]);
+    }
+
     #[test]
     fn real_op_precedence() {
         let mut registers = Registers::new(1);
@@ -124,7 +164,53 @@ mod tests {
         let mut registers = Registers::new(3);
         let output = bool.evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), &mut registers);
         assert_eq!([output[0], output[1], output[2]], [false, true, false]);
-        assert_eq!(registers.num_allocations(), 3);
+        assert_eq!(registers.num_allocations(), 2);
+    }
+
+    #[test]
+    fn bool_expression_as_real() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x > 0", binding_map).unwrap();
+        let bool = parsed.unwrap_bool();
+
+        let x = [-1.0, 0.0, 1.0];
+        let mut registers = Registers::new(3);
+        let output = bool.evaluate_real::<_, [_; 0]>(&[x], &[], |_| unreachable!(), &mut registers);
+        // `>` is strict, so zero maps to false.
+        assert_eq!(&output, &[0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bool_expression_run_length_encoded() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x > 0", binding_map).unwrap();
+        let bool = parsed.unwrap_bool();
+
+        let x = [1.0, 2.0, -1.0, 0.0, -3.0, 4.0];
+        let mut registers = Registers::new(x.len());
+        let runs = bool.evaluate_rle::<_, [_; 0]>(&[x], &[], |_| unreachable!(), &mut registers);
+        assert_eq!(runs, [(true, 2), (false, 3), (true, 1)]);
+
+        // Runs spanning several bit blocks.
+        let x: Vec<f64> = (0..300).map(|i| if i < 70 { -1.0 } else { 1.0 }).collect();
+        let mut registers = Registers::new(x.len());
+        let runs = bool.evaluate_rle::<_, [_; 0]>(&[&x], &[], |_| unreachable!(), &mut registers);
+        assert_eq!(runs, [(false, 70), (true, 230)]);
+
+        let mut registers = Registers::new(0);
+        let runs =
+            bool.evaluate_rle::<_, [_; 0]>(&[[0.0; 0]], &[], |_| unreachable!(), &mut registers);
+        assert!(runs.is_empty());
     }
 
     #[test]
@@ -158,7 +244,173 @@ mod tests {
             &mut registers,
         );
         assert_eq!([output[0], output[1], output[2]], [false, false, true]);
-        assert_eq!(registers.num_allocations(), 5);
+        assert_eq!(registers.num_allocations(), 4);
+    }
+
+    #[test]
+    fn raw_string_literals() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "path" => 0,
+                "r" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse(r#"path == r"C:\temp""#, binding_map).unwrap();
+        assert_eq!(parsed.string_literals(), [r"C:\temp"]);
+        let bool = parsed.unwrap_bool();
+
+        fn string_literal_id(value: &str) -> StringId {
+            match value {
+                r"C:\temp" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let path = [0, 1, 2];
+        let mut registers = Registers::new(3);
+        let output = bool.evaluate::<[f64; 0], _>(&[], &[path], string_literal_id, &mut registers);
+        assert_eq!([output[0], output[1], output[2]], [false, true, false]);
+
+        let parsed =
+            Expression::<f64>::parse(r###"path == r##"say "#hi"#""##"###, binding_map).unwrap();
+        assert_eq!(parsed.string_literals(), [r##"say "#hi"#""##]);
+
+        // `r` alone is still a variable.
+        let parsed = Expression::<f64>::parse(r#"r == "x""#, binding_map).unwrap();
+        assert_eq!(parsed.string_literals(), ["x"]);
+    }
+
+    #[test]
+    fn unknown_string_literal_is_an_error() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "foo" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("foo == \"a\" || foo == \"b\"", binding_map).unwrap();
+        let bool = parsed.unwrap_bool();
+        let string_literal_id = |value: &str| (value == "a").then_some(0);
+
+        let foo = [0, 1];
+        let mut registers = Registers::new(2);
+        let result =
+            bool.try_evaluate::<[f64; 0], _>(&[], &[foo], string_literal_id, &mut registers);
+        assert_eq!(result, Err(EvalError::UnknownStringLiteral("b".into())));
+
+        let parsed = Expression::<f64>::parse("foo == \"a\"", binding_map).unwrap();
+        let output = parsed
+            .unwrap_bool()
+            .try_evaluate::<[f64; 0], _>(&[], &[foo], string_literal_id, &mut registers)
+            .unwrap();
+        assert_eq!([output[0], output[1]], [true, false]);
+    }
+
+    #[test]
+    fn string_literals_for_interning() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "foo" => 0,
+                "bar" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed =
+            Expression::<f64>::parse("foo == \"foo_123\" && bar > 2", binding_map).unwrap();
+        assert_eq!(parsed.string_literals(), ["foo_123"]);
+
+        let parsed = Expression::<f64>::parse(
+            "foo != \"b\" || \"a\" == foo && !(foo == \"b\")",
+            binding_map,
+        )
+        .unwrap();
+        assert_eq!(parsed.string_literals(), ["a", "b"]);
+
+        let parsed = Expression::<f64>::parse("bar * 2", binding_map).unwrap();
+        assert!(parsed.string_literals().is_empty());
+    }
+
+    #[test]
+    fn comparison_matches_scalar_reference_at_every_length() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let less = Expression::parse("x < y", binding_map)
+            .unwrap()
+            .unwrap_bool();
+        let greater_equal = Expression::parse("x >= y", binding_map)
+            .unwrap()
+            .unwrap_bool();
+        // Lengths around multiples of the bit block size are the interesting
+        // ones.
+        for len in 0..=200 {
+            let x: Vec<f64> = (0..len).map(|i| ((i * 7) % 13) as f64).collect();
+            let y: Vec<f64> = (0..len).map(|i| ((i * 5) % 11) as f64).collect();
+            let bindings = &[x.as_slice(), y.as_slice()];
+            let expected: Vec<bool> = x.iter().zip(&y).map(|(x, y)| x < y).collect();
+
+            let mut registers = Registers::new(len);
+            // Recycle a register with the opposite bits set, so stale bits
+            // would show up in the output.
+            let output = greater_equal.evaluate::<_, [_; 0]>(
+                bindings,
+                &[],
+                |_| unreachable!(),
+                &mut registers,
+            );
+            assert_eq!(
+                output.iter().by_vals().collect::<Vec<_>>(),
+                expected.iter().map(|b| !b).collect::<Vec<_>>(),
+                "length {len}"
+            );
+            drop(output);
+            let output =
+                less.evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), &mut registers);
+            assert_eq!(
+                output.iter().by_vals().collect::<Vec<_>>(),
+                expected,
+                "length {len}"
+            );
+        }
+    }
+
+    #[test]
+    fn xor_and_implies_truth_tables() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let x = [0.0, 0.0, 1.0, 1.0];
+        let y = [0.0, 1.0, 0.0, 1.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(4);
+        let mut evaluate = |input: &str| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            let output = parsed.unwrap_bool().evaluate::<_, [_; 0]>(
+                bindings,
+                &[],
+                |_| unreachable!(),
+                &mut registers,
+            );
+            [output[0], output[1], output[2], output[3]]
+        };
+
+        assert_eq!(evaluate("x > 0 ^^ y > 0"), [false, true, true, false]);
+        assert_eq!(evaluate("x > 0 -> y > 0"), [true, true, false, true]);
+        // `->` binds looser than `&&`.
+        assert_eq!(
+            evaluate("x > 0 -> x > 0 && y > 0"),
+            [true, true, false, true]
+        );
+        // `^^` is not confused with `^`.
+        assert_eq!(evaluate("x ^ 2 > 0 ^^ y > 0"), [false, true, true, false]);
     }
 
     #[test]
@@ -189,32 +441,1572 @@ mod tests {
     }
 
     #[test]
-    fn real_bench() {
+    fn register_pool_shared_between_real_types() {
         fn binding_map(var_name: &str) -> BindingId {
             match var_name {
                 "x" => 0,
                 "y" => 1,
-                "z" => 2,
-                var => panic!("Unexpected variable: {var}"),
+                _ => unreachable!(),
             }
         }
-        let parsed = Expression::parse("(z + (z^2 - 4*x*y)^0.5) / (2*x)", binding_map).unwrap();
+        let bool_64 = Expression::<f64>::parse("x < y && y < 3", binding_map)
+            .unwrap()
+            .unwrap_bool();
+        let bool_32 = Expression::<f32>::parse("x < y && y < 3", binding_map)
+            .unwrap()
+            .unwrap_bool();
+        let real_64 = Expression::<f64>::parse("x * y + 1", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let real_32 = Expression::<f32>::parse("x * y + 1", binding_map)
+            .unwrap()
+            .unwrap_real();
+
+        let mut pool = RegisterPool::new(3);
+        for round in 0..3 {
+            let mut registers = pool.take::<f64>(2);
+            let bindings = &[[1.0, 2.0, 3.0], [2.0, 2.0, 4.0]];
+            let mask =
+                bool_64.evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), &mut registers);
+            assert_eq!([mask[0], mask[1], mask[2]], [true, false, false]);
+            registers.recycle_bool(mask);
+            let output = real_64.evaluate(bindings, &mut registers);
+            assert_eq!(output, [3.0, 5.0, 13.0]);
+            registers.recycle_real(output);
+            // Two bool registers and two real registers.
+            let expected_allocations = if round == 0 { 4 } else { 0 };
+            assert_eq!(registers.num_allocations(), expected_allocations);
+            pool.recycle(registers);
+
+            let mut registers = pool.take::<f32>(2);
+            let bindings = &[[1.0, 2.0, 3.0], [2.0, 2.0, 4.0]];
+            let mask =
+                bool_32.evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), &mut registers);
+            assert_eq!([mask[0], mask[1], mask[2]], [true, false, false]);
+            registers.recycle_bool(mask);
+            let output = real_32.evaluate(bindings, &mut registers);
+            assert_eq!(output, [3.0, 5.0, 13.0]);
+            registers.recycle_real(output);
+            // The bool registers allocated for `f64` are reused, but `f32`
+            // allocates its own real registers.
+            let expected_allocations = if round == 0 { 2 } else { 0 };
+            assert_eq!(registers.num_allocations(), expected_allocations);
+            pool.recycle(registers);
+        }
+
+        // Registers taken at the same time split the free registers.
+        let bindings = &[[1.0, 2.0, 3.0], [2.0, 2.0, 4.0]];
+        let mut first = pool.take::<f64>(1);
+        let mut second = pool.take::<f64>(1);
+        for registers in [&mut first, &mut second] {
+            let mask = bool_64.evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), registers);
+            registers.recycle_bool(mask);
+            assert_eq!(registers.num_allocations(), 1);
+        }
+    }
+
+    #[test]
+    fn register_pressure_predicts_allocations() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "bar" => 0,
+                "baz" => 1,
+                "foo" => 2,
+                _ => unreachable!(),
+            }
+        }
+        let bar = [1.0, 2.0, 3.0];
+        let baz = [4.0, 5.0, 6.0];
+        let foo = [7.0, 8.0, 9.0];
+        let bindings = &[bar, baz, foo];
+        for (input, expected) in [
+            ("foo + bar + baz + foo + bar + baz + foo + bar + baz", 2),
+            ("2 * (foo + bar) * -baz", 3),
+            ("foo", 1),
+            ("-foo * 2", 2),
+            ("(foo + bar) * (baz + (foo - bar) / (bar + baz))", 4),
+            ("max(sqrt(foo), bar ^ 2)", 3),
+            ("2 * foo", 1),
+            ("1 - (foo + bar)", 2),
+            ("2 / foo * 3", 2),
+            ("2 ^ 3", 2),
+        ] {
+            let real = Expression::parse(input, binding_map).unwrap().unwrap_real();
+            assert_eq!(real.max_register_pressure(), expected, "{input}");
+            let mut registers = Registers::new(3);
+            real.evaluate(bindings, &mut registers);
+            assert_eq!(registers.num_allocations(), expected, "{input}");
+
+            let mut registers = Registers::with_capacity(3, real.max_register_pressure());
+            real.evaluate(bindings, &mut registers);
+            assert_eq!(registers.num_allocations(), 0, "{input}");
+        }
+    }
+
+    #[test]
+    fn literal_operands_are_broadcast() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x * 2 + 1", binding_map).unwrap();
         let real = parsed.unwrap_real();
 
-        const LEN: i32 = 10_000_000;
-        let x: Vec<_> = (0..LEN).map(|i| i as f32).collect();
-        let y: Vec<_> = (0..LEN).map(|i| (LEN - i) as f32).collect();
-        let z: Vec<_> = (0..LEN).map(|i| ((LEN / 2) - i) as f32).collect();
-        let bindings = &[x, y, z];
+        let x = [1.0, 2.0, 3.0];
+        let bindings = &[x];
+        let mut registers = Registers::new(3);
+        let output = real.evaluate(bindings, &mut registers);
+        assert_eq!(&output, &[3.0, 5.0, 7.0]);
+        assert_eq!(registers.num_allocations(), 2);
 
-        let mut registers = Registers::new(LEN as usize);
-        let start = std::time::Instant::now();
-        let _output = real.evaluate(bindings, &mut registers);
-        let elapsed = start.elapsed().as_millis();
-        println!(
-            "Took {elapsed} ms, {} ns per element",
-            (1_000_000 * elapsed) / LEN as u128
+        let real = Expression::parse("1 - 2 / x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let mut registers = Registers::new(3);
+        let output = real.evaluate(bindings, &mut registers);
+        assert_eq!(&output, &[-1.0, 0.0, 1.0 - 2.0 / 3.0]);
+        assert_eq!(registers.num_allocations(), 2);
+    }
+
+    #[test]
+    fn implicit_multiplication() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let x = [1.0, 2.0, 3.0];
+        let y = [4.0, 5.0, 6.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+
+        let implicit = Expression::parse("2(x+1)", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let explicit = Expression::parse("2*(x+1)", binding_map)
+            .unwrap()
+            .unwrap_real();
+        assert_eq!(
+            implicit.evaluate(bindings, &mut registers),
+            explicit.evaluate(bindings, &mut registers)
         );
+
+        let implicit = Expression::parse("3x^2 - (x)(y)", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let output = implicit.evaluate(bindings, &mut registers);
+        assert_eq!(&output, &[-1.0, 2.0, 9.0]);
+
+        assert!(Expression::<f64>::parse("x(y)", binding_map).is_err());
+        assert!(Expression::<f64>::parse("2 (x)", binding_map).is_err());
+    }
+
+    #[test]
+    fn evaluate_with_wider_output_type() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f32>::parse("x * x + 1", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let x = [4097.0_f32, 0.1];
+        let bindings = &[x];
+        let mut registers = Registers::<f64>::new(2);
+        let output = real.evaluate_as::<f64, _>(bindings, &mut registers);
+        let reference: Vec<f64> = x
+            .iter()
+            .map(|&x| f64::from(x) * f64::from(x) + 1.0)
+            .collect();
+        assert_eq!(output, reference);
+
+        // The same calculation in f32 loses precision.
+        let mut registers = Registers::<f32>::new(2);
+        let narrow = real.evaluate(bindings, &mut registers);
+        assert_ne!(f64::from(narrow[0]), reference[0]);
+    }
+
+    #[test]
+    fn remap_bindings_to_new_layout() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "bar" => 0,
+                "baz" => 1,
+                "foo" => 2,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("2 * (foo + bar) * -baz", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let bar = [1.0, 2.0, 3.0];
+        let baz = [4.0, 5.0, 6.0];
+        let foo = [7.0, 8.0, 9.0];
+        let mut registers = Registers::new(3);
+        let expected = real.evaluate(&[bar, baz, foo], &mut registers);
+
+        let mut remapped = real.clone();
+        remapped.remap_bindings(|id| [2, 0, 1][id]);
+        let output = remapped.evaluate(&[baz, foo, bar], &mut registers);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn remap_string_bindings() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "foo" => 0,
+                "bar" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let mut parsed = Expression::parse("foo == \"foo_123\" && bar > 2", binding_map).unwrap();
+        parsed.remap_bindings(|id| id + 1);
+        let bool = parsed.unwrap_bool();
+
+        let real_bindings = &[[0.0; 3], [1.0, 2.0, 3.0]];
+        let string_bindings = &[[9; 3], [0, 1, 0]];
+        let mut registers = Registers::new(3);
+        let output = bool.evaluate(real_bindings, string_bindings, |_| 0, &mut registers);
+        assert_eq!([output[0], output[1], output[2]], [false, false, true]);
+    }
+
+    #[test]
+    fn donated_bindings_are_recycled() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let real = Expression::parse("x * y + x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let x = vec![1.0, 2.0, 3.0];
+        let y = vec![4.0, 5.0, 6.0];
+
+        let mut registers = Registers::new(3);
+        let output = real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(&output, &[5.0, 12.0, 21.0]);
+        let output = real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(&output, &[5.0, 12.0, 21.0]);
         assert_eq!(registers.num_allocations(), 3);
+
+        let mut registers = Registers::new(3);
+        let output = real.evaluate_owned(vec![x.clone(), y.clone()], &mut registers);
+        assert_eq!(&output, &[5.0, 12.0, 21.0]);
+        let output = real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(&output, &[5.0, 12.0, 21.0]);
+        assert_eq!(registers.num_allocations(), 2);
+
+        // A bare binding is handed back without copying.
+        let identity = Expression::parse("y", binding_map).unwrap().unwrap_real();
+        let mut registers = Registers::new(3);
+        let output = identity.evaluate_owned(vec![x, y], &mut registers);
+        assert_eq!(&output, &[4.0, 5.0, 6.0]);
+        assert_eq!(registers.num_allocations(), 0);
+    }
+
+    #[test]
+    fn non_finite_policy() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f32>::parse("x ^ 100 - 1", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let bindings = &[[2.0, -10.0, 10.0]];
+        let mut registers = Registers::new(3);
+
+        let output = real.evaluate(bindings, &mut registers);
+        assert_eq!(output[0], 2.0f32.powi(100) - 1.0);
+        assert_eq!(&output[1..], &[f32::INFINITY, f32::INFINITY]);
+        assert_eq!(real.try_evaluate(bindings, &mut registers), Ok(output));
+
+        registers.set_non_finite_policy(NonFinitePolicy::Saturate);
+        let output = real.evaluate(bindings, &mut registers);
+        assert_eq!(&output[1..], &[f32::MAX - 1.0, f32::MAX - 1.0]);
+
+        let parsed = Expression::<f32>::parse("-(x ^ 100)", binding_map).unwrap();
+        let output = parsed.unwrap_real().evaluate(bindings, &mut registers);
+        assert_eq!(&output[1..], &[f32::MIN, f32::MIN]);
+
+        registers.set_non_finite_policy(NonFinitePolicy::Report);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Err(EvalError::NonFinite { index: 1 })
+        );
+        assert_eq!(
+            real.try_evaluate(&[[2.0, 1.0, 0.5]], &mut registers),
+            Ok(vec![2.0f32.powi(100) - 1.0, 0.0, 0.5f32.powi(100) - 1.0])
+        );
+    }
+
+    #[test]
+    fn method_calls_match_prefix_calls() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let x = [-4.0, -1.0, 0.0, 2.25, 9.0];
+        let bindings = &[x];
+        let mut registers = Registers::new(x.len());
+        let evaluate = |input: &str, registers: &mut Registers<f64>| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            parsed.unwrap_real().evaluate(bindings, registers)
+        };
+
+        let expected = evaluate("sqrt(abs(x))", &mut registers);
+        assert_eq!(&expected, &[2.0, 1.0, 0.0, 1.5, 3.0]);
+        assert_eq!(evaluate("x.abs().sqrt()", &mut registers), expected);
+        assert_eq!(evaluate("abs(x).sqrt()", &mut registers), expected);
+        assert_eq!(evaluate("sqrt(x.abs())", &mut registers), expected);
+
+        // Method calls bind tighter than `^` and unary minus.
+        assert_eq!(
+            evaluate("2^x.abs() - 1", &mut registers),
+            evaluate("2^abs(x) - 1", &mut registers)
+        );
+        assert_eq!(
+            evaluate("-x.abs()", &mut registers),
+            &[-4.0, -1.0, -0.0, -2.25, -9.0]
+        );
+        assert_eq!(
+            evaluate("(x - 1).abs()", &mut registers),
+            &[5.0, 2.0, 1.0, 1.25, 8.0]
+        );
+        assert_eq!(
+            evaluate("2abs(x)", &mut registers),
+            &[8.0, 2.0, 0.0, 4.5, 18.0]
+        );
+
+        let err = Expression::<f64>::parse("x.foo()", binding_map).unwrap_err();
+        assert!(err.to_string().contains("unknown function `foo`"), "{err}");
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn evaluate_in_pool() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("2 * x + y ^ 2", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x: Vec<f64> = (0..10_000).map(f64::from).collect();
+        let y: Vec<f64> = (0..10_000).map(|i| f64::from(i) * 0.5).collect();
+        let bindings = &[x, y];
+        let mut registers = Registers::new(10_000);
+        let expected = real.evaluate(bindings, &mut registers);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let output = real.evaluate_in_pool(&pool, bindings, &mut registers);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn compare_string_variables() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "first_name" => 0,
+                "last_name" => 1,
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        fn variable_type(var_name: &str) -> ValueType {
+            match var_name {
+                "first_name" | "last_name" => ValueType::String,
+                _ => ValueType::Real,
+            }
+        }
+        let parse = |input| {
+            Expression::<f64>::parse_typed(input, binding_map, variable_type)
+                .unwrap()
+                .unwrap_bool()
+        };
+        let eq = parse("first_name == last_name");
+        assert!(matches!(eq, BoolExpression::StrEqual(..)), "{eq:?}");
+        let neq = parse("first_name != last_name");
+        assert!(matches!(neq, BoolExpression::StrNotEqual(..)), "{neq:?}");
+        assert!(matches!(parse("x == x"), BoolExpression::Equal(..)));
+
+        let first_name = [0, 1, 2];
+        let last_name = [0, 2, 2];
+        let string_bindings = &[first_name, last_name];
+        let mut registers = Registers::new(3);
+        let output =
+            eq.evaluate::<[f64; 0], _>(&[], string_bindings, |_| unreachable!(), &mut registers);
+        assert_eq!(
+            output.iter().by_vals().collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        let output =
+            neq.evaluate::<[f64; 0], _>(&[], string_bindings, |_| unreachable!(), &mut registers);
+        assert_eq!(
+            output.iter().by_vals().collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        let err =
+            Expression::<f64>::parse_typed("first_name < last_name", binding_map, variable_type)
+                .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("cannot apply `<` to a string and a string"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn coerce_strings_to_reals() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "code" => 0,
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        fn variable_type(var_name: &str) -> ValueType {
+            match var_name {
+                "code" => ValueType::String,
+                _ => ValueType::Real,
+            }
+        }
+        // Strict typing is the default.
+        let err =
+            Expression::<f64>::parse_typed("code == 5", binding_map, variable_type).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("cannot apply `==` to a string and a real"),
+            "{err}"
+        );
+
+        let parser = Parser::new().with_string_coercion();
+        let parse = |input| {
+            parser
+                .parse_typed::<f64>(input, binding_map, variable_type)
+                .unwrap()
+                .unwrap_bool()
+        };
+        let interned = ["4", "5", "6", "five", " 5.0"];
+        let evaluate = |expr: &BoolExpression<f64>, code: [StringId; 3]| {
+            let mut registers = Registers::new(3);
+            let output = expr.evaluate_coercing(
+                &[[5.0, 5.0, 6.0]],
+                &[code],
+                |_| unreachable!(),
+                |id| interned[id as usize],
+                &mut registers,
+            );
+            output.iter().by_vals().collect::<Vec<_>>()
+        };
+
+        let eq = parse("code == 5");
+        assert!(matches!(eq, BoolExpression::CoercedEqual(..)), "{eq:?}");
+        assert_eq!(evaluate(&eq, [0, 1, 2]), [false, true, false]);
+        // Unparseable strings are not equal to anything.
+        assert_eq!(evaluate(&eq, [3, 4, 3]), [false, true, false]);
+        assert_eq!(
+            evaluate(&parse("5 != code"), [0, 1, 2]),
+            [true, false, true]
+        );
+        assert_eq!(
+            evaluate(&parse("code == x"), [0, 1, 2]),
+            [false, true, true]
+        );
+    }
+
+    #[test]
+    fn missing_binding_is_an_error() {
+        let parsed = Expression::<f64>::parse("x * 2 + y", |name| match name {
+            "x" => 0,
+            _ => 2,
+        })
+        .unwrap();
+        let real = parsed.unwrap_real();
+        let mut registers = Registers::new(2);
+        let bindings = &[[1.0, 2.0], [3.0, 4.0]];
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Err(EvalError::MissingBinding(2))
+        );
+        assert_eq!(
+            real.try_evaluate(&[[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]], &mut registers),
+            Ok(vec![7.0, 10.0])
+        );
+        assert_eq!(
+            real.try_evaluate(&[&[1.0, 2.0][..], &[3.0], &[5.0, 6.0]], &mut registers),
+            Err(EvalError::BindingLengthMismatch {
+                binding: 1,
+                len: 1,
+                expected: 2
+            })
+        );
+
+        // Also for the real and string bindings of a boolean expression.
+        let parsed = Expression::<f64>::parse_typed(
+            "x > 1 && s == \"a\"",
+            |name| match name {
+                "x" => 2,
+                _ => 1,
+            },
+            |name| match name {
+                "s" => ValueType::String,
+                _ => ValueType::Real,
+            },
+        )
+        .unwrap();
+        let boolean = parsed.unwrap_bool();
+        let strings = &[[0, 0], [1, 1]];
+        let mut evaluate = |real_bindings: &[[f64; 2]], string_bindings: &[[StringId; 2]]| {
+            boolean.try_evaluate(real_bindings, string_bindings, |_| Some(1), &mut registers)
+        };
+        assert_eq!(
+            evaluate(bindings, strings),
+            Err(EvalError::MissingBinding(2))
+        );
+        assert_eq!(
+            evaluate(&[[0.0; 2], [0.0; 2], [1.0, 2.0]], &strings[..1]),
+            Err(EvalError::MissingBinding(1))
+        );
+        let mask = evaluate(&[[0.0; 2], [0.0; 2], [1.0, 2.0]], strings).unwrap();
+        assert_eq!([mask[0], mask[1]], [false, true]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing binding 2: only 2 bindings were provided")]
+    fn missing_binding_panics_clearly() {
+        let parsed = Expression::<f64>::parse("x + y", |name| match name {
+            "x" => 0,
+            _ => 2,
+        })
+        .unwrap();
+        let mut registers = Registers::new(2);
+        parsed
+            .unwrap_real()
+            .evaluate(&[[1.0, 2.0], [3.0, 4.0]], &mut registers);
+    }
+
+    #[test]
+    fn evaluate_auto_infers_length() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("x * y + 1", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x = [1.0, 2.0, 3.0];
+        let y = [4.0, 5.0, 6.0];
+        assert_eq!(real.evaluate_auto(&[&x, &y]), Ok(vec![5.0, 11.0, 19.0]));
+
+        assert_eq!(
+            real.evaluate_auto(&[&x[..], &y[..2]]),
+            Err(EvalError::BindingLengthMismatch {
+                binding: 1,
+                len: 2,
+                expected: 3
+            })
+        );
+
+        let parsed = Expression::<f64>::parse("2 ^ 3", empty_binding_map).unwrap();
+        let constant = parsed.unwrap_real().evaluate_auto::<[f64; 0]>(&[]);
+        assert_eq!(constant, Ok(vec![8.0]));
+    }
+
+    #[test]
+    fn coalesce_nan() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let x = [1.0, f64::NAN, 3.0];
+        let y = [f64::NAN, f64::NAN, 5.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+        let evaluate = |input: &str, registers: &mut Registers<f64>| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            parsed.unwrap_real().evaluate(bindings, registers)
+        };
+        assert_eq!(evaluate("coalesce(x, 0)", &mut registers), [1.0, 0.0, 3.0]);
+        assert_eq!(evaluate("x.coalesce(-1)", &mut registers), [1.0, -1.0, 3.0]);
+        assert_eq!(
+            evaluate("coalesce(y, coalesce(x, 2))", &mut registers),
+            [1.0, 2.0, 5.0]
+        );
+        assert_eq!(evaluate("max(x, 2)", &mut registers), [2.0, 2.0, 3.0]);
+        assert_eq!(evaluate("x.min(2)", &mut registers), [1.0, 2.0, 2.0]);
+
+        let err = Expression::<f64>::parse("coalesce(x)", binding_map).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("`coalesce` takes 2 argument(s) but 1 were given"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn activation_functions_saturate() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let x = [-1000.0, 0.0, 1000.0];
+        let mut registers = Registers::new(3);
+        let mut evaluate = |input: &str| {
+            let parsed = Expression::<f64>::parse(input, binding_map).unwrap();
+            parsed.unwrap_real().evaluate(&[x], &mut registers)
+        };
+        assert_eq!(evaluate("sigmoid(x)"), [0.0, 0.5, 1.0]);
+        assert_eq!(evaluate("tanh(x)"), [-1.0, 0.0, 1.0]);
+        assert_eq!(evaluate("relu(x)"), [0.0, 0.0, 1000.0]);
+        assert_eq!(
+            evaluate("softplus(x)"),
+            [0.0, std::f64::consts::LN_2, 1000.0]
+        );
+
+        // `f32` overflows `exp` much sooner.
+        let x = [-100.0f32, 100.0, 1.0];
+        let mut registers = Registers::new(3);
+        let parsed = Expression::parse("sigmoid(x) + softplus(x)", binding_map).unwrap();
+        let output = parsed.unwrap_real().evaluate(&[x], &mut registers);
+        assert!((0.0..1e-40).contains(&output[0]), "{output:?}");
+        assert_eq!(output[1], 101.0);
+        let expected = 1.0 / (1.0 + (-1.0f32).exp()) + 1.0f32.exp().ln_1p();
+        assert!((output[2] - expected).abs() < 1e-6, "{output:?}");
+    }
+
+    #[test]
+    fn integer_power() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let x: Vec<f64> = (0..50).map(|i| i as f64 * 0.37 - 9.0).collect();
+        let mut registers = Registers::new(x.len());
+        let evaluate = |input: &str, registers: &mut Registers<f64>| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            parsed.unwrap_real().evaluate(&[&x], registers)
+        };
+        assert_eq!(
+            evaluate("powi(x, 3)", &mut registers),
+            evaluate("x * x * x", &mut registers)
+        );
+        assert_eq!(
+            evaluate("x.powi(-2)", &mut registers),
+            evaluate("1 / (x * x)", &mut registers)
+        );
+
+        for input in ["powi(x, 1.5)", "powi(x, x)"] {
+            let err = Expression::<f64>::parse(input, binding_map).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("the exponent of `powi` must be an integer literal"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn piecewise_case() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        // The conditions overlap, so lanes below zero must take the first arm.
+        let parsed = Expression::parse(
+            "when x < 0 then -x when x < 10 then y else 100 + y",
+            binding_map,
+        )
+        .unwrap();
+        let real = parsed.unwrap_real();
+
+        let x: Vec<f64> = (0..150).map(|i| i as f64 / 5.0 - 10.0).collect();
+        let y: Vec<f64> = (0..150).map(|i| i as f64).collect();
+        let expected: Vec<f64> = x
+            .iter()
+            .zip(&y)
+            .map(|(&x, &y)| match x {
+                x if x < 0.0 => -x,
+                x if x < 10.0 => y,
+                _ => 100.0 + y,
+            })
+            .collect();
+        let mut registers = Registers::new(x.len());
+        assert_eq!(real.evaluate(&[&x, &y], &mut registers), expected);
+        let pressure = real.max_register_pressure();
+        let mut registers = Registers::new(x.len());
+        real.evaluate(&[&x, &y], &mut registers);
+        // One bool register is reused by every condition.
+        assert_eq!(registers.num_allocations(), pressure + 1);
+
+        let folded = Expression::<f64>::parse(
+            "when 1 > 2 then x when x < 0 then y when 2 > 1 then 3 else 4",
+            binding_map,
+        )
+        .unwrap()
+        .unwrap_real()
+        .fold_constants();
+        let RealExpression::Case(arms, otherwise) = &folded else {
+            panic!("{folded:?}")
+        };
+        assert_eq!(arms.len(), 1);
+        assert_eq!(**otherwise, RealExpression::IntLiteral(3));
+
+        let err =
+            Expression::<f64>::parse("when x == \"a\" then 1 else 2", binding_map).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("conditions of `when` cannot compare strings"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn custom_binary_function() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let mut functions = FunctionTable::new();
+        let satadd = functions.register("satadd", |a: f64, b| (a + b).clamp(-10.0, 10.0));
+        assert_eq!(functions.name(satadd), Some("satadd"));
+
+        let x = [1.0, 8.0, -9.0];
+        let y = [2.0, 7.0, -3.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+        registers.set_functions(&functions);
+        let evaluate = |input: &str, registers: &mut Registers<f64>| {
+            let parsed = Expression::parse_with_functions(input, binding_map, &functions).unwrap();
+            parsed.unwrap_real().evaluate(bindings, registers)
+        };
+        assert_eq!(evaluate("satadd(x, y)", &mut registers), [3.0, 10.0, -10.0]);
+        assert_eq!(
+            evaluate("x.satadd(y) * 2", &mut registers),
+            [6.0, 20.0, -20.0]
+        );
+        // Built-in functions are still available.
+        assert_eq!(evaluate("max(x, y)", &mut registers), [2.0, 8.0, -3.0]);
+
+        let parsed = Expression::parse_with_functions("satadd(x, y)", binding_map, &functions);
+        assert!(matches!(
+            parsed.unwrap().unwrap_real(),
+            RealExpression::Func2(Func2::Custom(id), _, _) if id == satadd
+        ));
+        let err = Expression::<f64>::parse("satadd(x, y)", binding_map).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown function `satadd`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn balanced_tree_matches_serial_evaluation() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        // Both halves are large enough to be evaluated concurrently.
+        let half = "(x * y + x ^ 2 - y / 3 + (x - y) * (x + y) - x * 0.5 + y ^ 0.5)";
+        let input = format!("{half} * ({half} - 1) + ({half} + 2) / {half}");
+        let real = Expression::parse(&input, binding_map)
+            .unwrap()
+            .unwrap_real();
+
+        let x: Vec<f64> = (0..1000).map(|i| f64::from(i) * 0.25).collect();
+        let y: Vec<f64> = (0..1000).map(|i| f64::from(i % 7) + 1.0).collect();
+        let mut registers = Registers::new(1000);
+        let (output, profile) = real.evaluate_profiled(&[&x, &y], &mut registers);
+
+        let expected: Vec<f64> = x
+            .iter()
+            .zip(&y)
+            .map(|(&x, &y)| {
+                let half =
+                    x * y + x.powf(2.0) - y / 3.0 + (x - y) * (x + y) - x * 0.5 + y.powf(0.5);
+                half * (half - 1.0) + (half + 2.0) / half
+            })
+            .collect();
+        assert_eq!(output, expected);
+        assert_eq!(profile.node("Add").unwrap().evaluations, 4 * 4 + 2);
+        // The root and both of its operands split their operands.
+        #[cfg(feature = "rayon")]
+        assert_eq!(profile.concurrent_subtrees, 3);
+        #[cfg(not(feature = "rayon"))]
+        assert_eq!(profile.concurrent_subtrees, 0);
+
+        // Registers of concurrently evaluated subtrees are returned to the pool,
+        // so once it is large enough, only the output is allocated.
+        for _ in 0..3 {
+            real.evaluate(&[&x, &y], &mut registers);
+        }
+        let num_allocations = registers.num_allocations();
+        real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(registers.num_allocations(), num_allocations + 1);
+
+        // Concurrent operands each need their own registers, so the pressure
+        // is the sum of theirs, and at least the two operand registers plus
+        // the output.
+        let pressure = real.max_register_pressure();
+        #[cfg(feature = "rayon")]
+        {
+            let RealExpression::Add(lhs, rhs) = &real else {
+                unreachable!()
+            };
+            let operands = lhs.max_register_pressure() + rhs.max_register_pressure();
+            assert_eq!(pressure, operands.max(3));
+        }
+        let mut registers = Registers::new(1000);
+        real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(registers.num_allocations(), pressure);
+        let mut registers = Registers::with_capacity(1000, pressure);
+        real.evaluate(&[&x, &y], &mut registers);
+        assert_eq!(registers.num_allocations(), 0);
+    }
+
+    #[test]
+    fn evaluate_borrowed_bare_binding() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let x = [1.0, 2.0];
+        let y = [3.0, 4.0];
+        let bindings: &[&[f64]] = &[&x, &y];
+        let mut registers = Registers::new(2);
+
+        let parsed = Expression::parse("y", binding_map).unwrap();
+        let output = parsed
+            .unwrap_real()
+            .evaluate_borrowed(bindings, &mut registers);
+        assert!(matches!(output, Cow::Borrowed(_)));
+        assert_eq!(&*output, &y);
+        assert_eq!(registers.num_allocations(), 0);
+
+        let parsed = Expression::parse("x + y", binding_map).unwrap();
+        let output = parsed
+            .unwrap_real()
+            .evaluate_borrowed(bindings, &mut registers);
+        assert!(matches!(output, Cow::Owned(_)));
+        assert_eq!(&*output, &[4.0, 6.0]);
+    }
+
+    #[test]
+    fn evaluate_subtree_by_path() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "bar" => 0,
+                "baz" => 1,
+                "foo" => 2,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("2 * (foo + bar) * baz", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let bar = [1.0, 2.0, 3.0];
+        let baz = [4.0, 5.0, 6.0];
+        let foo = [7.0, 8.0, 9.0];
+        let bindings = &[bar, baz, foo];
+        let mut registers = Registers::new(3);
+        let evaluate = |path: &[ChildIndex], registers: &mut Registers<f64>| {
+            real.evaluate_node_path(path, bindings, registers)
+        };
+        assert_eq!(evaluate(&[], &mut registers), [64.0, 100.0, 144.0]);
+        // The left operand of the root `Mul` is `2 * (foo + bar)`.
+        assert_eq!(evaluate(&[0], &mut registers), [16.0, 20.0, 24.0]);
+        assert_eq!(evaluate(&[0, 1], &mut registers), [8.0, 10.0, 12.0]);
+        assert_eq!(evaluate(&[0, 1, 0], &mut registers), foo);
+        assert_eq!(evaluate(&[1], &mut registers), baz);
+
+        assert!(real.node_at_path(&[2]).is_none());
+        assert!(real.node_at_path(&[1, 0]).is_none());
+    }
+
+    #[test]
+    fn absolute_value_bars() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let x = [-3.0, -1.0, 0.0, 2.0, 5.0];
+        let y = [1.0, -2.0, 4.0, -4.0, 2.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(x.len());
+        let evaluate = |input: &str, registers: &mut Registers<f64>| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            parsed.unwrap_real().evaluate(bindings, registers)
+        };
+        assert_eq!(
+            evaluate("|x - y|", &mut registers),
+            evaluate("abs(x - y)", &mut registers)
+        );
+        assert_eq!(
+            evaluate("|x - y|", &mut registers),
+            [4.0, 1.0, 4.0, 6.0, 3.0]
+        );
+        assert_eq!(
+            evaluate("||x| - |y||", &mut registers),
+            evaluate("abs(abs(x) - abs(y))", &mut registers)
+        );
+        assert_eq!(
+            evaluate("2 * |x| + 1", &mut registers),
+            [7.0, 3.0, 1.0, 5.0, 11.0]
+        );
+
+        let parsed = Expression::<f64>::parse("|x| > 1 || |y| > 3", binding_map).unwrap();
+        let output = parsed.unwrap_bool().evaluate::<_, [_; 0]>(
+            bindings,
+            &[],
+            |_| unreachable!(),
+            &mut registers,
+        );
+        assert_eq!(
+            output.iter().by_vals().collect::<Vec<_>>(),
+            [true, false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn between_is_inclusive() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let x = [0.0, 5.0, 10.0];
+        let bindings = &[x];
+        let mut registers = Registers::new(x.len());
+        let mut evaluate = |input: &str| {
+            let parsed = Expression::parse(input, binding_map).unwrap();
+            let output = parsed.unwrap_bool().evaluate::<_, [_; 0]>(
+                bindings,
+                &[],
+                |_| unreachable!(),
+                &mut registers,
+            );
+            [output[0], output[1], output[2]]
+        };
+
+        assert_eq!(evaluate("between(x, 1, 9)"), [false, true, false]);
+        assert_eq!(evaluate("between(x, 0, 10)"), [true, true, true]);
+        assert_eq!(evaluate("x.between(5, 10)"), [false, true, true]);
+        // An empty range contains nothing.
+        assert_eq!(evaluate("between(x, 9, 1)"), [false, false, false]);
+        assert_eq!(evaluate("x > 0 && !between(x, 1, 9)"), [false, false, true]);
+
+        // The value operand is only evaluated once.
+        let parsed = Expression::parse("between(2 * x, 2, 18)", binding_map).unwrap();
+        let mut registers = Registers::new(x.len());
+        registers.enable_profiling();
+        let output = parsed.unwrap_bool().evaluate::<_, [_; 0]>(
+            bindings,
+            &[],
+            |_| unreachable!(),
+            &mut registers,
+        );
+        assert_eq!(
+            output.iter().by_vals().collect::<Vec<_>>(),
+            [false, true, false]
+        );
+        let profile = registers.take_profile().unwrap();
+        assert_eq!(profile.node("Mul").unwrap().evaluations, 1);
+        assert_eq!(profile.node("Between").unwrap().evaluations, 1);
+    }
+
+    #[test]
+    fn evaluate_interleaved_bindings() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                "z" => 2,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x * z - y ^ 2", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let x = [1.0, 2.0, 3.0, 4.0];
+        let y = [5.0, 6.0, 7.0, 8.0];
+        let z = [9.0, 10.0, 11.0, 12.0];
+        let data: Vec<f64> = (0..4).flat_map(|row| [x[row], y[row], z[row]]).collect();
+        let mut registers = Registers::new(4);
+        let expected = real.evaluate(&[x, y, z], &mut registers);
+        assert_eq!(
+            real.evaluate_interleaved(&data, 3, &mut registers),
+            expected
+        );
+
+        let parsed = Expression::parse("y", binding_map).unwrap();
+        let output = parsed
+            .unwrap_real()
+            .evaluate_interleaved(&data, 3, &mut registers);
+        assert_eq!(output, y);
+
+        // Without any rows, every binding is empty.
+        let mut registers = Registers::new(0);
+        assert_eq!(
+            real.evaluate_interleaved(&[], 3, &mut registers),
+            Vec::<f64>::new()
+        );
+    }
+
+    #[test]
+    fn cancel_evaluation() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        // Cancels as soon as it sees the first row.
+        fn cancel_at_zero(x: f64, _: f64) -> f64 {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            if x == 0.0 {
+                CANCEL.store(true, Ordering::Relaxed);
+            }
+            x
+        }
+        let mut functions = FunctionTable::new();
+        functions.register("cancel_at_zero", cancel_at_zero);
+        let real =
+            Expression::parse_with_functions("cancel_at_zero(x, 0) + 1", binding_map, &functions)
+                .unwrap()
+                .unwrap_real();
+
+        const LEN: usize = 1_000_000;
+        let x: Vec<f64> = (0..LEN).map(|i| i as f64).collect();
+        let mut registers = Registers::new(LEN);
+        registers.set_functions(&functions);
+        assert_eq!(
+            real.evaluate_cancellable(&[&x], &CANCEL, &mut registers),
+            None
+        );
+        assert!(CALLS.load(Ordering::Relaxed) < LEN);
+
+        CANCEL.store(false, Ordering::Relaxed);
+        let x: Vec<f64> = (1..=LEN).map(|i| i as f64).collect();
+        let allocations = registers.num_allocations();
+        let output = real.evaluate_cancellable(&[&x], &CANCEL, &mut registers);
+        // The output register is reused, and only the chunk-sized registers
+        // are allocated, once for all chunks.
+        assert_eq!(
+            registers.num_allocations() - allocations,
+            real.max_register_pressure()
+        );
+        assert_eq!(output, Some(real.evaluate(&[&x], &mut registers)));
+    }
+
+    #[test]
+    fn evaluate_iterator_bindings() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                "z" => 2,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x * z - x / 2", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let x: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        let z: Vec<f64> = (0..100).map(|i| (i as f64).sqrt()).collect();
+        let mut registers = Registers::new(100);
+        let expected = real.evaluate(&[&x, &z, &z], &mut registers);
+
+        // `y` is not referenced, so its unbounded iterator is never advanced.
+        let lazy: Vec<Box<dyn Iterator<Item = f64>>> = vec![
+            Box::new((0..).map(|i| i as f64)),
+            Box::new(std::iter::repeat_with(|| unreachable!())),
+            Box::new((0..).map(|i| (i as f64).sqrt())),
+        ];
+        assert_eq!(real.evaluate_iter(lazy, &mut registers), expected);
+    }
+
+    #[test]
+    fn tuple_expression() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("(x + y, x - y, 2)", binding_map).unwrap();
+        let tuple = parsed.unwrap_tuple();
+        assert_eq!(tuple.elements.len(), 3);
+
+        let x = [1.0, 2.0, 3.0];
+        let y = [4.0, 6.0, 8.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+        let outputs = tuple.evaluate(bindings, &mut registers);
+        for (element, output) in ["x + y", "x - y", "2"].into_iter().zip(&outputs) {
+            let real = Expression::parse(element, binding_map)
+                .unwrap()
+                .unwrap_real();
+            assert_eq!(output, &real.evaluate(bindings, &mut registers));
+        }
+
+        // A parenthesized expression is not a tuple.
+        let parsed = Expression::<f64>::parse("(x + y)", binding_map).unwrap();
+        assert_eq!(parsed.value_type(), ValueType::Real);
+    }
+
+    #[test]
+    fn batch_into_column_major_matrix() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let exprs = [
+            Expression::parse("x + y", binding_map)
+                .unwrap()
+                .unwrap_real(),
+            Expression::parse("x * y", binding_map)
+                .unwrap()
+                .unwrap_real(),
+        ];
+
+        let x = [1.0, 2.0, 3.0];
+        let y = [4.0, 5.0, 6.0];
+        let bindings = &[x, y];
+        let mut registers = Registers::new(3);
+        let mut out = [0.0; 6];
+        evaluate_batch_into(&exprs, bindings, &mut registers, &mut out, 3);
+        assert_eq!(out, [5.0, 7.0, 9.0, 4.0, 10.0, 18.0]);
+        assert_eq!(registers.num_allocations(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn batch_into_checks_output_length() {
+        let exprs = [Expression::<f64>::parse("1", empty_binding_map)
+            .unwrap()
+            .unwrap_real()];
+        let mut registers = Registers::new(3);
+        let mut out = [0.0; 2];
+        evaluate_batch_into::<_, [_; 0]>(&exprs, &[], &mut registers, &mut out, 3);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn many_expressions_in_parallel() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let exprs: Vec<_> = ["x + y", "x * y", "x - y ^ 2", "sqrt(x) + 1", "y / x"]
+            .into_iter()
+            .map(|source| {
+                Expression::<f64>::parse(source, binding_map)
+                    .unwrap()
+                    .unwrap_real()
+            })
+            .collect();
+        let x: Vec<_> = (1..=100).map(f64::from).collect();
+        let y: Vec<_> = (1..=100).map(|i| f64::from(i) * 0.5).collect();
+        let bindings = &[x, y];
+
+        let mut registers = Registers::new(100);
+        let serial: Vec<_> = exprs
+            .iter()
+            .map(|expr| expr.evaluate(bindings, &mut registers))
+            .collect();
+        assert_eq!(evaluate_many_parallel(&exprs, bindings), serial);
+    }
+
+    #[test]
+    fn real_bench() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                "z" => 2,
+                var => panic!("Unexpected variable: {var}"),
+            }
+        }
+        let parsed = Expression::parse("(z + (z^2 - 4*x*y)^0.5) / (2*x)", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        const LEN: i32 = 10_000_000;
+        let x: Vec<_> = (0..LEN).map(|i| i as f32).collect();
+        let y: Vec<_> = (0..LEN).map(|i| (LEN - i) as f32).collect();
+        let z: Vec<_> = (0..LEN).map(|i| ((LEN / 2) - i) as f32).collect();
+        let bindings = &[x, y, z];
+
+        let mut registers = Registers::new(LEN as usize);
+        let start = std::time::Instant::now();
+        let _output = real.evaluate(bindings, &mut registers);
+        let elapsed = start.elapsed().as_millis();
+        println!(
+            "Took {elapsed} ms, {} ns per element",
+            (1_000_000 * elapsed) / LEN as u128
+        );
+        assert_eq!(registers.num_allocations(), 3);
+    }
+
+    #[test]
+    fn evaluate_over_grid() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("10 * x + y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let xs = [1.0, 2.0, 3.0];
+        let ys = [0.25, 0.5, 0.75];
+        let mut registers = Registers::new(9);
+        let output = real.evaluate_grid(0, &xs, 1, &ys, &mut registers);
+
+        let x_column = [1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 3.0, 3.0, 3.0];
+        let y_column = [0.25, 0.5, 0.75, 0.25, 0.5, 0.75, 0.25, 0.5, 0.75];
+        let expected = real.evaluate(&[x_column, y_column], &mut registers);
+        assert_eq!(output, expected);
+        assert_eq!(output[5], 20.75);
+    }
+
+    #[test]
+    fn bool_expression_indices() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x < 0.3", binding_map).unwrap();
+        let bool_expr = parsed.unwrap_bool();
+
+        // A linear congruential generator is random enough for a mask.
+        let mut state = 12345u64;
+        let x: Vec<f64> = (0..10_000)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 11) as f64 / (1u64 << 53) as f64
+            })
+            .collect();
+        let expected: Vec<usize> = (0..x.len()).filter(|&i| x[i] < 0.3).collect();
+
+        let mut registers = Registers::new(x.len());
+        let indices =
+            bool_expr.evaluate_indices::<_, [_; 0]>(&[&x], &[], |_| unreachable!(), &mut registers);
+        assert_eq!(indices, expected);
+    }
+
+    #[test]
+    fn bool_expression_bytes() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("x < 100 || x > 900", binding_map).unwrap();
+        let bool_expr = parsed.unwrap_bool();
+        // Not a multiple of the bits in a block.
+        let x: Vec<f64> = (0..1001).map(f64::from).collect();
+
+        let mut registers = Registers::new(x.len());
+        let mask = bool_expr.evaluate::<_, [_; 0]>(&[&x], &[], |_| unreachable!(), &mut registers);
+        let mut bytes = vec![2; x.len()];
+        bool_expr.evaluate_bytes_into::<_, [_; 0]>(
+            &[&x],
+            &[],
+            |_| unreachable!(),
+            &mut registers,
+            &mut bytes,
+        );
+        assert!(bytes.iter().any(|&b| b == 1));
+        assert!(bytes
+            .iter()
+            .zip(mask.iter())
+            .all(|(&b, bit)| b == u8::from(*bit)));
+    }
+
+    #[test]
+    fn evaluate_selected_rows() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("x + y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x = [1.0, 2.0, 3.0];
+        let y = [10.0, 20.0, 30.0];
+        let indices = [0, 2];
+        let mut registers = Registers::new(indices.len());
+        let output = real.evaluate_gather(&[x, y], &indices, &mut registers);
+
+        let gathered = [[x[0], x[2]], [y[0], y[2]]];
+        assert_eq!(output, real.evaluate(&gathered, &mut registers));
+        assert_eq!(output, [11.0, 33.0]);
+    }
+
+    #[test]
+    fn evaluate_into_arc() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("2 * x + 1", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x = [1.0, 2.0, 3.0];
+        let mut registers = Registers::new(x.len());
+        let shared = real.evaluate_arc(&[x], &mut registers);
+        let num_allocations = registers.num_allocations();
+        assert_eq!(*shared, *real.evaluate(&[x], &mut registers));
+
+        // The output register was recycled, so evaluating again did not
+        // allocate.
+        assert_eq!(registers.num_allocations(), num_allocations);
+    }
+
+    #[test]
+    fn logic_chain_accumulates_in_place() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse(
+            "x > 0 && x > 1 && x > 2 && x > 3 && x > 4 && x > 5",
+            binding_map,
+        )
+        .unwrap();
+        let bool = parsed.unwrap_bool();
+        let x = [6.0, 3.0, 0.0];
+        let mut registers = Registers::new(x.len());
+        let output = bool.evaluate::<_, [_; 0]>(&[x], &[], |_| unreachable!(), &mut registers);
+        assert_eq!([output[0], output[1], output[2]], [true, false, false]);
+        // One bool register accumulates the conjunction while another holds
+        // each comparison in turn, plus a real register for the literals.
+        assert_eq!(registers.num_allocations(), 3);
+    }
+
+    #[test]
+    fn short_bindings_use_defaults() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("x + y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x: &[f64] = &[1.0, 2.0, 3.0];
+        let y: &[f64] = &[10.0, 20.0];
+        let mut registers = Registers::new(3);
+        let output = real.evaluate_with_defaults(&[(x, -1.0), (y, 0.0)], &mut registers);
+        assert_eq!(output, [11.0, 22.0, 3.0]);
+    }
+
+    #[test]
+    fn validate_against_schema() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "name" => 1,
+                "y" => 2,
+                _ => unreachable!(),
+            }
+        }
+        fn schema(binding: BindingId) -> Option<ValueType> {
+            match binding {
+                0 => Some(ValueType::Real),
+                1 => Some(ValueType::String),
+                _ => None,
+            }
+        }
+
+        let parsed = Expression::<f64>::parse("x * 2 < 3 && name == \"a\"", binding_map).unwrap();
+        assert_eq!(parsed.validate(schema), Ok(ValueType::Boolean));
+        let parsed = Expression::<f64>::parse("(x, x + 1)", binding_map).unwrap();
+        assert_eq!(parsed.validate(schema), Ok(ValueType::Tuple));
+
+        let parsed = Expression::<f64>::parse("name + 1", binding_map).unwrap();
+        assert_eq!(
+            parsed.validate(schema),
+            Err(ValidationError::BindingType {
+                binding: 1,
+                expected: ValueType::Real,
+                found: ValueType::String
+            })
+        );
+        let parsed = Expression::<f64>::parse("x + y", binding_map).unwrap();
+        assert_eq!(
+            parsed.validate(schema),
+            Err(ValidationError::UnknownBinding(2))
+        );
+    }
+
+    #[test]
+    fn power_overflow_check() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f32>::parse("x ^ 2 + 1", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let bindings = &[[1.0, 1e30, 2.0, f32::INFINITY]];
+        let mut registers = Registers::new(4);
+
+        assert!(real.try_evaluate(bindings, &mut registers).is_ok());
+
+        registers.set_check_power_overflow(true);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Err(EvalError::Overflow { index: 1 })
+        );
+        assert_eq!(
+            real.try_evaluate(&[[1.0, 2.0, 3.0, f32::INFINITY]], &mut registers),
+            Ok(vec![2.0, 5.0, 10.0, f32::INFINITY])
+        );
+
+        let parsed = Expression::<f32>::parse("x ^ y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        assert_eq!(
+            real.try_evaluate(&[[2.0; 4], [1.0, 2.0, 1000.0, 1.0]], &mut registers),
+            Err(EvalError::Overflow { index: 2 })
+        );
+    }
+
+    #[test]
+    fn memory_budget() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::<f64>::parse("(x + y) * (x - y) + x * y", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let x = [1.0; 100];
+        let bindings = &[x, x];
+        let needed = real.max_register_pressure() * 100 * 8;
+        assert!(needed > 800);
+
+        let mut registers = Registers::with_budget(100, needed - 1);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Err(EvalError::MemoryBudgetExceeded {
+                needed,
+                budget: needed - 1
+            })
+        );
+        // The register that would exceed the budget is never allocated.
+        assert!(registers.num_allocations() < real.max_register_pressure());
+
+        let mut registers = Registers::with_budget(100, needed);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Ok(vec![1.0; 100])
+        );
+        // The budget applies to each evaluation.
+        assert!(real.try_evaluate(bindings, &mut registers).is_ok());
+
+        // Boolean registers are counted while evaluating.
+        let parsed = Expression::<f64>::parse("x > y || y > x", binding_map).unwrap();
+        let bool_expr = parsed.unwrap_bool();
+        let mut registers = Registers::with_budget(100, 20);
+        let result =
+            bool_expr.try_evaluate::<_, [_; 0]>(bindings, &[], |_| unreachable!(), &mut registers);
+        assert!(
+            matches!(
+                result,
+                Err(EvalError::MemoryBudgetExceeded { needed, budget: 20 }) if needed > 20
+            ),
+            "{result:?}"
+        );
+
+        // Checking powers for overflow copies every operand into a register.
+        let parsed = Expression::<f64>::parse("(x + y) ^ 2", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        let mut registers = Registers::with_budget(100, 2 * 800);
+        registers.set_check_power_overflow(true);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Err(EvalError::MemoryBudgetExceeded {
+                needed: 3 * 800,
+                budget: 2 * 800
+            })
+        );
+        let mut registers = Registers::with_budget(100, 3 * 800);
+        registers.set_check_power_overflow(true);
+        assert_eq!(
+            real.try_evaluate(bindings, &mut registers),
+            Ok(vec![4.0; 100])
+        );
     }
 }
@@ -0,0 +1,150 @@
+use crate::{BindingId, Expression, FloatExt, ParseError, Registers, ValueType};
+use std::cell::RefCell;
+use std::fmt;
+
+/// An error returned by [`eval_real`].
+#[derive(Debug)]
+pub enum EvalRealError {
+    Parse(ParseError),
+    /// The same variable name was bound more than once.
+    DuplicateVariable(String),
+    /// The expression reads a variable that was not bound.
+    UnknownVariable(String),
+    /// The bound variables do not all have the same length.
+    LengthMismatch {
+        variable: String,
+        len: usize,
+        expected: usize,
+    },
+    /// The expression does not produce real values.
+    NotReal(ValueType),
+}
+
+impl fmt::Display for EvalRealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::DuplicateVariable(name) => write!(f, "variable `{name}` is bound more than once"),
+            Self::UnknownVariable(name) => write!(f, "variable `{name}` is not bound"),
+            Self::LengthMismatch {
+                variable,
+                len,
+                expected,
+            } => write!(
+                f,
+                "variable `{variable}` has length {len}, expected {expected}"
+            ),
+            Self::NotReal(value_type) => {
+                write!(f, "expected a real expression, found a {value_type}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalRealError {}
+
+impl From<ParseError> for EvalRealError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+/// Parses `input` and evaluates it with the named `bindings` in one call.
+///
+/// Allocates fresh [`Registers`], so prefer [`Expression::parse`] and
+/// [`RealExpression::evaluate`](crate::RealExpression::evaluate) when
+/// evaluating repeatedly. Without any bindings, the output has a single
+/// element.
+///
+/// ```rust
+/// let bar = [1.0, 2.0, 3.0];
+/// let baz = [4.0, 5.0, 6.0];
+/// let foo = [7.0, 8.0, 9.0];
+/// let output = vector_expr::eval_real(
+///     "2 * (foo + bar) * baz",
+///     &[("bar", &bar), ("baz", &baz), ("foo", &foo)],
+/// )
+/// .unwrap();
+/// assert_eq!(&output, &[64.0, 100.0, 144.0]);
+/// ```
+pub fn eval_real<Real: FloatExt, R: AsRef<[Real]>>(
+    input: &str,
+    bindings: &[(&str, R)],
+) -> Result<Vec<Real>, EvalRealError> {
+    let expected = bindings
+        .first()
+        .map_or(1, |(_, values)| values.as_ref().len());
+    for (i, (name, values)) in bindings.iter().enumerate() {
+        if bindings[..i].iter().any(|(other, _)| other == name) {
+            return Err(EvalRealError::DuplicateVariable(name.to_string()));
+        }
+        let len = values.as_ref().len();
+        if len != expected {
+            return Err(EvalRealError::LengthMismatch {
+                variable: name.to_string(),
+                len,
+                expected,
+            });
+        }
+    }
+
+    let unknown = RefCell::new(None);
+    let binding_map = |var_name: &str| -> BindingId {
+        bindings
+            .iter()
+            .position(|(name, _)| *name == var_name)
+            .unwrap_or_else(|| {
+                unknown
+                    .borrow_mut()
+                    .get_or_insert_with(|| var_name.to_string());
+                0
+            })
+    };
+    let parsed = Expression::parse(input, binding_map)?;
+    if let Some(name) = unknown.into_inner() {
+        return Err(EvalRealError::UnknownVariable(name));
+    }
+    let Expression::Real(real) = parsed else {
+        return Err(EvalRealError::NotReal(parsed.value_type()));
+    };
+
+    let values: Vec<&[Real]> = bindings.iter().map(|(_, values)| values.as_ref()).collect();
+    let mut registers = Registers::new(expected);
+    Ok(real.evaluate(&values, &mut registers))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn eval_real_errors() {
+        let a = [1.0, 2.0];
+        let b = [3.0];
+        assert!(matches!(
+            eval_real("a + c", &[("a", &a[..]), ("b", &a[..])]),
+            Err(EvalRealError::UnknownVariable(name)) if name == "c"
+        ));
+        assert!(matches!(
+            eval_real("a", &[("a", &a[..]), ("a", &a[..])]),
+            Err(EvalRealError::DuplicateVariable(name)) if name == "a"
+        ));
+        assert!(matches!(
+            eval_real("a + b", &[("a", &a[..]), ("b", &b[..])]),
+            Err(EvalRealError::LengthMismatch {
+                len: 1,
+                expected: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval_real("a > 1", &[("a", &a[..])]),
+            Err(EvalRealError::NotReal(ValueType::Boolean))
+        ));
+        assert!(matches!(
+            eval_real::<f64, &[f64]>("1 +", &[]),
+            Err(EvalRealError::Parse(_))
+        ));
+        assert_eq!(eval_real::<f64, &[f64]>("1 + 2", &[]).unwrap(), vec![3.0]);
+    }
+}
@@ -1,12 +1,18 @@
-use crate::expression::{BindingId, BoolExpression, Expression, RealExpression};
-use crate::StringExpression;
+use crate::expression::{
+    BinaryNode, BindingId, BoolExpression, ChildIndex, Expression, RealExpression,
+};
+use crate::{
+    CustomFunctionId, Func1, Func2, FunctionTable, StringExpression, TupleExpression, ValueType,
+};
 use num_traits::Float;
 use once_cell::sync::Lazy;
-use pest::iterators::Pairs;
+use pest::error::ErrorVariant;
+use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::{Assoc, Op, PrattParser};
-use pest::Parser;
+use pest::{Parser as _, Position};
 use pest_derive::Parser;
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 #[derive(Parser)]
@@ -16,6 +22,35 @@ struct ExpressionParser;
 // Boxed because error is much larger than Ok variant in most results.
 pub type ParseError = Box<pest::error::Error<Rule>>;
 
+/// The `(start, end)` byte range of the source of each node, keyed by its path
+/// (see [`Expression::parse_with_spans`]).
+pub type NodeSpans = HashMap<Vec<ChildIndex>, (usize, usize)>;
+
+/// Options of [`Expression::parse_with_options`] and
+/// [`Parser::with_options`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Whether `^` is logical xor, like `^^`, instead of exponentiation.
+    ///
+    /// Then `^` only applies to booleans, e.g. `(x < 1) ^ (y < 1)`, and binds
+    /// like `^^`.
+    pub caret_is_xor: bool,
+    /// Rejects expressions with more than this many nodes (see
+    /// [`Expression::node_count`]).
+    pub max_nodes: Option<usize>,
+}
+
+impl ParseOptions {
+    /// The lazily initialized global precedence table for these options.
+    fn shared_pratt_parser(&self) -> &'static PrattParser<Rule> {
+        if self.caret_is_xor {
+            &CARET_XOR_PRATT_PARSER
+        } else {
+            &PRATT_PARSER
+        }
+    }
+}
+
 impl<Real: Float + FromStr> Expression<Real> {
     /// Assume this expression is real-valued.
     pub fn unwrap_real(self) -> RealExpression<Real> {
@@ -33,6 +68,14 @@ impl<Real: Float + FromStr> Expression<Real> {
         }
     }
 
+    /// Assume this expression is a tuple of real-valued expressions.
+    pub fn unwrap_tuple(self) -> TupleExpression<Real> {
+        match self {
+            Self::Tuple(t) => t,
+            _ => panic!("Expected Tuple"),
+        }
+    }
+
     /// Assume this expression is boolean-valued.
     pub fn unwrap_bool(self) -> BoolExpression<Real> {
         match self {
@@ -44,7 +87,7 @@ impl<Real: Float + FromStr> Expression<Real> {
     pub fn parse_real_variable_names(input: &str) -> Result<HashSet<String>, ParseError> {
         Ok(ExpressionParser::parse(Rule::calculation, input)?
             .flatten()
-            .filter(|p| (p.as_rule() == Rule::real_variable))
+            .filter(|p| p.as_rule() == Rule::real_variable)
             .map(|p| p.as_str().to_string())
             .collect())
     }
@@ -52,7 +95,7 @@ impl<Real: Float + FromStr> Expression<Real> {
     pub fn parse_string_variable_names(input: &str) -> Result<HashSet<String>, ParseError> {
         Ok(ExpressionParser::parse(Rule::calculation, input)?
             .flatten()
-            .filter(|p| (p.as_rule() == Rule::str_variable))
+            .filter(|p| p.as_rule() == Rule::str_variable)
             .map(|p| p.as_str().to_string())
             .collect())
     }
@@ -65,19 +108,339 @@ impl<Real: Float + FromStr> Expression<Real> {
     /// [`Expression`] to be efficiently reused with many different data
     /// bindings.
     pub fn parse(input: &str, binding_map: impl Fn(&str) -> BindingId) -> Result<Self, ParseError> {
-        let mut pairs = ExpressionParser::parse(Rule::calculation, input)?;
-        // HACK: Working around https://github.com/pest-parser/pest/issues/943
-        let inner_expr = pairs.next().unwrap().into_inner();
-        Ok(parse_recursive(inner_expr, &binding_map))
+        Self::parse_with_options(input, binding_map, ParseOptions::default())
+    }
+
+    /// Like [`Self::parse`], but also returns the source span of each node, so
+    /// that errors and highlighting can be mapped back to `input`.
+    ///
+    /// Nodes of every type are keyed by their path as in
+    /// [`Self::node_at_path`]. Spans include enclosing parentheses.
+    pub fn parse_with_spans(
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+    ) -> Result<(Self, NodeSpans), ParseError> {
+        parse_with_spans(
+            input,
+            &ParseContext::shared(ParseOptions::default(), &binding_map),
+        )
+    }
+
+    /// Like [`Self::parse`], but with non-default `options`.
+    ///
+    /// To combine options with e.g. [`Self::parse_typed`], use
+    /// [`Parser::with_options`].
+    pub fn parse_with_options(
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        options: ParseOptions,
+    ) -> Result<Self, ParseError> {
+        parse_with_context(input, &ParseContext::shared(options, &binding_map))
+    }
+
+    /// Like [`Self::parse`], but calls of two arguments to functions that are
+    /// not built in are looked up in `functions`.
+    ///
+    /// The resulting expression must be evaluated with registers that were
+    /// given the same table (see [`Registers::set_functions`](crate::Registers::set_functions)).
+    pub fn parse_with_functions(
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        functions: &FunctionTable<Real>,
+    ) -> Result<Self, ParseError> {
+        let find_function = |name: &str| functions.find(name);
+        let context = ParseContext {
+            find_function: Some(&find_function),
+            ..ParseContext::shared(ParseOptions::default(), &binding_map)
+        };
+        parse_with_context(input, &context)
+    }
+
+    /// Like [`Self::parse`], but rejects expressions with more than
+    /// `max_nodes` nodes (see [`Self::node_count`]).
+    ///
+    /// This protects against evaluating excessively expensive expressions
+    /// from untrusted input. Parsing stops at the node that exceeds the limit,
+    /// so the rest of an oversized expression is never built.
+    pub fn parse_with_limits(
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        max_nodes: usize,
+    ) -> Result<Self, ParseError> {
+        let options = ParseOptions {
+            max_nodes: Some(max_nodes),
+            ..ParseOptions::default()
+        };
+        Self::parse_with_options(input, binding_map, options)
+    }
+
+    /// Like [`Self::parse`], but `variable_type` declares the type of each
+    /// variable instead of inferring it from the grammar.
+    ///
+    /// This allows string variables to appear anywhere a real variable can,
+    /// and operators applied to operands of the wrong type are reported as a
+    /// [`ParseError`], e.g. "cannot apply `+` to a string and a real".
+    pub fn parse_typed(
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        variable_type: impl Fn(&str) -> ValueType,
+    ) -> Result<Self, ParseError> {
+        let context = ParseContext {
+            variable_type: Some(&variable_type),
+            ..ParseContext::shared(ParseOptions::default(), &binding_map)
+        };
+        parse_with_context(input, &context)
+    }
+
+    /// Like [`Self::parse`], but `binding_map` returns `None` for unknown
+    /// variables, and every unknown variable is reported instead of just the
+    /// first.
+    ///
+    /// Syntax errors are still reported one at a time, since parsing stops at
+    /// the first.
+    pub fn parse_collect_errors(
+        input: &str,
+        binding_map: impl Fn(&str) -> Option<BindingId>,
+    ) -> Result<Self, Vec<ParseError>> {
+        let pairs = ExpressionParser::parse(Rule::calculation, input)
+            .map_err(|e| vec![chained_comparison_error(input, e)])?;
+        let mut unknown = HashSet::new();
+        let errors: Vec<ParseError> = pairs
+            .flatten()
+            .filter(|p| matches!(p.as_rule(), Rule::real_variable | Rule::str_variable))
+            .filter(|p| binding_map(p.as_str()).is_none() && unknown.insert(p.as_str()))
+            .map(|p| custom_error(&p, format!("unknown variable `{}`", p.as_str())))
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        // Every variable is known by now.
+        Self::parse(input, |name| binding_map(name).unwrap_or_default()).map_err(|e| vec![e])
+    }
+}
+
+/// Parses expressions using its own operator precedence table.
+///
+/// [`Expression::parse`] and [`Expression::parse_typed`] share a lazily
+/// initialized global table instead. Parsing through a `Parser` never touches
+/// that global, so its state is dropped along with the `Parser`.
+pub struct Parser {
+    pratt: PrattParser<Rule>,
+    options: ParseOptions,
+    coerce_strings: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::with_options(ParseOptions::default())
+    }
+
+    /// A parser that applies `options` to every method.
+    pub fn with_options(options: ParseOptions) -> Self {
+        Self {
+            pratt: new_pratt_parser(options),
+            options,
+            coerce_strings: false,
+        }
+    }
+
+    /// Allows `==` and `!=` between a string and a real, e.g. `code == 5` for
+    /// a string variable `code`.
+    ///
+    /// Each string is parsed as a real when evaluated, and strings that fail
+    /// to parse are not equal to any real. Such expressions must be evaluated
+    /// with [`BoolExpression::evaluate_coercing`](crate::BoolExpression::evaluate_coercing).
+    pub fn with_string_coercion(mut self) -> Self {
+        self.coerce_strings = true;
+        self
+    }
+
+    /// Like [`Expression::parse`].
+    pub fn parse<Real: FromStr + Float>(
+        &self,
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+    ) -> Result<Expression<Real>, ParseError> {
+        parse_with_context(input, &self.context(&binding_map))
+    }
+
+    /// Like [`Expression::parse_with_spans`].
+    pub fn parse_with_spans<Real: FromStr + Float>(
+        &self,
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+    ) -> Result<(Expression<Real>, NodeSpans), ParseError> {
+        parse_with_spans(input, &self.context(&binding_map))
+    }
+
+    /// Like [`Expression::parse_typed`].
+    pub fn parse_typed<Real: FromStr + Float>(
+        &self,
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        variable_type: impl Fn(&str) -> ValueType,
+    ) -> Result<Expression<Real>, ParseError> {
+        let context = ParseContext {
+            variable_type: Some(&variable_type),
+            ..self.context(&binding_map)
+        };
+        parse_with_context(input, &context)
+    }
+
+    /// Like [`Expression::parse_with_functions`].
+    pub fn parse_with_functions<Real: FromStr + Float>(
+        &self,
+        input: &str,
+        binding_map: impl Fn(&str) -> BindingId,
+        functions: &FunctionTable<Real>,
+    ) -> Result<Expression<Real>, ParseError> {
+        let find_function = |name: &str| functions.find(name);
+        let context = ParseContext {
+            find_function: Some(&find_function),
+            ..self.context(&binding_map)
+        };
+        parse_with_context(input, &context)
+    }
+
+    fn context<'a>(&'a self, binding_map: &'a dyn Fn(&str) -> BindingId) -> ParseContext<'a> {
+        ParseContext {
+            coerce_strings: self.coerce_strings,
+            ..ParseContext::new(&self.pratt, self.options, binding_map)
+        }
+    }
+}
+
+fn parse_with_context<Real: FromStr + Float>(
+    input: &str,
+    context: &ParseContext,
+) -> Result<Expression<Real>, ParseError> {
+    parse_spanned(input, context).map(|(expr, _)| expr)
+}
+
+fn parse_with_spans<Real: FromStr + Float>(
+    input: &str,
+    context: &ParseContext,
+) -> Result<(Expression<Real>, NodeSpans), ParseError> {
+    let (expr, tree) = parse_spanned(input, context)?;
+    let mut spans = NodeSpans::new();
+    tree.collect(&mut Vec::new(), &mut spans);
+    Ok((expr, spans))
+}
+
+fn parse_spanned<Real: FromStr + Float>(
+    input: &str,
+    context: &ParseContext,
+) -> Result<Spanned<Real>, ParseError> {
+    let mut pairs = ExpressionParser::parse(Rule::calculation, input)
+        .map_err(|e| chained_comparison_error(input, e))?;
+    let expr = pairs
+        .next()
+        .ok_or_else(|| input_error(input, "expected an expression".into()))?;
+    if expr.as_rule() == Rule::tuple_expr {
+        context.count_node(&expr)?;
+        let span = pair_span(&expr);
+        let (elements, operands) = expr
+            .into_inner()
+            .map(|element| {
+                let (value, tree) = parse_recursive(element.clone().into_inner(), context)?;
+                Ok((*real_operand(&element, value)?, tree))
+            })
+            .collect::<Result<Vec<_>, ParseError>>()?
+            .into_iter()
+            .unzip();
+        let tree = SpanTree { span, operands };
+        return Ok((Expression::Tuple(TupleExpression { elements }), tree));
+    }
+    // HACK: Working around https://github.com/pest-parser/pest/issues/943
+    parse_recursive(expr.into_inner(), context)
+}
+
+/// The source span of a parsed node, and the span trees of its operands in
+/// the order of [`NodeRef::operand`].
+struct SpanTree {
+    span: (usize, usize),
+    operands: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    /// Keeps only the `operands` that are operands of `expr`, e.g. dropping
+    /// the exponent of [`RealExpression::PowI`].
+    fn new<Real>(expr: &Expression<Real>, span: (usize, usize), mut operands: Vec<Self>) -> Self {
+        operands.truncate(expr.root().num_operands());
+        Self { span, operands }
+    }
+
+    fn leaf(span: (usize, usize)) -> Self {
+        Self {
+            span,
+            operands: Vec::new(),
+        }
+    }
+
+    fn collect(self, path: &mut Vec<ChildIndex>, spans: &mut NodeSpans) {
+        spans.insert(path.clone(), self.span);
+        for (i, operand) in self.operands.into_iter().enumerate() {
+            path.push(i);
+            operand.collect(path, spans);
+            path.pop();
+        }
     }
 }
 
-static PRATT_PARSER: Lazy<PrattParser<Rule>> = Lazy::new(|| {
+type Spanned<Real> = (Expression<Real>, SpanTree);
+
+fn pair_span(pair: &Pair<Rule>) -> (usize, usize) {
+    (pair.as_span().start(), pair.as_span().end())
+}
+
+/// The span of `pair` widened to the parentheses around it.
+fn parenthesized_span(pair: &Pair<Rule>) -> (usize, usize) {
+    let input = pair.get_input();
+    let (start, end) = pair_span(pair);
+    let before = input[..start].trim_end_matches([' ', '\t']);
+    let after = input[end..].trim_start_matches([' ', '\t']);
+    (
+        before.len().saturating_sub(1),
+        (input.len() - after.len() + 1).min(input.len()),
+    )
+}
+
+static PRATT_PARSER: Lazy<PrattParser<Rule>> =
+    Lazy::new(|| new_pratt_parser(ParseOptions::default()));
+static CARET_XOR_PRATT_PARSER: Lazy<PrattParser<Rule>> = Lazy::new(|| {
+    new_pratt_parser(ParseOptions {
+        caret_is_xor: true,
+        ..ParseOptions::default()
+    })
+});
+
+fn new_pratt_parser(options: ParseOptions) -> PrattParser<Rule> {
     use Assoc::*;
     use Rule::*;
 
-    PrattParser::new()
-        .op(Op::infix(and, Left) | Op::infix(or, Left))
+    // The grammar parses `^` between reals as `power` and between booleans as
+    // `caret`, and both mean the same operator.
+    let logic = Op::infix(and, Left) | Op::infix(or, Left) | Op::infix(xor, Left);
+    let (logic, power_op) = if options.caret_is_xor {
+        (
+            logic | Op::infix(power, Left) | Op::infix(caret, Left),
+            None,
+        )
+    } else {
+        (
+            logic,
+            Some(Op::infix(power, Right) | Op::infix(caret, Right)),
+        )
+    };
+    let pratt = PrattParser::new()
+        .op(Op::infix(implies, Right))
+        .op(logic)
         .op(Op::infix(str_eq, Left)
             | Op::infix(str_neq, Left)
             | Op::infix(real_eq, Left)
@@ -87,131 +450,600 @@ static PRATT_PARSER: Lazy<PrattParser<Rule>> = Lazy::new(|| {
             | Op::infix(greater, Left)
             | Op::infix(ge, Left))
         .op(Op::infix(add, Left) | Op::infix(subtract, Left))
-        .op(Op::infix(multiply, Left) | Op::infix(divide, Left))
-        .op(Op::infix(power, Right))
-});
+        .op(Op::infix(multiply, Left)
+            | Op::infix(divide, Left)
+            | Op::infix(implicit_multiply, Left));
+    match power_op {
+        Some(power_op) => pratt.op(power_op),
+        None => pratt,
+    }
+    .op(Op::postfix(method_call))
+}
+
+/// Resolves operators and variables encountered while parsing.
+struct ParseContext<'a> {
+    pratt: &'a PrattParser<Rule>,
+    binding_map: &'a dyn Fn(&str) -> BindingId,
+    /// When absent, variable types are inferred from the grammar.
+    variable_type: Option<&'a dyn Fn(&str) -> ValueType>,
+    /// Whether strings can be compared to reals for equality.
+    coerce_strings: bool,
+    /// Looks up functions that are not built in.
+    find_function: Option<FindFunction<'a>>,
+    /// Whether `^` is xor instead of power, which must agree with `pratt`.
+    caret_is_xor: bool,
+    /// The number of nodes after which parsing fails.
+    max_nodes: Option<usize>,
+    /// The number of nodes built so far.
+    num_nodes: Cell<usize>,
+}
+
+type FindFunction<'a> = &'a dyn Fn(&str) -> Option<CustomFunctionId>;
+
+impl<'a> ParseContext<'a> {
+    /// The context of every parse method, which the method may extend.
+    ///
+    /// `pratt` must have been built with `options`.
+    fn new(
+        pratt: &'a PrattParser<Rule>,
+        options: ParseOptions,
+        binding_map: &'a dyn Fn(&str) -> BindingId,
+    ) -> Self {
+        Self {
+            pratt,
+            binding_map,
+            variable_type: None,
+            coerce_strings: false,
+            find_function: None,
+            caret_is_xor: options.caret_is_xor,
+            max_nodes: options.max_nodes,
+            num_nodes: Cell::new(0),
+        }
+    }
+
+    /// Like [`Self::new`], with the global precedence table for `options`.
+    fn shared(options: ParseOptions, binding_map: &'a dyn Fn(&str) -> BindingId) -> Self {
+        Self::new(options.shared_pratt_parser(), options, binding_map)
+    }
+
+    /// Counts a node built from `pair`, and fails if that exceeds
+    /// `max_nodes`.
+    fn count_node(&self, pair: &Pair<Rule>) -> Result<(), ParseError> {
+        let num_nodes = self.num_nodes.get() + 1;
+        self.num_nodes.set(num_nodes);
+        match self.max_nodes {
+            Some(max_nodes) if num_nodes > max_nodes => Err(custom_error(
+                pair,
+                format!("expression exceeds the limit of {max_nodes} nodes"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stops counting nodes that were built but then discarded.
+    fn uncount_nodes(&self, num_nodes: usize) {
+        self.num_nodes.set(self.num_nodes.get() - num_nodes);
+    }
+}
 
 fn parse_recursive<Real: FromStr + Float>(
     pairs: Pairs<Rule>,
-    binding_map: &impl Fn(&str) -> BindingId,
-) -> Expression<Real> {
-    PRATT_PARSER
-        .map_primary(|pair| match pair.as_rule() {
-            Rule::bool_expr => parse_recursive(pair.into_inner(), binding_map),
-            Rule::real_expr => parse_recursive(pair.into_inner(), binding_map),
-            Rule::string_expr => parse_recursive(pair.into_inner(), binding_map),
-            Rule::real_literal => {
-                let literal_str = pair.as_str();
-                if let Ok(value) = literal_str.parse::<Real>() {
-                    return Expression::Real(RealExpression::Literal(value));
-                }
-                panic!("Unexpected literal: {}", literal_str)
+    context: &ParseContext,
+) -> Result<Spanned<Real>, ParseError> {
+    context
+        .pratt
+        .map_primary(|pair| {
+            let span = pair_span(&pair);
+            if matches!(
+                pair.as_rule(),
+                Rule::real_literal
+                    | Rule::string_literal_value
+                    | Rule::raw_string_literal_value
+                    | Rule::unary_logic_expr
+                    | Rule::abs_bars
+                    | Rule::case_expr
+                    | Rule::real_variable
+                    | Rule::str_variable
+            ) {
+                context.count_node(&pair)?;
             }
-            Rule::string_literal => parse_recursive(pair.into_inner(), binding_map),
-            Rule::string_literal_value => {
-                let literal_str = pair.as_str();
-                if let Ok(value) = literal_str.parse::<String>() {
-                    return Expression::String(StringExpression::Literal(value));
+            match pair.as_rule() {
+                // Only parenthesized, since other operands are parsed from their
+                // inner pairs.
+                Rule::bool_expr | Rule::real_expr => {
+                    let (expr, mut tree) = parse_recursive(pair.clone().into_inner(), context)?;
+                    tree.span = parenthesized_span(&pair);
+                    Ok((expr, tree))
                 }
-                panic!("Unexpected literal: {}", literal_str)
-            }
-            Rule::unary_real_op_expr => {
-                let mut inner = pair.into_inner();
-                let unary = inner.next().unwrap();
-                match unary.as_rule() {
-                    Rule::neg => Expression::Real(RealExpression::Neg(Box::new(
-                        parse_recursive(inner, binding_map).unwrap_real(),
-                    ))),
-                    x => panic!("Unexpected unary logic operator: {x:?}"),
+                Rule::string_expr => parse_recursive(pair.into_inner(), context),
+                Rule::implicit_coefficient => parse_recursive(pair.into_inner(), context),
+                // Skip the trailing `implicit_close`.
+                Rule::implicit_group => {
+                    let group = next_inner(&mut pair.clone().into_inner(), &pair)?;
+                    parse_recursive(group.into_inner(), context)
                 }
-            }
-            Rule::unary_logic_expr => {
-                let mut inner = pair.into_inner();
-                let unary = inner.next().unwrap();
-                match unary.as_rule() {
-                    Rule::not => Expression::Boolean(BoolExpression::Not(Box::new(
-                        parse_recursive(inner, binding_map).unwrap_bool(),
-                    ))),
-                    x => panic!("Unexpected unary logic operator: {x:?}"),
+                Rule::real_literal => {
+                    let literal_str = pair.as_str();
+                    if let Ok(value) = literal_str.parse::<i32>() {
+                        let expr = Expression::Real(RealExpression::IntLiteral(value));
+                        return Ok((expr, SpanTree::leaf(span)));
+                    }
+                    if let Ok(value) = literal_str.parse::<Real>() {
+                        let expr = Expression::Real(RealExpression::Literal(value));
+                        return Ok((expr, SpanTree::leaf(span)));
+                    }
+                    Err(custom_error(
+                        &pair,
+                        format!("invalid number `{literal_str}`"),
+                    ))
                 }
+                Rule::string_literal => parse_recursive(pair.into_inner(), context),
+                Rule::string_literal_value | Rule::raw_string_literal_value => Ok((
+                    Expression::String(StringExpression::Literal(pair.as_str().to_string())),
+                    SpanTree::leaf(span),
+                )),
+                Rule::unary_real_op_expr => {
+                    let mut inner = pair.clone().into_inner();
+                    let unary = next_inner(&mut inner, &pair)?;
+                    let (only, only_tree) = parse_recursive(inner, context)?;
+                    let expr = match unary.as_rule() {
+                        Rule::neg => {
+                            context.count_node(&unary)?;
+                            Expression::Real(RealExpression::Neg(real_operand(&unary, only)?))
+                        }
+                        // Unary plus is the identity.
+                        Rule::pos => {
+                            return Ok((Expression::Real(*real_operand(&unary, only)?), only_tree))
+                        }
+                        _ => return Err(unexpected_rule(&unary)),
+                    };
+                    let tree = SpanTree::new(&expr, span, vec![only_tree]);
+                    Ok((expr, tree))
+                }
+                Rule::unary_logic_expr => {
+                    let mut inner = pair.clone().into_inner();
+                    let unary = next_inner(&mut inner, &pair)?;
+                    let (only, only_tree) = parse_recursive(inner, context)?;
+                    let expr = match unary.as_rule() {
+                        Rule::not => {
+                            Expression::Boolean(BoolExpression::Not(bool_operand(&unary, only)?))
+                        }
+                        _ => return Err(unexpected_rule(&unary)),
+                    };
+                    let tree = SpanTree::new(&expr, span, vec![only_tree]);
+                    Ok((expr, tree))
+                }
+                Rule::abs_bars => {
+                    let (only, only_tree) = parse_recursive(pair.clone().into_inner(), context)?;
+                    let expr = Expression::Real(RealExpression::Func1(
+                        Func1::Abs,
+                        real_operand(&pair, only)?,
+                    ));
+                    let tree = SpanTree::new(&expr, span, vec![only_tree]);
+                    Ok((expr, tree))
+                }
+                Rule::case_expr => {
+                    let mut inner = pair.clone().into_inner();
+                    let mut arms = Vec::new();
+                    let mut operands = Vec::new();
+                    let mut condition_trees = Vec::new();
+                    while let Some(keyword) = inner.next() {
+                        let (first, first_tree) =
+                            parse_recursive(next_inner(&mut inner, &pair)?.into_inner(), context)?;
+                        if keyword.as_rule() == Rule::case_else {
+                            let otherwise = real_operand(&keyword, first)?;
+                            operands.push(first_tree);
+                            operands.append(&mut condition_trees);
+                            let expr = Expression::Real(RealExpression::Case(arms, otherwise));
+                            let tree = SpanTree::new(&expr, span, operands);
+                            return Ok((expr, tree));
+                        }
+                        let condition = bool_operand(&keyword, first)?;
+                        if condition.reads_strings() {
+                            return Err(custom_error(
+                                &keyword,
+                                "conditions of `when` cannot compare strings".into(),
+                            ));
+                        }
+                        let then = next_inner(&mut inner, &pair)?;
+                        let (value, value_tree) =
+                            parse_recursive(next_inner(&mut inner, &pair)?.into_inner(), context)?;
+                        arms.push((*condition, *real_operand(&then, value)?));
+                        operands.push(value_tree);
+                        condition_trees.push(first_tree);
+                    }
+                    Err(custom_error(&pair, "expected `else`".into()))
+                }
+                Rule::function_call => {
+                    let mut inner = pair.clone().into_inner();
+                    let name = next_inner(&mut inner, &pair)?;
+                    let (args, operands): (Vec<_>, Vec<_>) = inner
+                        .map(|arg| parse_recursive(arg.into_inner(), context))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .into_iter()
+                        .unzip();
+                    let expr = call(&name, args, context)?;
+                    let tree = SpanTree::new(&expr, span, operands);
+                    Ok((expr, tree))
+                }
+                Rule::real_variable => {
+                    let name = pair.as_str();
+                    let binding = (context.binding_map)(name);
+                    let expr = match context.variable_type.map_or(ValueType::Real, |f| f(name)) {
+                        ValueType::Real => Expression::Real(RealExpression::Binding(binding)),
+                        ValueType::String => Expression::String(StringExpression::Binding(binding)),
+                        ty @ (ValueType::Boolean | ValueType::Tuple) => {
+                            return Err(custom_error(
+                                &pair,
+                                format!("variable `{name}` cannot be a {ty}"),
+                            ))
+                        }
+                    };
+                    Ok((expr, SpanTree::leaf(span)))
+                }
+                Rule::str_variable => Ok((
+                    Expression::String(StringExpression::Binding((context.binding_map)(
+                        pair.as_str(),
+                    ))),
+                    SpanTree::leaf(span),
+                )),
+                _ => Err(unexpected_rule(&pair)),
             }
-            Rule::real_variable => {
-                Expression::Real(RealExpression::Binding(binding_map(pair.as_str())))
-            }
-            Rule::str_variable => {
-                Expression::String(StringExpression::Binding(binding_map(pair.as_str())))
-            }
-            x => panic!("Unexpected primary rule {x:?}"),
         })
-        .map_infix(|lhs, op, rhs| match op.as_rule() {
-            Rule::add => Expression::Real(RealExpression::Add(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::subtract => Expression::Real(RealExpression::Sub(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::multiply => Expression::Real(RealExpression::Mul(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::divide => Expression::Real(RealExpression::Div(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::power => Expression::Real(RealExpression::Pow(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::real_eq => Expression::Boolean(BoolExpression::Equal(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::real_neq => Expression::Boolean(BoolExpression::NotEqual(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::str_eq => Expression::Boolean(BoolExpression::StrEqual(
-                lhs.unwrap_string(),
-                rhs.unwrap_string(),
-            )),
-            Rule::str_neq => Expression::Boolean(BoolExpression::StrNotEqual(
-                lhs.unwrap_string(),
-                rhs.unwrap_string(),
-            )),
-            Rule::less => Expression::Boolean(BoolExpression::Less(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::le => Expression::Boolean(BoolExpression::LessEqual(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::greater => Expression::Boolean(BoolExpression::Greater(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::ge => Expression::Boolean(BoolExpression::GreaterEqual(
-                Box::new(lhs.unwrap_real()),
-                Box::new(rhs.unwrap_real()),
-            )),
-            Rule::and => Expression::Boolean(BoolExpression::And(
-                Box::new(lhs.unwrap_bool()),
-                Box::new(rhs.unwrap_bool()),
-            )),
-            Rule::or => Expression::Boolean(BoolExpression::Or(
-                Box::new(lhs.unwrap_bool()),
-                Box::new(rhs.unwrap_bool()),
-            )),
-            x => panic!("Unexpected operator {x:?}"),
+        .map_infix(|lhs, op, rhs| {
+            let ((lhs, lhs_tree), (rhs, rhs_tree)) = (lhs?, rhs?);
+            context.count_node(&op)?;
+            let span = (lhs_tree.span.0, rhs_tree.span.1);
+            let lhs_is_real = matches!(lhs, Expression::Real(_));
+            let expr = match op.as_rule() {
+                Rule::add => real_op(RealExpression::Add, lhs, &op, rhs),
+                Rule::subtract => real_op(RealExpression::Sub, lhs, &op, rhs),
+                Rule::multiply | Rule::implicit_multiply => {
+                    real_op(RealExpression::Mul, lhs, &op, rhs)
+                }
+                Rule::divide => real_op(RealExpression::Div, lhs, &op, rhs),
+                Rule::power | Rule::caret if context.caret_is_xor => {
+                    logic_op(BoolExpression::Xor, lhs, &op, rhs)
+                }
+                Rule::power | Rule::caret => real_op(RealExpression::Pow, lhs, &op, rhs),
+                Rule::real_eq => equality(
+                    BoolExpression::Equal,
+                    BoolExpression::StrEqual,
+                    context
+                        .coerce_strings
+                        .then_some(BoolExpression::CoercedEqual),
+                    lhs,
+                    &op,
+                    rhs,
+                ),
+                Rule::real_neq => equality(
+                    BoolExpression::NotEqual,
+                    BoolExpression::StrNotEqual,
+                    context
+                        .coerce_strings
+                        .then_some(BoolExpression::CoercedNotEqual),
+                    lhs,
+                    &op,
+                    rhs,
+                ),
+                Rule::str_eq => string_comparison(BoolExpression::StrEqual, lhs, &op, rhs),
+                Rule::str_neq => string_comparison(BoolExpression::StrNotEqual, lhs, &op, rhs),
+                Rule::less => real_comparison(BoolExpression::Less, lhs, &op, rhs),
+                Rule::le => real_comparison(BoolExpression::LessEqual, lhs, &op, rhs),
+                Rule::greater => real_comparison(BoolExpression::Greater, lhs, &op, rhs),
+                Rule::ge => real_comparison(BoolExpression::GreaterEqual, lhs, &op, rhs),
+                Rule::and => logic_op(BoolExpression::And, lhs, &op, rhs),
+                Rule::or => logic_op(BoolExpression::Or, lhs, &op, rhs),
+                Rule::xor => logic_op(BoolExpression::Xor, lhs, &op, rhs),
+                Rule::implies => logic_op(BoolExpression::Implies, lhs, &op, rhs),
+                _ => Err(unexpected_rule(&op)),
+            }?;
+            // A coerced comparison puts its string operand first.
+            let operands = match expr {
+                Expression::Boolean(
+                    BoolExpression::CoercedEqual(..) | BoolExpression::CoercedNotEqual(..),
+                ) if lhs_is_real => vec![rhs_tree, lhs_tree],
+                _ => vec![lhs_tree, rhs_tree],
+            };
+            let tree = SpanTree::new(&expr, span, operands);
+            Ok((expr, tree))
+        })
+        .map_postfix(|receiver, op| match op.as_rule() {
+            Rule::method_call => {
+                let receiver = receiver?;
+                let span = (receiver.1.span.0, op.as_span().end());
+                let mut inner = op.clone().into_inner();
+                let name = next_inner(&mut inner, &op)?;
+                let (args, operands): (Vec<_>, Vec<_>) = std::iter::once(Ok(receiver))
+                    .chain(inner.map(|arg| parse_recursive(arg.into_inner(), context)))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .unzip();
+                let expr = call(&name, args, context)?;
+                let tree = SpanTree::new(&expr, span, operands);
+                Ok((expr, tree))
+            }
+            _ => Err(unexpected_rule(&op)),
         })
         .parse(pairs)
 }
 
+/// Calls the built-in function `name`, with the receiver of a method call as
+/// the first of `args`.
+fn call<Real: Float>(
+    name: &Pair<Rule>,
+    args: Vec<Expression<Real>>,
+    context: &ParseContext,
+) -> Result<Expression<Real>, ParseError> {
+    context.count_node(name)?;
+    let unknown_function = || custom_error(name, format!("unknown function `{}`", name.as_str()));
+    let func2 = Func2::from_name(name.as_str()).or_else(|| {
+        let find_function = context.find_function?;
+        find_function(name.as_str()).map(Func2::Custom)
+    });
+    let num_params = if name.as_str() == "between" {
+        3
+    } else if name.as_str() == "powi" {
+        2
+    } else if Func1::from_name(name.as_str()).is_some() {
+        1
+    } else if func2.is_some() {
+        2
+    } else {
+        return Err(unknown_function());
+    };
+    if args.len() != num_params {
+        return Err(custom_error(
+            name,
+            format!(
+                "`{}` takes {num_params} argument(s) but {} were given",
+                name.as_str(),
+                args.len()
+            ),
+        ));
+    }
+    let mut args = args.into_iter();
+    let mut next_arg = || {
+        args.next()
+            .ok_or_else(|| custom_error(name, "missing argument".into()))
+    };
+    let first = real_operand(name, next_arg()?)?;
+    if num_params == 3 {
+        let lo = real_operand(name, next_arg()?)?;
+        let hi = real_operand(name, next_arg()?)?;
+        return Ok(Expression::Boolean(BoolExpression::Between(first, lo, hi)));
+    }
+    if name.as_str() == "powi" {
+        // The exponent is stored in the `PowI` node rather than as an operand.
+        let exponent = next_arg()?;
+        context.uncount_nodes(exponent.node_count());
+        let exponent = integer_literal(exponent).ok_or_else(|| {
+            custom_error(
+                name,
+                "the exponent of `powi` must be an integer literal".into(),
+            )
+        })?;
+        return Ok(Expression::Real(RealExpression::PowI(first, exponent)));
+    }
+    if num_params == 1 {
+        let func1 = Func1::from_name(name.as_str()).ok_or_else(unknown_function)?;
+        return Ok(Expression::Real(RealExpression::Func1(func1, first)));
+    }
+    let second = real_operand(name, next_arg()?)?;
+    let func2 = func2.ok_or_else(unknown_function)?;
+    Ok(Expression::Real(RealExpression::Func2(
+        func2, first, second,
+    )))
+}
+
+/// The value of `expr` if it is an integer literal, possibly negated, that
+/// fits in an `i32`.
+fn integer_literal<Real: Float>(expr: Expression<Real>) -> Option<i32> {
+    let value = match expr {
+        Expression::Real(RealExpression::Neg(only)) => -only.literal_value()?,
+        Expression::Real(expr) => expr.literal_value()?,
+        _ => return None,
+    };
+    if value.fract() != Real::zero() {
+        return None;
+    }
+    num_traits::cast(value)
+}
+
+type Operands<T> = (Box<T>, Box<T>);
+
+fn real_op<Real>(
+    build: BinaryNode<RealExpression<Real>, RealExpression<Real>>,
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Expression<Real>, ParseError> {
+    let (lhs, rhs) = real_operands(lhs, op, rhs)?;
+    Ok(Expression::Real(build(lhs, rhs)))
+}
+
+fn real_comparison<Real>(
+    build: BinaryNode<RealExpression<Real>, BoolExpression<Real>>,
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Expression<Real>, ParseError> {
+    let (lhs, rhs) = real_operands(lhs, op, rhs)?;
+    Ok(Expression::Boolean(build(lhs, rhs)))
+}
+
+/// The grammar matches `==` and `!=` between two variables as a real
+/// comparison, so use a string comparison if both operands turned out to be
+/// strings (see [`Expression::parse_typed`]).
+///
+/// A string and a real are only comparable with `coerced_build` (see
+/// [`Parser::with_string_coercion`]).
+fn equality<Real>(
+    real_build: BinaryNode<RealExpression<Real>, BoolExpression<Real>>,
+    string_build: fn(StringExpression, StringExpression) -> BoolExpression<Real>,
+    coerced_build: Option<CoercedNode<Real>>,
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Expression<Real>, ParseError> {
+    match (lhs, rhs, coerced_build) {
+        (lhs @ Expression::String(_), rhs @ Expression::String(_), _) => {
+            string_comparison(string_build, lhs, op, rhs)
+        }
+        (Expression::String(lhs), Expression::Real(rhs), Some(build))
+        | (Expression::Real(rhs), Expression::String(lhs), Some(build)) => {
+            Ok(Expression::Boolean(build(lhs, Box::new(rhs))))
+        }
+        (lhs, rhs, _) => real_comparison(real_build, lhs, op, rhs),
+    }
+}
+
+/// Constructor of a string-to-real comparison, e.g.
+/// `BoolExpression::CoercedEqual`.
+type CoercedNode<Real> = fn(StringExpression, Box<RealExpression<Real>>) -> BoolExpression<Real>;
+
+fn logic_op<Real>(
+    build: BinaryNode<BoolExpression<Real>, BoolExpression<Real>>,
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Expression<Real>, ParseError> {
+    match (lhs, rhs) {
+        (Expression::Boolean(lhs), Expression::Boolean(rhs)) => {
+            Ok(Expression::Boolean(build(Box::new(lhs), Box::new(rhs))))
+        }
+        (lhs, rhs) => Err(binary_type_error(op, &lhs, &rhs)),
+    }
+}
+
+fn string_comparison<Real>(
+    build: fn(StringExpression, StringExpression) -> BoolExpression<Real>,
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Expression<Real>, ParseError> {
+    match (lhs, rhs) {
+        (Expression::String(lhs), Expression::String(rhs)) => {
+            Ok(Expression::Boolean(build(lhs, rhs)))
+        }
+        (lhs, rhs) => Err(binary_type_error(op, &lhs, &rhs)),
+    }
+}
+
+fn real_operands<Real>(
+    lhs: Expression<Real>,
+    op: &Pair<Rule>,
+    rhs: Expression<Real>,
+) -> Result<Operands<RealExpression<Real>>, ParseError> {
+    match (lhs, rhs) {
+        (Expression::Real(lhs), Expression::Real(rhs)) => Ok((Box::new(lhs), Box::new(rhs))),
+        (lhs, rhs) => Err(binary_type_error(op, &lhs, &rhs)),
+    }
+}
+
+fn real_operand<Real>(
+    op: &Pair<Rule>,
+    only: Expression<Real>,
+) -> Result<Box<RealExpression<Real>>, ParseError> {
+    match only {
+        Expression::Real(only) => Ok(Box::new(only)),
+        only => Err(unary_type_error(op, &only)),
+    }
+}
+
+fn bool_operand<Real>(
+    op: &Pair<Rule>,
+    only: Expression<Real>,
+) -> Result<Box<BoolExpression<Real>>, ParseError> {
+    match only {
+        Expression::Boolean(only) => Ok(Box::new(only)),
+        only => Err(unary_type_error(op, &only)),
+    }
+}
+
+fn binary_type_error<Real>(
+    op: &Pair<Rule>,
+    lhs: &Expression<Real>,
+    rhs: &Expression<Real>,
+) -> ParseError {
+    custom_error(
+        op,
+        format!(
+            "cannot apply `{}` to a {} and a {}",
+            op.as_str(),
+            lhs.value_type(),
+            rhs.value_type()
+        ),
+    )
+}
+
+fn unary_type_error<Real>(op: &Pair<Rule>, only: &Expression<Real>) -> ParseError {
+    custom_error(
+        op,
+        format!("cannot apply `{}` to a {}", op.as_str(), only.value_type()),
+    )
+}
+
+/// The grammar has no rule for `a < b < c`, so parsing stops at the second
+/// comparison operator. Replaces that error with a suggested rewrite.
+fn chained_comparison_error(input: &str, error: pest::error::Error<Rule>) -> ParseError {
+    let (ErrorVariant::ParsingError { .. }, pest::error::InputLocation::Pos(pos)) =
+        (&error.variant, &error.location)
+    else {
+        return Box::new(error);
+    };
+    let is_comparison = |s: &str| ["<", ">", "==", "!="].iter().any(|op| s.starts_with(op));
+    let Some(position) = Position::new(input, *pos) else {
+        return Box::new(error);
+    };
+    let (before, after) = input.split_at(*pos);
+    if !is_comparison(after.trim_start()) || !before.contains(['<', '>', '=']) {
+        return Box::new(error);
+    }
+    Box::new(pest::error::Error::new_from_pos(
+        ErrorVariant::CustomError {
+            message: "comparisons cannot be chained; write `a < b && b < c` instead of \
+                      `a < b < c`"
+                .into(),
+        },
+        position,
+    ))
+}
+
+fn custom_error(pair: &Pair<Rule>, message: String) -> ParseError {
+    Box::new(pest::error::Error::new_from_span(
+        ErrorVariant::CustomError { message },
+        pair.as_span(),
+    ))
+}
+
+fn input_error(input: &str, message: String) -> ParseError {
+    Box::new(pest::error::Error::new_from_pos(
+        ErrorVariant::CustomError { message },
+        Position::from_start(input),
+    ))
+}
+
+/// An error for a `pair` that the grammar does not produce where it was found.
+fn unexpected_rule(pair: &Pair<Rule>) -> ParseError {
+    custom_error(pair, format!("unexpected {:?}", pair.as_rule()))
+}
+
+/// The next of the `inner` pairs of `parent`, or an error if the grammar
+/// produced fewer than expected.
+fn next_inner<'i>(
+    inner: &mut Pairs<'i, Rule>,
+    parent: &Pair<'i, Rule>,
+) -> Result<Pair<'i, Rule>, ParseError> {
+    inner
+        .next()
+        .ok_or_else(|| custom_error(parent, format!("incomplete {:?}", parent.as_rule())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::NodeRef;
 
     #[test]
     fn parse_variable_names() {
@@ -224,6 +1056,168 @@ mod tests {
         assert!(vars.contains("x"), "{vars:?}");
     }
 
+    #[test]
+    fn mixed_string_and_real_arithmetic_is_an_error() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "name" => 0,
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        fn variable_type(var_name: &str) -> ValueType {
+            match var_name {
+                "name" => ValueType::String,
+                _ => ValueType::Real,
+            }
+        }
+        let err =
+            Expression::<f32>::parse_typed("name + 1", binding_map, variable_type).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("cannot apply `+` to a string and a real"),
+            "{err}"
+        );
+        let err =
+            Expression::<f32>::parse_typed("2 * -name", binding_map, variable_type).unwrap_err();
+        assert!(
+            err.to_string().contains("cannot apply `-` to a string"),
+            "{err}"
+        );
+
+        let parsed = Expression::<f32>::parse_typed("x + 1", binding_map, variable_type).unwrap();
+        assert_eq!(parsed.value_type(), ValueType::Real);
+    }
+
+    #[test]
+    fn chained_comparison_suggests_and() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "a" => 0,
+                "b" => 1,
+                "c" => 2,
+                _ => unreachable!(),
+            }
+        }
+        for input in ["a < b < c", "(a <= b) > c", "a == b != c"] {
+            let err = Expression::<f32>::parse(input, binding_map).unwrap_err();
+            assert!(err.to_string().contains("`a < b && b < c`"), "{err}");
+        }
+
+        let err = Expression::<f32>::parse("a < b +", binding_map).unwrap_err();
+        assert!(!err.to_string().contains("chained"), "{err}");
+    }
+
+    #[test]
+    fn collect_unknown_variables() {
+        fn binding_map(var_name: &str) -> Option<BindingId> {
+            match var_name {
+                "x" => Some(0),
+                _ => None,
+            }
+        }
+        let errors = Expression::<f32>::parse_collect_errors("x * foo + bar / foo", binding_map)
+            .unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(
+            messages[0].contains("unknown variable `foo`"),
+            "{}",
+            messages[0]
+        );
+        assert!(
+            messages[1].contains("unknown variable `bar`"),
+            "{}",
+            messages[1]
+        );
+
+        let parsed = Expression::<f32>::parse_collect_errors("x * 2", binding_map).unwrap();
+        assert_eq!(parsed.value_type(), ValueType::Real);
+        let errors = Expression::<f32>::parse_collect_errors("x *", binding_map).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn node_count_limit() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+        // Add(Mul(x, 2), Neg(y))
+        let input = "x * 2 + -y";
+        let parsed = Expression::<f32>::parse(input, binding_map).unwrap();
+        assert_eq!(parsed.node_count(), 6);
+        Expression::<f32>::parse_with_limits(input, binding_map, 6).unwrap();
+        let err = Expression::<f32>::parse_with_limits(input, binding_map, 5).unwrap_err();
+        assert!(
+            err.to_string().contains("exceeds the limit of 5 nodes"),
+            "{err}"
+        );
+
+        // Wide expressions are limited too.
+        let parsed = Expression::<f32>::parse("x > 1 && y < 2 || x == y", binding_map).unwrap();
+        assert_eq!(parsed.node_count(), 11);
+
+        // Parsing stops at the first node over the limit.
+        let input = vec!["x"; 200].join(" + ");
+        let err = Expression::<f32>::parse_with_limits(&input, binding_map, 5).unwrap_err();
+        assert!(
+            matches!(err.location, pest::error::InputLocation::Span((start, _)) if start < 20),
+            "{:?}",
+            err.location
+        );
+
+        // The nodes counted while parsing are exactly those of the result.
+        for input in [
+            "+x - 2(x + 1)",
+            "powi(x, -3) + x.powi(2)",
+            "max(x, y).sqrt() ^ 2 ^ y",
+            "between(x, 1, 2) && !(y > 3)",
+            "when x < 0 then -x when x > 1 then |y| else 2",
+            "(x, y * 2, 3)",
+            "x == \"W\" || y != \"V\"",
+        ] {
+            let node_count = Expression::<f32>::parse(input, binding_map)
+                .unwrap()
+                .node_count();
+            assert!(
+                Expression::<f32>::parse_with_limits(input, binding_map, node_count).is_ok(),
+                "{input}"
+            );
+            assert!(
+                Expression::<f32>::parse_with_limits(input, binding_map, node_count - 1).is_err(),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_with_owned_parser() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                _ => unreachable!(),
+            }
+        }
+        let parser = Parser::new();
+        let parsed = parser.parse::<f64>("2 * x + 1", binding_map).unwrap();
+        let mut registers = crate::Registers::new(2);
+        let output = parsed.unwrap_real().evaluate(&[[1.0, 2.0]], &mut registers);
+        assert_eq!(output, [3.0, 5.0]);
+
+        let err = parser
+            .parse_typed::<f64>("x + 1", binding_map, |_| ValueType::String)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("cannot apply `+` to a string and a real"),
+            "{err}"
+        );
+    }
+
     #[test]
     fn parse_comparisons() {
         fn binding_map(var_name: &str) -> BindingId {
@@ -240,4 +1234,220 @@ mod tests {
         Expression::<f32>::parse("x <= y", binding_map).unwrap();
         Expression::<f32>::parse("x >= y", binding_map).unwrap();
     }
+
+    #[test]
+    fn parse_with_spans() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                _ => unreachable!(),
+            }
+        }
+
+        let input = "( x + 1 ) * y.max(2)";
+        let (parsed, spans) = Expression::<f64>::parse_with_spans(input, binding_map).unwrap();
+        let real = parsed.unwrap_real();
+        assert!(matches!(real, RealExpression::Mul(_, _)), "{real:?}");
+        assert_eq!(spans[&vec![]], (0, input.len()));
+        let source = |path: &[usize]| {
+            let (start, end) = spans[path];
+            &input[start..end]
+        };
+        assert_eq!(source(&[0]), "( x + 1 )");
+        assert_eq!(source(&[0, 0]), "x");
+        assert_eq!(source(&[1]), "y.max(2)");
+        assert_eq!(source(&[1, 1]), "2");
+        assert_eq!(spans.len(), real.node_count());
+
+        // Boolean nodes and their operands have spans too, including the
+        // conditions of a case after its values.
+        let input = "when !(x < 1) && between(x, 0, y) then 1 else 2";
+        let (parsed, spans) = Expression::<f64>::parse_with_spans(input, binding_map).unwrap();
+        let source = |path: &[usize]| {
+            let (start, end) = spans[path];
+            &input[start..end]
+        };
+        assert_eq!(source(&[2]), "!(x < 1) && between(x, 0, y)");
+        assert!(matches!(
+            parsed.node_at_path(&[2, 0]),
+            Some(NodeRef::Boolean(BoolExpression::Not(_)))
+        ));
+        assert_eq!(source(&[2, 0]), "!(x < 1)");
+        assert_eq!(source(&[2, 0, 0]), "(x < 1)");
+        assert_eq!(source(&[2, 0, 0, 1]), "1");
+        assert_eq!(source(&[2, 1, 2]), "y");
+        assert_eq!(spans.len(), parsed.node_count());
+
+        // The string operand of a coerced comparison comes first.
+        let input = "1 == y";
+        let variable_type = |name: &str| match name {
+            "y" => ValueType::String,
+            _ => ValueType::Real,
+        };
+        let context = ParseContext {
+            variable_type: Some(&variable_type),
+            coerce_strings: true,
+            ..ParseContext::shared(ParseOptions::default(), &binding_map)
+        };
+        let (parsed, spans) = super::parse_with_spans::<f64>(input, &context).unwrap();
+        assert!(matches!(
+            parsed.node_at_path(&[0]),
+            Some(NodeRef::String(StringExpression::Binding(1)))
+        ));
+        assert_eq!(spans[&vec![0]], (5, 6));
+        assert_eq!(spans[&vec![1]], (0, 1));
+    }
+
+    #[test]
+    fn caret_as_xor() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "a" => 0,
+                "b" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let xor = ParseOptions {
+            caret_is_xor: true,
+            ..ParseOptions::default()
+        };
+
+        let power = Expression::<f64>::parse("a ^ b", binding_map).unwrap();
+        assert!(
+            matches!(power, Expression::Real(RealExpression::Pow(_, _))),
+            "{power:?}"
+        );
+        let error = Expression::<f64>::parse_with_options("a ^ b", binding_map, xor).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("cannot apply `^` to a real and a real"),
+            "{error}"
+        );
+
+        let input = "(a < 1) ^ (b < 1) && a > 0";
+        let parsed = Expression::<f64>::parse_with_options(input, binding_map, xor).unwrap();
+        let Expression::Boolean(BoolExpression::And(lhs, _)) = &parsed else {
+            panic!("{parsed:?}")
+        };
+        assert!(matches!(**lhs, BoolExpression::Xor(_, _)), "{lhs:?}");
+        let error = Expression::<f64>::parse(input, binding_map).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("cannot apply `^` to a boolean and a boolean"),
+            "{error}"
+        );
+
+        // Options apply to every method of a parser.
+        let parser = Parser::with_options(ParseOptions {
+            max_nodes: Some(11),
+            ..xor
+        });
+        let variable_type = |_: &str| ValueType::Real;
+        let typed = parser.parse_typed::<f64>(input, binding_map, variable_type);
+        assert_eq!(typed.unwrap(), parsed);
+        let (spanned, spans) = parser.parse_with_spans::<f64>(input, binding_map).unwrap();
+        assert_eq!((spanned, spans[&vec![]]), (parsed, (0, input.len())));
+        let mut functions = FunctionTable::new();
+        functions.register("hypot2", |x: f64, y: f64| x * x + y * y);
+        let called =
+            parser.parse_with_functions("(hypot2(a, b) < 1) ^ (b > 2)", binding_map, &functions);
+        assert!(
+            matches!(called, Ok(Expression::Boolean(BoolExpression::Xor(_, _)))),
+            "{called:?}"
+        );
+        let error = parser
+            .parse::<f64>("(a < 1) ^ (b < 1) && a + b > 0", binding_map)
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("exceeds the limit of 11 nodes"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        fn binding_map(_: &str) -> BindingId {
+            0
+        }
+        let corpus = [
+            "",
+            " ",
+            "(",
+            ")",
+            "()",
+            "(,)",
+            "(x,",
+            "x +",
+            "* x",
+            "x ^",
+            "--",
+            "!",
+            "||",
+            "|x",
+            "x|",
+            "\"",
+            "\"a",
+            "r\"a",
+            "r#\"a\"",
+            "when x > 1",
+            "when x > 1 then",
+            "when x > 1 then 2 else",
+            "else 1",
+            "powi(x)",
+            "powi(x, y)",
+            "powi(x, 1e10)",
+            "between(x, 1)",
+            "sqrt()",
+            "sqrt(x, y)",
+            "x.sqrt(1)",
+            "x.",
+            "x.foo()",
+            "foo(x)",
+            "(x > 1, 2)",
+            "(\"a\", 2)",
+            "\"a\".sqrt()",
+            "-\"a\"",
+            "!x",
+            "x && 1",
+            "1e+",
+            "1.2.3",
+            "2x(",
+            "x == y == z",
+            "\u{0}",
+            "é",
+            "\\",
+            "x ?? y",
+        ];
+        for input in corpus {
+            assert!(
+                Expression::<f64>::parse(input, binding_map).is_err(),
+                "{input:?}"
+            );
+        }
+
+        // Short random sequences of tokens, which are mostly malformed.
+        let tokens = [
+            "x", "1", "2.5", "-", "+", "*", "/", "^", "(", ")", ",", "==", "!=", "<", ">=", "&&",
+            "||", "!", "\"a\"", "r\"b\"", "sqrt", "powi", "between", "when", "then", "else", "|",
+            ".", "2x", " ",
+        ];
+        let mut state = 1u64;
+        let options = ParseOptions {
+            caret_is_xor: true,
+            ..ParseOptions::default()
+        };
+        for _ in 0..5_000 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let len = (state >> 60) as usize;
+            let input: String = (0..len)
+                .map(|i| tokens[(state >> (4 * i)) as usize % tokens.len()])
+                .collect();
+            let _ = Expression::<f64>::parse(&input, binding_map);
+            let _ = Expression::<f64>::parse_with_spans(&input, binding_map);
+            let _ = Expression::<f64>::parse_with_options(&input, binding_map, options);
+        }
+    }
 }
@@ -0,0 +1,183 @@
+use crate::{ExprScalar, RealExpression, Registers};
+use std::collections::BTreeMap;
+
+/// Evaluation statistics collected while [`Registers`] profiling is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Statistics per node type, keyed by variant name (e.g. `"Mul"`).
+    ///
+    /// Operands that are read directly from bindings or broadcast as scalars
+    /// are not evaluated as nodes of their own, so they are not counted.
+    pub nodes: BTreeMap<&'static str, NodeProfile>,
+    /// Number of registers allocated (not recycled) while profiling.
+    pub allocations: usize,
+    /// Number of nodes whose operands were evaluated concurrently, which only
+    /// happens with the `rayon` feature.
+    pub concurrent_subtrees: usize,
+}
+
+/// Statistics for a single node type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodeProfile {
+    /// Number of times a node of this type was evaluated.
+    pub evaluations: usize,
+    /// Total number of elements processed by nodes of this type.
+    pub elements: usize,
+}
+
+impl Profile {
+    /// The statistics for nodes of type `node_name`, if any were evaluated.
+    pub fn node(&self, node_name: &str) -> Option<&NodeProfile> {
+        self.nodes.get(node_name)
+    }
+
+    pub(crate) fn record_node(&mut self, node_name: &'static str, elements: usize) {
+        let node = self.nodes.entry(node_name).or_default();
+        node.evaluations += 1;
+        node.elements += elements;
+    }
+
+    pub(crate) fn merge(&mut self, other: Self) {
+        for (node_name, other) in other.nodes {
+            let node = self.nodes.entry(node_name).or_default();
+            node.evaluations += other.evaluations;
+            node.elements += other.elements;
+        }
+        self.allocations += other.allocations;
+        self.concurrent_subtrees += other.concurrent_subtrees;
+    }
+}
+
+/// Number of leading values of each node kept in a [`Trace`].
+pub const TRACE_VALUES: usize = 8;
+
+/// The values of each node computed while [`Registers`] tracing is enabled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace<Real> {
+    /// One step per evaluated node, in the order their evaluation finished,
+    /// so operands come before the nodes that read them.
+    ///
+    /// Like [`Profile::nodes`], operands that are read directly from bindings
+    /// are not recorded.
+    pub steps: Vec<TraceStep<Real>>,
+}
+
+/// The output of a single node in a [`Trace`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceStep<Real> {
+    /// The variant name of the node (e.g. `"Mul"`).
+    pub label: &'static str,
+    /// The first [`TRACE_VALUES`] elements of the node's output.
+    pub values: Vec<Real>,
+}
+
+impl<Real: ExprScalar> RealExpression<Real> {
+    /// Like [`Self::evaluate`], but also returns a [`Profile`] of the
+    /// evaluation.
+    pub fn evaluate_profiled<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> (Vec<Real>, Profile) {
+        registers.enable_profiling();
+        let output = self.evaluate(bindings, registers);
+        let profile = registers.take_profile().unwrap_or_default();
+        (output, profile)
+    }
+
+    /// Like [`Self::evaluate`], but also returns a [`Trace`] of the value of
+    /// each subexpression.
+    pub fn evaluate_traced<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> (Vec<Real>, Trace<Real>) {
+        registers.enable_tracing();
+        let output = self.evaluate(bindings, registers);
+        let trace = registers
+            .take_trace()
+            .unwrap_or_else(|| Trace { steps: Vec::new() });
+        (output, trace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn profile_real_bench_expression() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "x" => 0,
+                "y" => 1,
+                "z" => 2,
+                var => panic!("Unexpected variable: {var}"),
+            }
+        }
+        let parsed = Expression::parse("(z + (z^2 - 4*x*y)^0.5) / (2*x)", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let x = [1.0, 2.0, 3.0, 4.0];
+        let y = [0.25, 0.5, 0.1, 0.0];
+        let z = [2.0, 3.0, 4.0, 5.0];
+        let bindings = &[x, y, z];
+        let mut registers = Registers::new(4);
+        let expected = real.evaluate(bindings, &mut registers);
+
+        let (output, profile) = real.evaluate_profiled(bindings, &mut registers);
+        assert_eq!(output, expected);
+        assert_eq!(
+            profile.node("Mul"),
+            Some(&NodeProfile {
+                evaluations: 3,
+                elements: 12
+            })
+        );
+        assert_eq!(profile.node("Pow").unwrap().evaluations, 2);
+        assert_eq!(profile.node("Div").unwrap().evaluations, 1);
+        assert_eq!(profile.node("Binding"), None);
+        // Only the output of the first evaluation left the register pool.
+        assert_eq!(profile.allocations, 1);
+        assert!(registers.take_profile().is_none());
+    }
+
+    #[test]
+    fn trace_intermediate_values() {
+        fn binding_map(var_name: &str) -> BindingId {
+            match var_name {
+                "foo" => 0,
+                "bar" => 1,
+                _ => unreachable!(),
+            }
+        }
+        let parsed = Expression::parse("2 * (foo + bar)", binding_map).unwrap();
+        let real = parsed.unwrap_real();
+
+        let foo = [1.0, 2.0, 3.0];
+        let bar = [10.0, 20.0, 30.0];
+        let mut registers = Registers::new(3);
+        let (output, trace) = real.evaluate_traced(&[foo, bar], &mut registers);
+        assert_eq!(output, [22.0, 44.0, 66.0]);
+        assert_eq!(
+            trace.steps[trace.steps.len() - 2..],
+            [
+                TraceStep {
+                    label: "Add",
+                    values: vec![11.0, 22.0, 33.0]
+                },
+                TraceStep {
+                    label: "Mul",
+                    values: vec![22.0, 44.0, 66.0]
+                },
+            ]
+        );
+        assert!(registers.take_trace().is_none());
+
+        // Only the leading values are kept.
+        let foo = vec![1.0; TRACE_VALUES + 1];
+        let mut registers = Registers::new(foo.len());
+        let (_, trace) = real.evaluate_traced(&[&foo, &foo], &mut registers);
+        assert!(trace.steps.iter().all(|s| s.values.len() == TRACE_VALUES));
+    }
+}
@@ -0,0 +1,437 @@
+use crate::{evaluate::Fold, FloatExt, RealExpression, Registers};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator, ParallelSlice,
+};
+
+/// Combines all elements of an expression's output into a single value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reduction {
+    Sum,
+    /// The sum divided by the number of elements, which is NaN if there are
+    /// none.
+    Mean,
+    /// The largest element. NaN elements are skipped unless `propagate`, in
+    /// which case any NaN makes the result NaN. NaN if there are no elements
+    /// that are not skipped.
+    NanMax {
+        propagate: bool,
+    },
+    /// Like [`Self::NanMax`], but the smallest element.
+    NanMin {
+        propagate: bool,
+    },
+}
+
+/// Summary statistics of an expression's output (see
+/// [`RealExpression::evaluate_with_stats`]).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats<Real> {
+    /// The smallest element that is not NaN, or NaN if there is none.
+    pub min: Real,
+    /// The largest element that is not NaN, or NaN if there is none.
+    pub max: Real,
+    /// The sum of the elements that are not NaN.
+    pub sum: Real,
+    /// The number of elements that are not NaN.
+    pub count: usize,
+    /// The number of NaN elements.
+    pub nan_count: usize,
+}
+
+impl<Real: FloatExt> Stats<Real> {
+    fn empty() -> Self {
+        Self {
+            min: Real::nan(),
+            max: Real::nan(),
+            sum: Real::zero(),
+            count: 0,
+            nan_count: 0,
+        }
+    }
+
+    fn push(mut self, value: Real) -> Self {
+        if value.is_nan() {
+            self.nan_count += 1;
+        } else {
+            // `Float::min` and `Float::max` ignore the initial NaN.
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.sum = self.sum + value;
+            self.count += 1;
+        }
+        self
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            sum: self.sum + other.sum,
+            count: self.count + other.count,
+            nan_count: self.nan_count + other.nan_count,
+        }
+    }
+}
+
+/// Number of elements summed serially per block by deterministic reductions.
+///
+/// This must not depend on the number of threads.
+#[cfg(feature = "rayon")]
+const DETERMINISTIC_BLOCK_LEN: usize = 4096;
+
+impl<Real: FloatExt> RealExpression<Real> {
+    /// Like [`Self::evaluate`], but combines the output with `reduction`.
+    ///
+    /// With the `rayon` feature, the output is summed in whatever order rayon
+    /// splits the work, so the rounding error can differ between runs and
+    /// thread counts. If `deterministic`, fixed-size blocks are summed in
+    /// parallel and then combined from left to right, which makes the result
+    /// bit-reproducible. Without `rayon`, the sum is always taken from left to
+    /// right.
+    pub fn evaluate_reduce<R: AsRef<[Real]>>(
+        &self,
+        reduction: Reduction,
+        deterministic: bool,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Real {
+        self.evaluate_reduce_with_nan(reduction, deterministic, bindings, registers)
+            .0
+    }
+
+    /// Like [`Self::evaluate_reduce`], but also returns whether any element of
+    /// the output was NaN.
+    ///
+    /// For [`Reduction::NanMax`] and [`Reduction::NanMin`], this is tracked in
+    /// the same pass as the reduction.
+    pub fn evaluate_reduce_with_nan<R: AsRef<[Real]>>(
+        &self,
+        reduction: Reduction,
+        deterministic: bool,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> (Real, bool) {
+        let output = self.evaluate(bindings, registers);
+        let reduced = match reduction {
+            Reduction::Sum => (sum(&output, deterministic), any_nan(&output)),
+            Reduction::Mean => {
+                let mean = match Real::from(output.len()) {
+                    Some(len) => sum(&output, deterministic) / len,
+                    None => Real::nan(),
+                };
+                (mean, any_nan(&output))
+            }
+            Reduction::NanMax { propagate } => nan_extreme(&output, Real::max, propagate),
+            Reduction::NanMin { propagate } => nan_extreme(&output, Real::min, propagate),
+        };
+        registers.recycle_real(output);
+        reduced
+    }
+
+    /// Like [`Self::evaluate`], but also returns [`Stats`] of the output,
+    /// accumulated while the root node writes the output rather than in a
+    /// second pass over it.
+    ///
+    /// A [`Case`](Self::Case) root, a power root while
+    /// [`Registers::set_check_power_overflow`] is enabled and
+    /// [`NonFinitePolicy::Saturate`](crate::NonFinitePolicy::Saturate) still
+    /// need a second pass.
+    ///
+    /// With the `rayon` feature, the sum is accumulated in whatever order
+    /// rayon splits the work, like a non-deterministic
+    /// [`Self::evaluate_reduce`].
+    pub fn evaluate_with_stats<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> (Vec<Real>, Stats<Real>) {
+        let fold = Fold {
+            identity: Stats::empty(),
+            push: Stats::push,
+            merge: Stats::merge,
+        };
+        self.evaluate_folding(bindings, registers, &fold)
+    }
+
+    /// Like [`Self::evaluate`], but finds the index and value of the largest
+    /// element of the output.
+    ///
+    /// NaN elements are skipped unless every element is NaN. Ties go to the
+    /// lowest index, regardless of how the work is split between threads.
+    /// Returns `None` if the register length is zero.
+    pub fn evaluate_argmax<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Option<(usize, Real)> {
+        self.evaluate_arg_extreme(|candidate, best| candidate > best, bindings, registers)
+    }
+
+    /// Like [`Self::evaluate_argmax`], but finds the smallest element.
+    pub fn evaluate_argmin<R: AsRef<[Real]>>(
+        &self,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Option<(usize, Real)> {
+        self.evaluate_arg_extreme(|candidate, best| candidate < best, bindings, registers)
+    }
+
+    fn evaluate_arg_extreme<R: AsRef<[Real]>>(
+        &self,
+        is_better: impl Fn(Real, Real) -> bool + Sync,
+        bindings: &[R],
+        registers: &mut Registers<Real>,
+    ) -> Option<(usize, Real)> {
+        let output = self.evaluate(bindings, registers);
+        // `best` always has the lower index, so it wins ties.
+        let pick = |best: (usize, Real), candidate: (usize, Real)| {
+            if is_better(candidate.1, best.1) || (best.1.is_nan() && !candidate.1.is_nan()) {
+                candidate
+            } else {
+                best
+            }
+        };
+
+        #[cfg(feature = "rayon")]
+        let extreme = output.par_iter().copied().enumerate().reduce_with(pick);
+        #[cfg(not(feature = "rayon"))]
+        let extreme = output.iter().copied().enumerate().reduce(pick);
+
+        registers.recycle_real(output);
+        extreme
+    }
+}
+
+fn sum<Real: FloatExt>(values: &[Real], deterministic: bool) -> Real {
+    let serial_sum = |values: &[Real]| values.iter().fold(Real::zero(), |sum, v| sum + *v);
+
+    #[cfg(feature = "rayon")]
+    {
+        if deterministic {
+            let block_sums: Vec<Real> = values
+                .par_chunks(DETERMINISTIC_BLOCK_LEN)
+                .map(serial_sum)
+                .collect();
+            serial_sum(&block_sums)
+        } else {
+            values.par_iter().copied().reduce(Real::zero, |a, b| a + b)
+        }
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        let _ = deterministic;
+        serial_sum(values)
+    }
+}
+
+fn any_nan<Real: FloatExt>(values: &[Real]) -> bool {
+    #[cfg(feature = "rayon")]
+    {
+        values.par_iter().any(|v| v.is_nan())
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        values.iter().any(|v| v.is_nan())
+    }
+}
+
+/// Combines the elements that are not NaN with `pick`, which must ignore a NaN
+/// operand like [`Float::max`](num_traits::Float::max), and tracks whether any
+/// element was NaN.
+fn nan_extreme<Real: FloatExt>(
+    values: &[Real],
+    pick: fn(Real, Real) -> Real,
+    propagate: bool,
+) -> (Real, bool) {
+    let empty = || (Real::nan(), false);
+    let push = |(extreme, saw_nan): (Real, bool), value: &Real| {
+        (pick(extreme, *value), saw_nan || value.is_nan())
+    };
+
+    #[cfg(feature = "rayon")]
+    let (extreme, saw_nan) = values
+        .par_iter()
+        .fold(empty, push)
+        .reduce(empty, |a, b| (pick(a.0, b.0), a.1 || b.1));
+    #[cfg(not(feature = "rayon"))]
+    let (extreme, saw_nan) = values.iter().fold(empty(), push);
+
+    if propagate && saw_nan {
+        (Real::nan(), true)
+    } else {
+        (extreme, saw_nan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn binding_map(var_name: &str) -> BindingId {
+        match var_name {
+            "x" => 0,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn sum_and_mean() {
+        let real = Expression::<f64>::parse("2 * x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let x = [1.0, 2.0, 3.0, 4.0];
+        let mut registers = Registers::new(x.len());
+        let sum = real.evaluate_reduce(Reduction::Sum, false, &[x], &mut registers);
+        assert_eq!(sum, 20.0);
+        let mean = real.evaluate_reduce(Reduction::Mean, true, &[x], &mut registers);
+        assert_eq!(mean, 5.0);
+
+        let mut registers = Registers::new(0);
+        let mean = real.evaluate_reduce(Reduction::Mean, true, &[[0.0; 0]], &mut registers);
+        assert!(mean.is_nan());
+    }
+
+    #[test]
+    fn nan_max_and_min() {
+        let real = Expression::<f64>::parse("x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let x = [1.0, f64::NAN, 3.0];
+        let mut registers = Registers::new(x.len());
+        let mut reduce =
+            |reduction| real.evaluate_reduce_with_nan(reduction, false, &[x], &mut registers);
+        assert_eq!(reduce(Reduction::NanMax { propagate: false }), (3.0, true));
+        assert_eq!(reduce(Reduction::NanMin { propagate: false }), (1.0, true));
+        let (max, saw_nan) = reduce(Reduction::NanMax { propagate: true });
+        assert!(max.is_nan() && saw_nan);
+        let (sum, saw_nan) = reduce(Reduction::Sum);
+        assert!(sum.is_nan() && saw_nan);
+
+        let x = [1.0, 3.0, 2.0];
+        let mut reduce =
+            |reduction| real.evaluate_reduce_with_nan(reduction, false, &[x], &mut registers);
+        assert_eq!(reduce(Reduction::NanMax { propagate: true }), (3.0, false));
+        assert_eq!(reduce(Reduction::Mean), (2.0, false));
+    }
+
+    #[test]
+    fn stats_skip_nan() {
+        let real = Expression::<f64>::parse("x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let x = [1.0, f64::NAN, 3.0];
+        let mut registers = Registers::new(x.len());
+        let (output, stats) = real.evaluate_with_stats(&[x], &mut registers);
+        assert_eq!(output[0], 1.0);
+        assert!(output[1].is_nan());
+        assert_eq!(output[2], 3.0);
+        assert_eq!(
+            stats,
+            Stats {
+                min: 1.0,
+                max: 3.0,
+                sum: 4.0,
+                count: 2,
+                nan_count: 1
+            }
+        );
+
+        let x = [f64::NAN; 2];
+        let (_, stats) = real.evaluate_with_stats(&[x], &mut Registers::new(x.len()));
+        assert!(stats.min.is_nan() && stats.max.is_nan());
+        assert_eq!((stats.count, stats.nan_count), (0, 2));
+    }
+
+    #[test]
+    fn stats_of_every_root() {
+        let x = [4.0, -1.0, f64::NAN, 0.25, 1e300];
+        for input in [
+            "x * 2 - 1",
+            "1 - x",
+            "-x",
+            "sqrt(x)",
+            "max(x, 1)",
+            "x ^ 2",
+            "x ^ 0.5",
+            "x",
+            "3",
+            "when x < 0 then -x else x + 1",
+        ] {
+            let real = Expression::<f64>::parse(input, binding_map)
+                .unwrap()
+                .unwrap_real();
+            for policy in [NonFinitePolicy::Propagate, NonFinitePolicy::Saturate] {
+                let mut registers = Registers::new(x.len());
+                registers.set_non_finite_policy(policy);
+                let expected = real.evaluate(&[x], &mut registers);
+                let (output, stats) = real.evaluate_with_stats(&[x], &mut registers);
+                assert_eq!(
+                    output.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                    expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                    "{input}"
+                );
+                let expected = expected.iter().fold(Stats::empty(), |s, &v| s.push(v));
+                assert_eq!(stats.count, expected.count, "{input}");
+                assert_eq!(stats.nan_count, expected.nan_count, "{input}");
+                assert_eq!(stats.min, expected.min, "{input}");
+                assert_eq!(stats.max, expected.max, "{input}");
+                assert_eq!(stats.sum, expected.sum, "{input}");
+            }
+        }
+    }
+
+    #[test]
+    fn argmax_and_argmin() {
+        let real = Expression::<f64>::parse("x", binding_map)
+            .unwrap()
+            .unwrap_real();
+        let x = [3.0, 1.0, 4.0, 1.0, 5.0];
+        let mut registers = Registers::new(x.len());
+        assert_eq!(real.evaluate_argmax(&[x], &mut registers), Some((4, 5.0)));
+        assert_eq!(real.evaluate_argmin(&[x], &mut registers), Some((1, 1.0)));
+
+        let x = [f64::NAN, 2.0, f64::NAN, 2.0, 1.0];
+        assert_eq!(real.evaluate_argmax(&[x], &mut registers).unwrap().0, 1);
+
+        let empty: [f64; 0] = [];
+        let mut registers = Registers::new(0);
+        assert_eq!(real.evaluate_argmax(&[empty], &mut registers), None);
+        assert_eq!(real.evaluate_argmin(&[empty], &mut registers), None);
+
+        // Ties must resolve to the lowest index however rayon splits the work.
+        const LEN: usize = 100_000;
+        let x: Vec<f64> = (0..LEN).map(|i| (i % 1000) as f64).collect();
+        let mut registers = Registers::new(LEN);
+        assert_eq!(
+            real.evaluate_argmax(&[&x], &mut registers),
+            Some((999, 999.0))
+        );
+        assert_eq!(real.evaluate_argmin(&[&x], &mut registers), Some((0, 0.0)));
+    }
+
+    #[test]
+    fn deterministic_mean_is_bit_reproducible() {
+        let real = Expression::parse("x / 3 + 0.1", binding_map)
+            .unwrap()
+            .unwrap_real();
+        const LEN: usize = 100_000;
+        let x: Vec<f32> = (0..LEN).map(|i| (i as f32).sqrt() * 1e3).collect();
+        let mut registers = Registers::new(LEN);
+        let first = real.evaluate_reduce(Reduction::Mean, true, &[&x], &mut registers);
+        let second = real.evaluate_reduce(Reduction::Mean, true, &[&x], &mut registers);
+        assert_eq!(first.to_bits(), second.to_bits());
+
+        #[cfg(feature = "rayon")]
+        for num_threads in [1, 3] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            let mean =
+                pool.install(|| real.evaluate_reduce(Reduction::Mean, true, &[&x], &mut registers));
+            assert_eq!(mean.to_bits(), first.to_bits());
+        }
+    }
+}
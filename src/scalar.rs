@@ -0,0 +1,276 @@
+use crate::{BoolExpression, Func1, Func2, NodeRef, RealExpression};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// The arithmetic that evaluation needs of a real type.
+///
+/// Unlike [`FloatExt`](crate::FloatExt), this does not require
+/// [`num_traits::Float`], so it can be implemented for e.g. interval or
+/// fixed-point types. Such types only support the [`Func1`]s they provide,
+/// and the checks of [`NonFinitePolicy`](crate::NonFinitePolicy) and
+/// [`Registers::set_check_power_overflow`](crate::Registers::set_check_power_overflow)
+/// require a `FloatExt` type.
+///
+/// The methods are named differently from those of `Float`, so calls are not
+/// ambiguous where both traits are bounds.
+pub trait ExprScalar:
+    Copy
+    + PartialOrd
+    + FromStr
+    + Send
+    + Sync
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// The value of an [`IntLiteral`](RealExpression::IntLiteral).
+    fn from_i32(n: i32) -> Self;
+
+    /// Raises `self` to the power `exponent`.
+    fn pow(self, exponent: Self) -> Self;
+
+    /// Raises `self` to the integer power `n`.
+    fn pow_int(self, n: i32) -> Self;
+
+    /// The implementation of `func`, or `None` if it is not supported.
+    ///
+    /// Evaluating an unsupported function panics, or fails with
+    /// [`EvalError::Unsupported`](crate::EvalError::Unsupported) from e.g.
+    /// [`RealExpression::try_evaluate`].
+    fn func1(func: Func1) -> Option<fn(Self) -> Self> {
+        let _ = func;
+        None
+    }
+
+    /// The implementation of the built-in `func`, or `None` if it is not
+    /// supported like for [`Self::func1`].
+    ///
+    /// By default, `max` and `min` return their first operand unless the
+    /// second is greater or less, respectively. Like `coalesce`, both treat an
+    /// operand that is unordered with itself, like NaN, as missing.
+    fn func2(func: Func2) -> Option<fn(Self, Self) -> Self> {
+        Some(match func {
+            Func2::Coalesce => |x, default| if is_missing(x) { default } else { x },
+            Func2::Max => |x, y| if x < y || is_missing(x) { y } else { x },
+            Func2::Min => |x, y| if y < x || is_missing(x) { y } else { x },
+            Func2::Custom(_) => return None,
+        })
+    }
+}
+
+fn is_missing<Real: PartialOrd>(x: Real) -> bool {
+    x.partial_cmp(&x).is_none()
+}
+
+macro_rules! impl_expr_scalar {
+    ($($real:ty),*) => {$(
+        impl ExprScalar for $real {
+            fn from_i32(n: i32) -> Self {
+                n as $real
+            }
+
+            fn pow(self, exponent: Self) -> Self {
+                <$real>::powf(self, exponent)
+            }
+
+            fn pow_int(self, n: i32) -> Self {
+                <$real>::powi(self, n)
+            }
+
+            fn func1(func: Func1) -> Option<fn(Self) -> Self> {
+                Some(func.function())
+            }
+
+            fn func2(func: Func2) -> Option<fn(Self, Self) -> Self> {
+                func.function()
+            }
+        }
+    )*};
+}
+
+impl_expr_scalar!(f32, f64);
+
+impl<Real: ExprScalar> RealExpression<Real> {
+    /// The name of the first function that `Real` does not support, if any.
+    pub(crate) fn unsupported_function(&self) -> Option<&'static str> {
+        first_unsupported(NodeRef::Real(self))
+    }
+}
+
+impl<Real: ExprScalar> BoolExpression<Real> {
+    /// The name of the first function that `Real` does not support, if any.
+    pub(crate) fn unsupported_function(&self) -> Option<&'static str> {
+        first_unsupported(NodeRef::Boolean(self))
+    }
+}
+
+fn first_unsupported<Real: ExprScalar>(node: NodeRef<'_, Real>) -> Option<&'static str> {
+    match node {
+        NodeRef::Real(RealExpression::Func1(func, _)) if Real::func1(*func).is_none() => {
+            return Some(func.name())
+        }
+        NodeRef::Real(RealExpression::Func2(func, _, _))
+            if !matches!(func, Func2::Custom(_)) && Real::func2(*func).is_none() =>
+        {
+            return Some(func.name())
+        }
+        _ => {}
+    }
+    (0..node.num_operands()).find_map(|index| first_unsupported(node.operand(index)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::cmp::Ordering;
+    use std::ops::{Add, Div, Mul, Neg, Sub};
+    use std::str::FromStr;
+
+    /// The reals from `lo` to `hi`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Interval {
+        lo: f64,
+        hi: f64,
+    }
+
+    impl Interval {
+        fn new(a: f64, b: f64) -> Self {
+            Self {
+                lo: a.min(b),
+                hi: a.max(b),
+            }
+        }
+
+        /// The hull of `f` applied to each pair of endpoints.
+        fn hull(self, rhs: Self, f: fn(f64, f64) -> f64) -> Self {
+            let corners = [
+                f(self.lo, rhs.lo),
+                f(self.lo, rhs.hi),
+                f(self.hi, rhs.lo),
+                f(self.hi, rhs.hi),
+            ];
+            Self {
+                lo: corners.into_iter().fold(f64::INFINITY, f64::min),
+                hi: corners.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            }
+        }
+    }
+
+    impl PartialOrd for Interval {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            if self == other {
+                Some(Ordering::Equal)
+            } else if self.hi < other.lo {
+                Some(Ordering::Less)
+            } else if self.lo > other.hi {
+                Some(Ordering::Greater)
+            } else {
+                None
+            }
+        }
+    }
+
+    impl FromStr for Interval {
+        type Err = std::num::ParseFloatError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let value = s.parse()?;
+            Ok(Self::new(value, value))
+        }
+    }
+
+    impl Add for Interval {
+        type Output = Self;
+
+        fn add(self, rhs: Self) -> Self {
+            Self::new(self.lo + rhs.lo, self.hi + rhs.hi)
+        }
+    }
+
+    impl Sub for Interval {
+        type Output = Self;
+
+        fn sub(self, rhs: Self) -> Self {
+            Self::new(self.lo - rhs.hi, self.hi - rhs.lo)
+        }
+    }
+
+    impl Mul for Interval {
+        type Output = Self;
+
+        fn mul(self, rhs: Self) -> Self {
+            self.hull(rhs, |a, b| a * b)
+        }
+    }
+
+    impl Div for Interval {
+        type Output = Self;
+
+        fn div(self, rhs: Self) -> Self {
+            self.hull(rhs, |a, b| a / b)
+        }
+    }
+
+    impl Neg for Interval {
+        type Output = Self;
+
+        fn neg(self) -> Self {
+            Self::new(-self.hi, -self.lo)
+        }
+    }
+
+    impl ExprScalar for Interval {
+        fn from_i32(n: i32) -> Self {
+            Self::new(n.into(), n.into())
+        }
+
+        fn pow(self, exponent: Self) -> Self {
+            self.hull(exponent, f64::powf)
+        }
+
+        fn pow_int(self, n: i32) -> Self {
+            (0..n).fold(Self::from_i32(1), |product, _| product * self)
+        }
+    }
+
+    fn binding_map(var_name: &str) -> BindingId {
+        match var_name {
+            "x" => 0,
+            "y" => 1,
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_intervals(input: &str) -> RealExpression<Interval> {
+        Expression::<f64>::parse(input, binding_map)
+            .unwrap()
+            .unwrap_real()
+            .map_literals(&|&value| Interval::new(value, value))
+    }
+
+    #[test]
+    fn evaluate_intervals() {
+        let x = [Interval::new(1.0, 2.0), Interval::new(-1.0, 1.0)];
+        let y = [Interval::new(0.0, 0.5), Interval::new(3.0, 3.0)];
+        let mut registers = Registers::new(x.len());
+        let real = parse_intervals("x + y * 2");
+        assert_eq!(
+            real.evaluate(&[x, y], &mut registers),
+            [Interval::new(1.0, 3.0), Interval::new(5.0, 7.0)]
+        );
+
+        let real = parse_intervals("when x > y then max(x, y) else x - 1");
+        assert_eq!(
+            real.evaluate(&[x, y], &mut registers),
+            [Interval::new(1.0, 2.0), Interval::new(-2.0, 0.0)]
+        );
+
+        let real = parse_intervals("sqrt(x)");
+        assert_eq!(
+            real.try_evaluate(&[x], &mut registers),
+            Err(EvalError::Unsupported("sqrt"))
+        );
+    }
+}